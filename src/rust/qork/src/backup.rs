@@ -0,0 +1,221 @@
+use std::fs::{self as stdfs, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fs;
+
+/// The sub-directory of the data directory in which numbered backups are stored.
+pub const BACKUP_DIR: &'static str = "backups";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How a file is backed up before it is first overwritten.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BackupStyle {
+    /// No backups are made.
+    None,
+    /// A single backup is kept next to the file, with "~" appended to its name.
+    Tilde,
+    /// Numbered backups are kept in the data directory, subject to a retention policy.
+    Numbered
+}
+
+/// A backup of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// The version number of a numbered backup.
+    pub number: Option<u32>,
+    pub modified: SystemTime
+}
+
+/// Makes, finds and prunes backups according to the configured style. Numbered backups of
+/// "/home/phil/foo.txt" are called "!home!phil!foo.txt.~N~" and live in a single directory,
+/// so that backups of files in different directories do not clash.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    style: BackupStyle,
+    dir: Option<PathBuf>,
+    max_backups: usize,
+    max_age: Duration
+}
+
+impl Default for Backups {
+    fn default() -> Backups {
+        Backups::new(BackupStyle::None, None, 0, 0)
+    }
+}
+
+impl Backups {
+    /// Creates a backup policy. `dir` is where numbered backups are kept; if it is None then
+    /// numbered backups are not made. A `max_backups` or `max_age_days` of 0 means unlimited.
+    pub fn new(style: BackupStyle, dir: Option<PathBuf>, max_backups: usize, max_age_days: u64) -> Backups {
+        Backups {
+            style,
+            dir,
+            max_backups,
+            max_age: Duration::from_secs(max_age_days * SECONDS_PER_DAY)
+        }
+    }
+
+    pub fn style(&self) -> BackupStyle {
+        self.style
+    }
+
+    /// Copies `filename` to a new backup, returning the path of the backup. Nothing is done,
+    /// and None is returned, if backups are disabled or the file does not exist yet.
+    pub fn backup<P: AsRef<Path>>(&self, filename: P) -> io::Result<Option<PathBuf>> {
+        let filename = filename.as_ref();
+        if !filename.is_file() {
+            return Ok(None);
+        }
+
+        let backup_path = match self.style {
+            BackupStyle::None => return Ok(None),
+            BackupStyle::Tilde => tilde_backup_path(filename),
+            BackupStyle::Numbered => {
+                let dir = match self.dir {
+                    Some(ref dir) => dir,
+                    None => return Ok(None)
+                };
+                stdfs::create_dir_all(dir)?;
+                let next = self.numbered_backups(filename).iter().filter_map(|b| b.number).max().unwrap_or(0) + 1;
+                dir.join(format!("{}.~{}~", fs::path_to_filename(filename), next))
+            }
+        };
+
+        let mut contents = Vec::new();
+        io::Read::read_to_end(&mut File::open(filename)?, &mut contents)?;
+        fs::write_atomically(&backup_path, &contents)?;
+        info!("Backed up {:?} to {:?}", filename, backup_path);
+
+        if self.style == BackupStyle::Numbered {
+            self.prune(filename);
+        }
+
+        Ok(Some(backup_path))
+    }
+
+    /// Lists the backups of `filename`, newest first. Backups of both styles are found,
+    /// whichever style is currently configured.
+    pub fn list<P: AsRef<Path>>(&self, filename: P) -> Vec<Backup> {
+        let filename = filename.as_ref();
+        let mut backups = self.numbered_backups(filename);
+
+        let tilde = tilde_backup_path(filename);
+        if let Ok(m) = stdfs::metadata(&tilde) {
+            backups.push(Backup { path: tilde, number: None, modified: m.modified().unwrap_or(SystemTime::now()) });
+        }
+
+        backups.sort_by(|a, b| b.modified.cmp(&a.modified).then(b.number.cmp(&a.number)));
+        backups
+    }
+
+    fn numbered_backups(&self, filename: &Path) -> Vec<Backup> {
+        let dir = match self.dir {
+            Some(ref dir) => dir,
+            None => return Vec::new()
+        };
+
+        let prefix = format!("{}.~", fs::path_to_filename(filename));
+        let entries = match stdfs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new()
+        };
+
+        entries.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                let number = parse_backup_number(&name, &prefix)?;
+                let modified = e.metadata().and_then(|m| m.modified()).ok()?;
+                Some(Backup { path: e.path(), number: Some(number), modified })
+            })
+            .collect()
+    }
+
+    /// Deletes numbered backups of `filename` beyond the newest `max_backups`, and any that
+    /// are older than `max_age`. The newest backup is always kept.
+    fn prune(&self, filename: &Path) {
+        let mut backups = self.numbered_backups(filename);
+        backups.sort_by(|a, b| b.number.cmp(&a.number));
+        let now = SystemTime::now();
+
+        for (idx, backup) in backups.iter().enumerate().skip(1) {
+            let too_many = self.max_backups > 0 && idx >= self.max_backups;
+            let too_old = self.max_age.as_secs() > 0 &&
+                now.duration_since(backup.modified).map(|age| age > self.max_age).unwrap_or(false);
+
+            if too_many || too_old {
+                match stdfs::remove_file(&backup.path) {
+                    Ok(_) => info!("Deleted old backup {:?}", backup.path),
+                    Err(e) => warn!("Could not delete old backup {:?}: {}", backup.path, e)
+                }
+            }
+        }
+    }
+}
+
+fn tilde_backup_path(filename: &Path) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+fn parse_backup_number(name: &str, prefix: &str) -> Option<u32> {
+    if name.starts_with(prefix) && name.ends_with('~') && name.len() > prefix.len() + 1 {
+        name[prefix.len()..name.len() - 1].parse().ok()
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::TestDir;
+
+    #[test]
+    fn parse_backup_number_for_matching_name_returns_number() {
+        assert_eq!(parse_backup_number("!a.txt.~12~", "!a.txt.~"), Some(12));
+        assert_eq!(parse_backup_number("!a.txt.~x~", "!a.txt.~"), None);
+        assert_eq!(parse_backup_number("!b.txt.~1~", "!a.txt.~"), None);
+    }
+
+    #[test]
+    fn backup_for_missing_file_does_nothing() {
+        let dir = TestDir::new();
+        let backups = Backups::new(BackupStyle::Tilde, None, 0, 0);
+        assert_eq!(backups.backup(dir.join("a.txt")).unwrap(), None);
+    }
+
+    #[test]
+    fn backup_for_tilde_style_copies_file_alongside() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        fs::save_from_string(&filename, "hello").unwrap();
+
+        let backups = Backups::new(BackupStyle::Tilde, None, 0, 0);
+        let path = backups.backup(&filename).unwrap().unwrap();
+        assert_eq!(path, dir.join("a.txt~"));
+        assert_eq!(fs::load_to_string(&path).unwrap(), "hello");
+        assert_eq!(backups.list(&filename).len(), 1);
+    }
+
+    #[test]
+    fn backup_for_numbered_style_increments_numbers_and_prunes_to_max_backups() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        fs::save_from_string(&filename, "hello").unwrap();
+
+        let backups = Backups::new(BackupStyle::Numbered, Some(dir.join("backups")), 2, 0);
+        for _ in 0..3 {
+            backups.backup(&filename).unwrap();
+        }
+
+        let mut numbers: Vec<_> = backups.list(&filename).iter().filter_map(|b| b.number).collect();
+        numbers.sort();
+        assert_eq!(numbers, vec![2, 3]);
+    }
+}
@@ -1,27 +1,139 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Keys;
 use std::cell::{Ref, RefCell};
+use std::mem;
 use std::ops::Index;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use super::{Buffer, BufferId};
+use backup::Backups;
+use fs::{self, Codecs, FileStamp};
+use super::{Buffer, BufferError, BufferFactory, BufferId, MemoryUsage};
+use super::file_index::{FileIdentity, FileIndex};
+use super::title::{path_titles, TitleStyle};
+
+/// A change made to the file of a buffer by another program, as found by `check_disk_changes`.
+#[derive(Debug, PartialEq)]
+pub enum DiskChange {
+    /// The buffer had no unsaved changes, so it was reloaded.
+    Reloaded(BufferId),
+    /// The buffer had no unsaved changes, but reloading it failed.
+    ReloadFailed(BufferId, BufferError),
+    /// The buffer has unsaved changes, so the user must decide what to do.
+    Conflict(BufferId),
+    /// The file was deleted or renamed.
+    Deleted(BufferId)
+}
 
 /// Creates, manages and deletes all the buffers in Qork, maintaining the various invariants that
 /// we expect from the buffers. Firstly, if a buffer is backed by a file, a second buffer on that
-/// file cannot be created. Secondly, all buffers have unique identity.
+/// file cannot be created; a file is identified by its canonical path and its inode, so this
+/// holds however the filename is written and through symlinks and hard links. Secondly, all
+/// buffers have unique identity.
 ///
 /// Note that a Buffer is very different from a BufferView.
 pub struct BufferCollection {
     current_buffer: BufferId,
-    buffers: HashMap<BufferId, RefCell<Buffer>>
+    buffers: HashMap<BufferId, RefCell<Buffer>>,
+    /// Every buffer, most recently accessed first.
+    history: Vec<BufferId>,
+    /// When a workspace is active, the buffers in it. Other buffers are hidden.
+    visible: Option<HashSet<BufferId>>,
+    files: FileIndex,
+    backups: Backups,
+    codecs: Codecs,
+    title_style: TitleStyle,
+    /// The number of bytes the buffers may use before unmodified ones are evicted, or 0 for
+    /// no limit.
+    memory_limit: usize
 }
 
 impl BufferCollection {
     pub fn new() -> BufferCollection {
         BufferCollection {
             current_buffer: -1,
-            buffers: HashMap::with_capacity(20)
+            history: Vec::new(),
+            visible: None,
+            buffers: HashMap::with_capacity(20),
+            files: FileIndex::new(),
+            backups: Backups::default(),
+            codecs: Codecs::standard(),
+            title_style: TitleStyle::default(),
+            memory_limit: 0
+        }
+    }
+
+    /// Sets the policy used to back up files before they are first overwritten.
+    pub fn set_backups(&mut self, backups: Backups) {
+        self.backups = backups;
+    }
+
+    pub fn backups(&self) -> &Backups {
+        &self.backups
+    }
+
+    /// Sets the codecs used to choose the format of a file when a buffer is saved under a
+    /// new name, so that saving as "notes.txt.gz" compresses it.
+    pub fn set_codecs(&mut self, codecs: Codecs) {
+        self.codecs = codecs;
+    }
+
+    /// Sets how the titles of buffers on files with the same name are made unique, and
+    /// re-titles the existing buffers to match.
+    pub fn set_title_style(&mut self, title_style: TitleStyle) {
+        self.title_style = title_style;
+        self.retitle_file_buffers();
+    }
+
+    /// Sets the number of bytes the buffers may use before `enforce_memory_limit` evicts
+    /// some of them, or 0 for no limit.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    pub fn memory_usage(&self, buffer_id: BufferId) -> Option<MemoryUsage> {
+        self.buffers.get(&buffer_id).map(|rc| rc.borrow().memory_usage())
+    }
+
+    pub fn total_memory_usage(&self) -> MemoryUsage {
+        self.buffers.values().fold(MemoryUsage::default(), |total, rc| total + rc.borrow().memory_usage())
+    }
+
+    /// Drops the text of unmodified buffers on files, least recently used first, until the
+    /// buffers use no more than the memory limit. The current buffer is never evicted. An
+    /// evicted buffer is reloaded when it is next made current or saved. Returns the ids of
+    /// the buffers evicted.
+    pub fn enforce_memory_limit(&mut self) -> Vec<BufferId> {
+        let mut evicted = Vec::new();
+        if self.memory_limit == 0 {
+            return evicted;
         }
+
+        let mut total = self.total_memory_usage().total();
+        for &id in self.history.iter().rev() {
+            if total <= self.memory_limit {
+                break;
+            }
+            let mut b = self.buffers[&id].borrow_mut();
+            if id == self.current_buffer || !is_evictable(&b) {
+                continue;
+            }
+
+            let before = b.memory_usage().total();
+            b.evict();
+            total -= before - b.memory_usage().total();
+            evicted.push(id);
+        }
+        evicted
+    }
+
+    /// Reloads the text of a buffer which was evicted to save memory. Does nothing for
+    /// buffers which were not evicted.
+    pub fn ensure_loaded(&mut self, buffer_id: BufferId) -> Result<(), BufferError> {
+        self.buffers.get(&buffer_id).ok_or(BufferError::NoSuchBuffer(buffer_id))?.borrow_mut().ensure_loaded()
     }
 
     pub fn len(&self) -> usize {
@@ -32,13 +144,83 @@ impl BufferCollection {
         self.current_buffer
     }
 
+    /// Makes a buffer the current buffer, marking it as accessed and moving it to the front
+    /// of the history.
     pub fn set_current_buffer(&mut self, buffer_id: BufferId) -> bool {
-        if self.buffers.contains_key(&buffer_id) {
-            self.current_buffer = buffer_id;
-            return true;
-        } else {
+        self.make_current(buffer_id, true)
+    }
+
+    /// Makes a buffer current, reloading it if it was evicted. If that fails it stays empty
+    /// and refuses edits, so the error is reported but the buffer is still made current.
+    fn make_current(&mut self, buffer_id: BufferId, to_front: bool) -> bool {
+        if !self.buffers.contains_key(&buffer_id) {
             return false;
         }
+
+        if let Err(e) = self.ensure_loaded(buffer_id) {
+            warn!("Could not reload buffer {}, it will be empty until its file can be read: {}", buffer_id, e);
+        }
+        self.current_buffer = buffer_id;
+        self.buffers[&buffer_id].borrow_mut().set_accessed();
+        if to_front {
+            self.history.retain(|&id| id != buffer_id);
+            self.history.insert(0, buffer_id);
+        }
+        true
+    }
+
+    /// The ids of all buffers, most recently accessed first. Buffers which have never been
+    /// current come last, in the order they were created.
+    pub fn history(&self) -> &[BufferId] {
+        &self.history
+    }
+
+    /// Restricts the buffers which are visible to the user, for example to those in a
+    /// workspace, or makes all buffers visible if `visible` is None. Special buffers, such as
+    /// *messages*, are always visible, and new buffers are visible until this is next called.
+    /// Hidden buffers are left out of the buffer history and the buffer list.
+    pub fn set_visible_buffers(&mut self, visible: Option<HashSet<BufferId>>) {
+        self.visible = visible;
+    }
+
+    pub fn is_visible(&self, buffer_id: BufferId) -> bool {
+        match self.visible {
+            None => self.buffers.contains_key(&buffer_id),
+            Some(ref visible) => visible.contains(&buffer_id) ||
+                self.buffers.get(&buffer_id).map_or(false, |rc| is_special(&rc.borrow()))
+        }
+    }
+
+    /// The visible buffers, most recently accessed first.
+    pub fn visible_history(&self) -> Vec<BufferId> {
+        self.history.iter().cloned().filter(|&id| self.is_visible(id)).collect()
+    }
+
+    /// Switches back to the buffer that was current before this one, so that repeating it
+    /// toggles between two buffers. Returns the new current buffer, if there was another one.
+    pub fn switch_to_previous_buffer(&mut self) -> Option<BufferId> {
+        let previous = self.visible_history().into_iter().find(|&id| id != self.current_buffer)?;
+        self.set_current_buffer(previous);
+        Some(previous)
+    }
+
+    /// Makes the next (or, if `older` is false, the previous) buffer in the history current
+    /// without re-ordering the history, so that repeating it cycles through every buffer.
+    /// Returns the new current buffer, if there are any buffers.
+    pub fn cycle_buffers(&mut self, older: bool) -> Option<BufferId> {
+        let history = self.visible_history();
+        if history.is_empty() {
+            return None;
+        }
+
+        let len = history.len();
+        let target = match history.iter().position(|&id| id == self.current_buffer) {
+            Some(idx) if older => history[(idx + 1) % len],
+            Some(idx) => history[(idx + len - 1) % len],
+            None => history[0]
+        };
+        self.make_current(target, false);
+        Some(target)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -54,33 +236,224 @@ impl BufferCollection {
     }
 
     pub fn insert(&mut self, buffer: Buffer) {
+        if let Some(ref f) = buffer.filename {
+            self.files.insert(buffer.id(), f);
+        }
+        if !self.history.contains(&buffer.id()) {
+            self.history.push(buffer.id());
+        }
+        if let Some(ref mut visible) = self.visible {
+            visible.insert(buffer.id());
+        }
         self.buffers.insert(buffer.id(), RefCell::new(buffer));
     }
 
     pub fn remove(&mut self, buffer_id: BufferId) -> Option<RefCell<Buffer>> {
+        self.files.remove(buffer_id);
+        self.history.retain(|&id| id != buffer_id);
+        if let Some(ref mut visible) = self.visible {
+            visible.remove(&buffer_id);
+        }
         self.buffers.remove(&buffer_id)
     }
 
     pub fn find_by_filename<P : AsRef<Path>>(&mut self, filename: P) -> Option<&RefCell<Buffer>> {
-        self.buffers.values().find(|refcell| refcell.borrow().filename.as_ref().map_or(false, |f| f == filename.as_ref()))
+        let id = self.files.find(filename)?;
+        self.buffers.get(&id)
+    }
+
+    /// Re-records the identity of a buffer's file, which changes when the file is replaced
+    /// by a save or by another program.
+    fn reindex(&mut self, buffer_id: BufferId) {
+        let filename = self.buffers.get(&buffer_id).and_then(|rc| rc.borrow().filename.clone());
+        match filename {
+            Some(f) => self.files.insert(buffer_id, f),
+            None => self.files.remove(buffer_id)
+        }
+    }
+
+    pub fn find_by_title(&self, title: &str) -> Option<BufferId> {
+        self.buffers.values().map(|rc| rc.borrow()).find(|b| b.title == title).map(|b| b.id())
+    }
+
+    /// Creates a new empty buffer, not backed by a file, and makes it the current buffer.
+    /// The title is made unique if necessary.
+    pub fn new_buffer(&mut self, factory: &mut BufferFactory, title: &str) -> BufferId {
+        let mut b = factory.new_empty_buffer();
+        b.title = self.get_unique_title(title);
+        let id = b.id();
+        self.insert(b);
+        self.set_current_buffer(id);
+        id
+    }
+
+    /// Opens a file into a new buffer and makes it the current buffer. If the file is already
+    /// open then the existing buffer is made current instead and no new buffer is created.
+    /// Either way, the id of the buffer is returned.
+    pub fn open_file<P: AsRef<Path>>(&mut self, factory: &mut BufferFactory, filename: P) -> Result<BufferId, BufferError> {
+        let filename = filename.as_ref();
+
+        let existing_id = self.find_by_filename(filename).map(|rc| rc.borrow().id());
+        let id = match existing_id {
+            Some(id) => id,
+            None => {
+                let mut b = factory.open_file(filename)?;
+                b.title = self.get_unique_title(&leaf_title(filename));
+                let id = b.id();
+                self.insert(b);
+                self.retitle_file_buffers();
+                id
+            }
+        };
+
+        self.set_current_buffer(id);
+        Ok(id)
+    }
+
+    /// Saves a buffer to its file, returning the number of bytes written. The buffer must
+    /// already have a filename; use `save_buffer_as` for buffers that do not. If the file has
+    /// been changed by another program since it was loaded or saved, it is only overwritten
+    /// if `force` is true.
+    pub fn save_buffer(&mut self, buffer_id: BufferId, force: bool) -> Result<usize, BufferError> {
+        self.ensure_loaded(buffer_id)?;
+        let num_bytes = {
+            let mut b = self.buffers.get(&buffer_id).ok_or(BufferError::NoSuchBuffer(buffer_id))?.borrow_mut();
+            let filename = b.filename.clone().ok_or(BufferError::NoFilename(buffer_id))?;
+            write_buffer(&mut b, &filename, &self.backups, force)?
+        };
+        self.reindex(buffer_id);
+        Ok(num_bytes)
+    }
+
+    /// Replaces the contents of a buffer with the contents of its file, discarding any changes.
+    pub fn reload_buffer(&mut self, buffer_id: BufferId) -> Result<(), BufferError> {
+        self.ensure_loaded(buffer_id)?;
+        {
+            let mut b = self.get(buffer_id).ok_or(BufferError::NoSuchBuffer(buffer_id))?.borrow_mut();
+            let filename = b.filename.clone().ok_or(BufferError::NoFilename(buffer_id))?;
+
+            let disk_stamp = FileStamp::of(&filename);
+            let contents = fs::load_with_codec(&filename, b.codec())
+                .map_err(|message| BufferError::Io { filename: filename.clone(), message })?;
+
+            let len = b.data.len();
+            b.edit(0..len, &contents);
+            b.is_changed = false;
+            b.disk_stamp = disk_stamp;
+            b.reported_stamp = None;
+        }
+        self.reindex(buffer_id);
+        Ok(())
     }
 
-    // Needs a buffer factory passed in. Check to see if the file is already open. If it is, return
-    // the id of the first buffer rather than creating a new one.
-    pub fn open_file() {}
-    // Saves a buffer, but only if it already has a filename. Set changed to false.
-    pub fn save_buffer() {}
-    // Saves a buffer to a specific filename. Might need to allocate a new title. Can change
-    // the filename that a buffer is saved to. Set changed to false.
-    pub fn save_buffer_as() {}
-    // Not sure we need this, it is really just remove disguised.
-    pub fn close_buffer() {}
+    /// Checks whether the files of any buffers have been changed by other programs. Buffers
+    /// without unsaved changes are reloaded. Each change is only reported once.
+    pub fn check_disk_changes(&mut self) -> Vec<DiskChange> {
+        let mut changes = Vec::new();
+
+        let mut ids: Vec<_> = self.buffers.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let (current, is_changed, is_evicted) = {
+                let mut b = self.buffers[&id].borrow_mut();
+                let current = match b.filename {
+                    Some(ref f) => FileStamp::of(f),
+                    None => continue
+                };
+                if current == b.disk_stamp || Some(current) == b.reported_stamp {
+                    continue;
+                }
+                b.reported_stamp = Some(current);
+                (current, b.is_changed, b.is_evicted)
+            };
+
+            self.reindex(id);
+            let change = if current.is_none() {
+                DiskChange::Deleted(id)
+            } else if is_evicted {
+                // There is no text to reload yet; the new contents are loaded when needed.
+                let mut b = self.buffers[&id].borrow_mut();
+                b.disk_stamp = current;
+                b.reported_stamp = None;
+                continue;
+            } else if is_changed {
+                DiskChange::Conflict(id)
+            } else {
+                match self.reload_buffer(id) {
+                    Ok(_) => DiskChange::Reloaded(id),
+                    Err(e) => DiskChange::ReloadFailed(id, e)
+                }
+            };
+            changes.push(change);
+        }
+
+        changes
+    }
+
+    /// Saves a buffer to a specific filename, returning the number of bytes written. From
+    /// then on the buffer is backed by that file and is re-titled to match. It is an error
+    /// for the file to be open in a different buffer.
+    pub fn save_buffer_as<P: AsRef<Path>>(&mut self, buffer_id: BufferId, filename: P) -> Result<usize, BufferError> {
+        let filename = filename.as_ref();
+        self.ensure_loaded(buffer_id)?;
+
+        if let Some(other_id) = self.find_by_filename(filename).map(|rc| rc.borrow().id()) {
+            if other_id != buffer_id {
+                return Err(BufferError::FileAlreadyOpen { filename: PathBuf::from(filename), buffer_id: other_id });
+            }
+        }
 
-    // We also need a filename pre-processor better than just expand_variables.
-    // It should be able to deal with relative names, trying to find a file relative to the
-    // current buffer's directory (for working in projects), or failing that, to the cwd.
+        let other_titles: Vec<_> = self.all_buffers().iter()
+            .filter(|b| b.id() != buffer_id)
+            .map(|b| b.title.clone())
+            .collect();
+
+        let num_bytes = {
+            let mut b = self.buffers.get(&buffer_id).ok_or(BufferError::NoSuchBuffer(buffer_id))?.borrow_mut();
+            if is_special(&b) {
+                return Err(BufferError::SpecialBuffer(buffer_id));
+            }
+
+            // The format follows the new name, so "saving as" can also compress or decompress.
+            let old_codec = mem::replace(&mut b.codec, self.codecs.find_by_name(filename));
+            let num_bytes = write_buffer(&mut b, filename, &self.backups, false)
+                .map_err(|e| { b.codec = old_codec; e })?;
+            b.filename = Some(PathBuf::from(filename));
+            b.title = inner_get_unique_title(&leaf_title(filename), &other_titles);
+            num_bytes
+        };
+        self.reindex(buffer_id);
+        self.retitle_file_buffers();
+        Ok(num_bytes)
+    }
+
+    /// Removes a buffer from the collection and returns it. A buffer with unsaved changes is
+    /// only closed if `force` is true. Special buffers cannot be closed. If the buffer was the
+    /// current buffer then the most recently accessed of the other visible buffers becomes
+    /// current.
+    pub fn close_buffer(&mut self, buffer_id: BufferId, force: bool) -> Result<Buffer, BufferError> {
+        {
+            let b = self.get(buffer_id).ok_or(BufferError::NoSuchBuffer(buffer_id))?.borrow();
+            if is_special(&b) {
+                return Err(BufferError::SpecialBuffer(buffer_id));
+            }
+            if b.is_changed() && !force {
+                return Err(BufferError::UnsavedChanges(buffer_id));
+            }
+        }
+
+        let b = self.remove(buffer_id).unwrap().into_inner();
+        if self.current_buffer == buffer_id {
+            match self.visible_history().first().cloned() {
+                Some(id) => { self.set_current_buffer(id); },
+                None => self.current_buffer = -1
+            }
+        }
+        self.retitle_file_buffers();
+        Ok(b)
+    }
 
-    fn all_buffers(&self) -> Vec<Ref<Buffer>> {
+    pub fn all_buffers(&self) -> Vec<Ref<Buffer>> {
         let x : Vec<_> = self.buffers.values().map(|rcb| rcb.borrow()).collect();
         x
     }
@@ -96,6 +469,32 @@ impl BufferCollection {
 
         inner_get_unique_title(proposed, &titles)
     }
+
+    /// Re-titles all the buffers backed by files according to the title style, so that,
+    /// for example, closing one of two "mod.rs" buffers changes the other from
+    /// "mod.rs<buffer>" back to "mod.rs". Does nothing for the `Numbered` style, whose
+    /// titles never change.
+    fn retitle_file_buffers(&mut self) {
+        if self.title_style == TitleStyle::Numbered {
+            return;
+        }
+
+        let mut files: Vec<(BufferId, PathBuf)> = self.buffers.keys()
+            .filter_map(|&id| self.files.identity(id).map(|identity| (id, identity.path.clone())))
+            .collect();
+        files.sort_by_key(|f| f.0);
+
+        let mut other_titles: Vec<String> = self.all_buffers().iter()
+            .filter(|b| b.filename.is_none())
+            .map(|b| b.title.clone())
+            .collect();
+
+        for (id, title) in path_titles(&files, self.title_style) {
+            let title = inner_get_unique_title(&title, &other_titles);
+            other_titles.push(title.clone());
+            self.buffers[&id].borrow_mut().title = title;
+        }
+    }
 }
 
 fn inner_get_unique_title(proposed: &str, current_titles: &[String]) -> String {
@@ -118,6 +517,45 @@ fn inner_get_unique_title(proposed: &str, current_titles: &[String]) -> String {
     }
 }
 
+/// Special buffers, such as *messages*, are owned by Qork rather than the user.
+fn is_special(buffer: &Buffer) -> bool {
+    buffer.is_read_only() && buffer.filename().is_none()
+}
+
+/// Whether a buffer's text can be dropped and later reloaded from its file without losing
+/// anything.
+fn is_evictable(buffer: &Buffer) -> bool {
+    !buffer.is_evicted && !buffer.is_changed && buffer.filename().as_ref().map_or(false, |f| f.exists())
+}
+
+fn leaf_title(filename: &Path) -> String {
+    filename.file_name().unwrap_or(filename.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// Writes the buffer to `filename`. The first time that a file is overwritten in a session
+/// a backup of it is made, and the save is abandoned if that fails. Unless `force` is true,
+/// the buffer's own file is not overwritten if another program has changed it.
+fn write_buffer(buffer: &mut Buffer, filename: &Path, backups: &Backups, force: bool) -> Result<usize, BufferError> {
+    let is_same_file = buffer.filename.as_ref()
+        .map_or(false, |f| FileIdentity::of(f).is_same_file(&FileIdentity::of(filename)));
+    if is_same_file && !force && FileStamp::of(filename) != buffer.disk_stamp {
+        return Err(BufferError::ModifiedOnDisk(buffer.id));
+    }
+
+    if !(is_same_file && buffer.is_backed_up) {
+        backups.backup(filename)
+            .map_err(|err| BufferError::Io { filename: PathBuf::from(filename), message: format!("Could not make backup: {}", err) })?;
+    }
+
+    let num_bytes = fs::save_with_codec(filename, &buffer.text(), buffer.codec())
+        .map_err(|message| BufferError::Io { filename: PathBuf::from(filename), message })?;
+    buffer.is_changed = false;
+    buffer.is_backed_up = true;
+    buffer.disk_stamp = FileStamp::of(filename);
+    buffer.reported_stamp = None;
+    Ok(num_bytes)
+}
+
 impl Index<BufferId> for BufferCollection {
     type Output = RefCell<Buffer>;
 
@@ -129,7 +567,10 @@ impl Index<BufferId> for BufferCollection {
 #[cfg(test)]
 mod buffer_collection_tests {
     use super::*;
-    use super::super::BufferFactory;
+    use std::env::temp_dir;
+    use backup::BackupStyle;
+    use fs::TestDir;
+    use tempfile::NamedTempFile;
 
     #[test]
     fn get_unique_title_for_empty_collection_returns_proposed() {
@@ -296,11 +737,388 @@ mod buffer_collection_tests {
     fn find_by_filename_for_filename_in_collection_returns_buffer() {
         let mut bc = BufferCollection::new();
         let mut fac = BufferFactory::new();
-        let b = fac.open_file("/c/foo.txt");
+        let b = fac.open_file("/c/foo.txt").unwrap();
         let id = b.id;
         bc.insert(b);
 
         let result = bc.find_by_filename("/c/foo.txt").unwrap();
         assert_eq!(result.borrow().id(), id);
     }
+
+    #[test]
+    fn open_file_for_file_not_open_creates_current_buffer_titled_with_leaf_name() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+
+        assert_eq!(bc.current_buffer(), id);
+        assert_eq!(bc[id].borrow().title(), "foo.txt");
+    }
+
+    #[test]
+    fn open_file_for_file_already_open_returns_existing_buffer() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        bc.open_file(&mut fac, "/d/bar.txt").unwrap();
+
+        assert_eq!(bc.open_file(&mut fac, "/c/foo.txt").unwrap(), id);
+        assert_eq!(bc.len(), 2);
+        assert_eq!(bc.current_buffer(), id);
+    }
+
+    #[test]
+    fn open_file_for_same_file_under_another_name_returns_existing_buffer() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        fs::save_from_string(&filename, "hello").unwrap();
+        ::std::os::unix::fs::symlink(&filename, dir.join("link.txt")).unwrap();
+
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id = bc.open_file(&mut fac, &filename).unwrap();
+        assert_eq!(bc.open_file(&mut fac, dir.join("./a.txt")).unwrap(), id);
+        assert_eq!(bc.open_file(&mut fac, dir.join("link.txt")).unwrap(), id);
+        assert_eq!(bc.len(), 1);
+    }
+
+    #[test]
+    fn open_file_with_directories_title_style_retitles_buffers_with_the_same_leaf_name() {
+        let mut bc = BufferCollection::new();
+        bc.set_title_style(TitleStyle::Directories);
+        let mut fac = BufferFactory::new();
+        let first = bc.open_file(&mut fac, "/q/buffer/mod.rs").unwrap();
+        assert_eq!(bc[first].borrow().title(), "mod.rs");
+
+        let second = bc.open_file(&mut fac, "/q/commands/mod.rs").unwrap();
+        assert_eq!(bc[first].borrow().title(), "mod.rs<buffer>");
+        assert_eq!(bc[second].borrow().title(), "mod.rs<commands>");
+
+        bc.close_buffer(first, false).unwrap();
+        assert_eq!(bc[second].borrow().title(), "mod.rs");
+    }
+
+    #[test]
+    fn open_file_for_unreadable_file_returns_io_error() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        match bc.open_file(&mut fac, temp_dir()) {
+            Err(BufferError::Io { .. }) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn save_buffer_for_buffer_without_filename_returns_no_filename() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let b = fac.new_empty_buffer();
+        let id = b.id;
+        bc.insert(b);
+        assert_eq!(bc.save_buffer(id, false), Err(BufferError::NoFilename(id)));
+    }
+
+    #[test]
+    fn save_buffer_for_unknown_buffer_returns_no_such_buffer() {
+        let mut bc = BufferCollection::new();
+        assert_eq!(bc.save_buffer(42, false), Err(BufferError::NoSuchBuffer(42)));
+    }
+
+    #[test]
+    fn save_buffer_writes_file_and_clears_changed() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let file = NamedTempFile::new().expect("failed to create temporary file");
+        let filename = file.path();
+        let id = bc.open_file(&mut fac, filename).unwrap();
+        bc[id].borrow_mut().replace(0..0, "hello\n");
+
+        assert_eq!(bc.save_buffer(id, false), Ok(6));
+        assert!(!bc[id].borrow().is_changed());
+        assert_eq!(fs::load_to_string(filename).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn save_buffer_backs_up_file_only_before_first_save() {
+        let mut bc = BufferCollection::new();
+        bc.set_backups(Backups::new(BackupStyle::Tilde, None, 0, 0));
+        let mut fac = BufferFactory::new();
+        let file = NamedTempFile::new().expect("failed to create temporary file");
+        fs::save_from_string(file.path(), "original").unwrap();
+        let backup_path = PathBuf::from(format!("{}~", file.path().display()));
+
+        let id = bc.open_file(&mut fac, file.path()).unwrap();
+        bc[id].borrow_mut().replace(0..0, "first ");
+        bc.save_buffer(id, false).unwrap();
+        bc[id].borrow_mut().replace(0..0, "second ");
+        bc.save_buffer(id, false).unwrap();
+
+        let backup = fs::load_to_string(&backup_path);
+        let _ = ::std::fs::remove_file(&backup_path);
+        assert_eq!(backup.unwrap(), "original");
+    }
+
+    #[test]
+    fn save_buffer_for_file_changed_on_disk_requires_force() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        fs::save_from_string(&filename, "original").unwrap();
+        let id = bc.open_file(&mut fac, &filename).unwrap();
+        bc[id].borrow_mut().replace(0..0, "mine ");
+
+        fs::save_from_string(&filename, "theirs").unwrap();
+        assert_eq!(bc.save_buffer(id, false), Err(BufferError::ModifiedOnDisk(id)));
+        assert!(bc.save_buffer(id, true).is_ok());
+        assert_eq!(fs::load_to_string(&filename).unwrap(), "mine original");
+    }
+
+    #[test]
+    fn save_buffer_as_compressed_file_keeps_the_format_when_reopened() {
+        if ::std::process::Command::new("gzip").arg("--version").stdin(::std::process::Stdio::null()).output().is_err() {
+            return;
+        }
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let dir = TestDir::new();
+        let filename = dir.join("notes.txt.gz");
+        let b = fac.new_empty_buffer();
+        let id = b.id;
+        bc.insert(b);
+        bc[id].borrow_mut().replace(0..0, "hello\n");
+
+        assert_eq!(bc.save_buffer_as(id, &filename), Ok(6));
+        assert!(::std::fs::read(&filename).unwrap().starts_with(&[0x1f, 0x8b]));
+        bc.close_buffer(id, false).unwrap();
+
+        let id = bc.open_file(&mut fac, &filename).unwrap();
+        assert_eq!(bc[id].borrow().codec().map(|c| c.name()), Some("gzip"));
+        assert_eq!(bc[id].borrow().text(), "hello\n");
+        bc[id].borrow_mut().replace(0..0, "well, ");
+        bc.save_buffer(id, false).unwrap();
+        bc.reload_buffer(id).unwrap();
+        assert_eq!(bc[id].borrow().text(), "well, hello\n");
+    }
+
+    #[test]
+    fn enforce_memory_limit_evicts_unmodified_buffers_least_recently_used_first() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let dir = TestDir::new();
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt"];
+        for name in &names {
+            fs::save_from_string(&dir.join(name), &name.repeat(1000)).unwrap();
+        }
+        let ids: Vec<_> = names.iter().map(|n| bc.open_file(&mut fac, dir.join(n)).unwrap()).collect();
+        bc[ids[1]].borrow_mut().replace(0..0, "mine ");
+        assert!(bc.enforce_memory_limit().is_empty());
+
+        // a is the oldest but b has unsaved changes, and d is current.
+        bc.set_memory_limit(bc.total_memory_usage().total() - 8000);
+        assert_eq!(bc.enforce_memory_limit(), vec![ids[0], ids[2]]);
+        assert!(bc[ids[0]].borrow().is_evicted());
+        assert_eq!(bc[ids[0]].borrow().text(), "");
+        assert!(!bc[ids[1]].borrow().is_evicted());
+
+        fs::save_from_string(&dir.join("c.txt"), "changed").unwrap();
+        assert!(bc.check_disk_changes().is_empty());
+        bc.set_current_buffer(ids[0]);
+        bc.set_current_buffer(ids[2]);
+        assert_eq!(bc[ids[0]].borrow().text(), "a.txt".repeat(1000));
+        assert_eq!(bc[ids[2]].borrow().text(), "changed");
+        assert!(!bc[ids[2]].borrow().is_changed());
+    }
+
+    #[test]
+    fn evicted_buffers_are_reloaded_before_they_are_cycled_to_or_edited() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let dir = TestDir::new();
+        fs::save_from_string(&dir.join("a.txt"), "a\n").unwrap();
+        fs::save_from_string(&dir.join("b.txt"), "b\n").unwrap();
+        let a = bc.open_file(&mut fac, dir.join("a.txt")).unwrap();
+        let b = bc.open_file(&mut fac, dir.join("b.txt")).unwrap();
+        bc.set_current_buffer(a);
+        bc.set_current_buffer(b);
+
+        bc.set_memory_limit(1);
+        assert_eq!(bc.enforce_memory_limit(), vec![a]);
+        assert_eq!(bc.cycle_buffers(true), Some(a));
+        assert_eq!(bc[a].borrow().text(), "a\n");
+
+        bc.set_current_buffer(b);
+        assert_eq!(bc.enforce_memory_limit(), vec![a]);
+        bc[a].borrow_mut().replace(0..0, "MY EDIT ");
+        bc.save_buffer(a, false).unwrap();
+        assert_eq!(fs::load_to_string(&dir.join("a.txt")).unwrap(), "MY EDIT a\n");
+    }
+
+    #[test]
+    fn check_disk_changes_reloads_unchanged_buffers_and_reports_conflicts_once() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let dir = TestDir::new();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::save_from_string(&a, "a").unwrap();
+        fs::save_from_string(&b, "b").unwrap();
+        let id_a = bc.open_file(&mut fac, &a).unwrap();
+        let id_b = bc.open_file(&mut fac, &b).unwrap();
+        bc[id_b].borrow_mut().replace(0..0, "mine ");
+        assert!(bc.check_disk_changes().is_empty());
+
+        fs::save_from_string(&a, "new a").unwrap();
+        fs::save_from_string(&b, "new b").unwrap();
+        assert_eq!(bc.check_disk_changes(), vec![DiskChange::Reloaded(id_a), DiskChange::Conflict(id_b)]);
+        assert_eq!(bc[id_a].borrow().text(), "new a");
+        assert!(!bc[id_a].borrow().is_changed());
+        assert!(bc.check_disk_changes().is_empty());
+
+        bc.reload_buffer(id_b).unwrap();
+        assert_eq!(bc[id_b].borrow().text(), "new b");
+        assert!(bc.save_buffer(id_b, false).is_ok());
+    }
+
+    #[test]
+    fn save_buffer_as_changes_filename_and_title() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let file = NamedTempFile::new().expect("failed to create temporary file");
+        let b = fac.new_empty_buffer();
+        let id = b.id;
+        bc.insert(b);
+        bc[id].borrow_mut().replace(0..0, "hello");
+
+        bc.save_buffer_as(id, file.path()).unwrap();
+
+        let b = bc[id].borrow();
+        assert_eq!(b.filename(), &Some(file.path().to_path_buf()));
+        assert_eq!(b.title(), file.path().file_name().unwrap().to_string_lossy());
+        assert!(!b.is_changed());
+    }
+
+    #[test]
+    fn save_buffer_as_for_file_open_in_other_buffer_returns_file_already_open() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let other_id = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let b = fac.new_empty_buffer();
+        let id = b.id;
+        bc.insert(b);
+
+        assert_eq!(bc.save_buffer_as(id, "/c/foo.txt"),
+            Err(BufferError::FileAlreadyOpen { filename: PathBuf::from("/c/foo.txt"), buffer_id: other_id }));
+    }
+
+    #[test]
+    fn save_buffer_as_for_special_buffer_returns_special_buffer() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let b = fac.new_special_buffer("*messages*");
+        let id = b.id;
+        bc.insert(b);
+        assert_eq!(bc.save_buffer_as(id, "/c/foo.txt"), Err(BufferError::SpecialBuffer(id)));
+    }
+
+    #[test]
+    fn close_buffer_for_changed_buffer_requires_force() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        bc[id].borrow_mut().replace(0..0, "x");
+
+        assert_eq!(bc.close_buffer(id, false).unwrap_err(), BufferError::UnsavedChanges(id));
+        assert_eq!(bc.close_buffer(id, true).unwrap().id(), id);
+        assert!(bc.is_empty());
+    }
+
+    #[test]
+    fn close_buffer_for_current_buffer_makes_another_buffer_current() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id1 = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let id2 = bc.open_file(&mut fac, "/c/bar.txt").unwrap();
+
+        bc.close_buffer(id2, false).unwrap();
+        assert_eq!(bc.current_buffer(), id1);
+        bc.close_buffer(id1, false).unwrap();
+        assert_eq!(bc.current_buffer(), -1);
+    }
+
+    #[test]
+    fn close_buffer_for_current_buffer_makes_most_recently_accessed_buffer_current() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id1 = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let id2 = bc.open_file(&mut fac, "/c/bar.txt").unwrap();
+        let id3 = bc.open_file(&mut fac, "/c/baz.txt").unwrap();
+        bc.set_current_buffer(id1);
+        bc.set_current_buffer(id3);
+
+        bc.close_buffer(id3, false).unwrap();
+        assert_eq!(bc.current_buffer(), id1);
+        assert_eq!(bc.history(), &[id1, id2]);
+    }
+
+    #[test]
+    fn switch_to_previous_buffer_toggles_between_two_buffers() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id1 = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let id2 = bc.open_file(&mut fac, "/c/bar.txt").unwrap();
+        let id3 = bc.open_file(&mut fac, "/c/baz.txt").unwrap();
+        bc.set_current_buffer(id1);
+
+        assert_eq!(bc.switch_to_previous_buffer(), Some(id3));
+        assert_eq!(bc.switch_to_previous_buffer(), Some(id1));
+        assert_eq!(bc.history(), &[id1, id3, id2]);
+    }
+
+    #[test]
+    fn cycle_buffers_visits_every_buffer_in_history_order() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let id1 = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let id2 = bc.open_file(&mut fac, "/c/bar.txt").unwrap();
+        let id3 = bc.open_file(&mut fac, "/c/baz.txt").unwrap();
+
+        assert_eq!(bc.cycle_buffers(true), Some(id2));
+        assert_eq!(bc.cycle_buffers(true), Some(id1));
+        assert_eq!(bc.cycle_buffers(true), Some(id3));
+        assert_eq!(bc.cycle_buffers(false), Some(id1));
+        assert_eq!(bc.history(), &[id3, id2, id1]);
+    }
+
+    #[test]
+    fn set_visible_buffers_hides_other_buffers_from_history_but_not_new_ones() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let messages = fac.new_special_buffer("*messages*");
+        let messages_id = messages.id;
+        bc.insert(messages);
+        let id1 = bc.open_file(&mut fac, "/c/foo.txt").unwrap();
+        let id2 = bc.open_file(&mut fac, "/c/bar.txt").unwrap();
+
+        bc.set_visible_buffers(Some(vec![id1].into_iter().collect()));
+        assert!(!bc.is_visible(id2));
+        assert!(bc.is_visible(messages_id));
+        assert_eq!(bc.visible_history(), vec![id1, messages_id]);
+
+        let id3 = bc.open_file(&mut fac, "/c/baz.txt").unwrap();
+        assert_eq!(bc.switch_to_previous_buffer(), Some(id1));
+        assert_eq!(bc.cycle_buffers(true), Some(id3));
+
+        bc.set_visible_buffers(None);
+        assert!(bc.is_visible(id2));
+    }
+
+    #[test]
+    fn close_buffer_for_special_buffer_returns_special_buffer() {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let b = fac.new_special_buffer("*messages*");
+        let id = b.id;
+        bc.insert(b);
+        assert_eq!(bc.close_buffer(id, true).unwrap_err(), BufferError::SpecialBuffer(id));
+    }
 }
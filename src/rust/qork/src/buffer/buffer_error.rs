@@ -0,0 +1,41 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use super::BufferId;
+
+/// The ways in which operations on buffers can fail.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferError {
+    /// There is no buffer with the given id.
+    NoSuchBuffer(BufferId),
+    /// The buffer is not backed by a file, so it can only be saved using "save as".
+    NoFilename(BufferId),
+    /// The file is already open in another buffer.
+    FileAlreadyOpen { filename: PathBuf, buffer_id: BufferId },
+    /// The buffer has changes which have not been saved, and closing it was not forced.
+    UnsavedChanges(BufferId),
+    /// The file was changed by another program since it was loaded or last saved.
+    ModifiedOnDisk(BufferId),
+    /// The buffer is a special buffer, such as *messages*, which belongs to Qork.
+    SpecialBuffer(BufferId),
+    /// Reading or writing the file failed.
+    Io { filename: PathBuf, message: String }
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BufferError::NoSuchBuffer(id) => write!(f, "No buffer with an id of {} exists", id),
+            BufferError::NoFilename(id) => write!(f, "Buffer {} has no filename", id),
+            BufferError::FileAlreadyOpen { ref filename, buffer_id } =>
+                write!(f, "{:?} is already open in buffer {}", filename, buffer_id),
+            BufferError::UnsavedChanges(id) => write!(f, "Buffer {} has unsaved changes", id),
+            BufferError::ModifiedOnDisk(id) => write!(f, "The file of buffer {} has been changed by another program", id),
+            BufferError::SpecialBuffer(id) => write!(f, "Buffer {} is a special buffer", id),
+            BufferError::Io { ref filename, ref message } => write!(f, "{:?}: {}", filename, message)
+        }
+    }
+}
+
+impl Error for BufferError {}
@@ -1,16 +1,26 @@
-use fs;
+use fs::{self, Codecs, FileStamp};
 use std::path::{Path, PathBuf};
 use time::now_utc;
 use xi_rope::Rope;
-use super::{Buffer, BufferId};
+use super::{Buffer, BufferError, BufferId};
 
 pub struct BufferFactory {
-    next_buffer_id: BufferId
+    next_buffer_id: BufferId,
+    codecs: Codecs
 }
 
 impl BufferFactory {
     pub fn new() -> BufferFactory {
-        BufferFactory { next_buffer_id: 0 }
+        BufferFactory { next_buffer_id: 0, codecs: Codecs::standard() }
+    }
+
+    /// Sets the codecs used to decode files, such as compressed files, when opening them.
+    pub fn set_codecs(&mut self, codecs: Codecs) {
+        self.codecs = codecs;
+    }
+
+    pub fn codecs(&self) -> &Codecs {
+        &self.codecs
     }
 
     fn empty_buffer(id: BufferId) -> Buffer {
@@ -22,6 +32,16 @@ impl BufferFactory {
             title: String::default(),
             data: Rope::from(""),
             is_changed: false,
+            disk_stamp: None,
+            reported_stamp: None,
+            is_backed_up: false,
+            codec: None,
+            is_evicted: false,
+            is_read_only: false,
+            point: 0,
+            narrowing: None,
+            edits: Vec::new(),
+            first_revision: 0,
             created_time_utc: now,
             last_accessed_time_utc: now,
             last_changed_time_utc: now
@@ -33,33 +53,75 @@ impl BufferFactory {
         Self::empty_buffer(self.next_buffer_id)
     }
 
-    /// Creates a buffer from a filename. If there is already a Buffer for the file it is returned,
-    /// else the file is opened and loaded if it exists, else if the file does not exist then a
-    /// new buffer is created with that filename, but no loading is done (the Buffer is considered
-    /// to be backed by a file that does not exist yet, it will be created when you save it.)
-    pub fn open_file<P: AsRef<Path>>(&mut self, filename: P) -> Buffer {
+    /// Creates a read-only buffer with the given title, not backed by any file. Such buffers
+    /// are used by Qork to present information such as log messages.
+    pub fn new_special_buffer(&mut self, title: &str) -> Buffer {
+        let mut b = self.new_empty_buffer();
+        b.title = String::from(title);
+        b.is_read_only = true;
+        b
+    }
+
+    /// Creates a buffer from a filename. The file is loaded if it exists, else if the file does
+    /// not exist then a new buffer is created with that filename, but no loading is done (the
+    /// Buffer is considered to be backed by a file that does not exist yet, it will be created
+    /// when you save it.) It is an error if the file exists but cannot be read.
+    ///
+    /// Files matching one of the codecs, such as "notes.txt.gz", are decoded as they are
+    /// loaded and the buffer remembers the codec so that they are saved in the same format. A
+    /// file which only looks like it is in a codec's format, because it cannot be decoded but
+    /// can be read as it is, is opened as plain text.
+    pub fn open_file<P: AsRef<Path>>(&mut self, filename: P) -> Result<Buffer, BufferError> {
         let filename = PathBuf::from(filename.as_ref());
+        // The stamp is taken first so that a change made while loading is seen as a change.
+        let disk_stamp = FileStamp::of(&filename);
+        let mut codec = self.codecs.find(&filename);
+        let contents = if filename.exists() {
+            match fs::load_with_codec(&filename, codec.as_ref().map(|c| &**c)) {
+                Ok(text) => text,
+                Err(message) => match (codec.take(), fs::load_with_codec(&filename, None)) {
+                    (Some(c), Ok(text)) => {
+                        warn!("{:?} could not be decoded using {}, so it has been opened as plain text: {}", filename, c.name(), message);
+                        text
+                    },
+                    _ => return Err(BufferError::Io { filename, message })
+                }
+            }
+        } else {
+            String::new()
+        };
+
         let title = filename.to_string_lossy().into_owned();
         let now = now_utc();
         self.next_buffer_id += 1;
-        let contents = fs::load_to_string(&filename).unwrap_or_default();
 
-        Buffer {
+        Ok(Buffer {
             id: self.next_buffer_id,
             filename: Some(filename),
             title: title,
             data: Rope::from(contents),
             is_changed: false,
+            disk_stamp: disk_stamp,
+            reported_stamp: None,
+            is_backed_up: false,
+            codec: codec,
+            is_evicted: false,
+            is_read_only: false,
+            point: 0,
+            narrowing: None,
+            edits: Vec::new(),
+            first_revision: 0,
             created_time_utc: now,
             last_accessed_time_utc: now,
             last_changed_time_utc: now
-        }
+        })
     }
 }
 
 #[cfg(test)]
 mod buffer_tests {
     use super::*;
+    use fs::{save_from_string, TestDir};
 
     #[test]
     fn new_empty_buffer_increments_buffer_id() {
@@ -68,4 +130,15 @@ mod buffer_tests {
         let b2 = fac.new_empty_buffer();
         assert!(b2.id == b1.id + 1);
     }
+
+    #[test]
+    fn open_file_which_cannot_be_decoded_opens_it_as_plain_text() {
+        let dir = TestDir::new();
+        let filename = dir.join("notes.txt");
+        save_from_string(&filename, "BZh9 is the start of a bzip2 file").unwrap();
+
+        let b = BufferFactory::new().open_file(&filename).unwrap();
+        assert_eq!(b.text(), "BZh9 is the start of a bzip2 file");
+        assert!(b.codec().is_none());
+    }
 }
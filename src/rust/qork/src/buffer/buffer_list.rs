@@ -0,0 +1,297 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use time::{strftime, Tm};
+use regex::Regex;
+use super::{Buffer, BufferCollection, BufferId};
+
+/// The title of the special buffer which shows the buffer list.
+pub const BUFFER_LIST_TITLE: &'static str = "*buffer list*";
+
+/// Buffers are always held as UTF-8 text, which is followed by the name of the buffer's codec
+/// if its file is compressed.
+const ENCODING: &'static str = "utf-8";
+
+const TIME_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S";
+
+/// The column the buffer list is sorted on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferListSort {
+    Id,
+    Title,
+    Path,
+    Size,
+    Accessed
+}
+
+impl BufferListSort {
+    pub fn parse(name: &str) -> Option<BufferListSort> {
+        match name {
+            "id" => Some(BufferListSort::Id),
+            "title" => Some(BufferListSort::Title),
+            "path" => Some(BufferListSort::Path),
+            "size" => Some(BufferListSort::Size),
+            "accessed" => Some(BufferListSort::Accessed),
+            _ => None
+        }
+    }
+}
+
+/// A condition that buffers must meet to be shown in the buffer list.
+#[derive(Debug, Clone)]
+pub enum BufferFilter {
+    /// Only buffers with unsaved changes.
+    Modified,
+    /// Only buffers on files within a directory, at any depth.
+    Directory(PathBuf),
+    /// Only buffers on files with an extension, given without the dot.
+    Extension(String),
+    /// Only buffers whose title or path matches.
+    Regex(Regex)
+}
+
+impl BufferFilter {
+    fn matches(&self, entry: &BufferListEntry) -> bool {
+        match *self {
+            BufferFilter::Modified => entry.is_changed,
+            BufferFilter::Directory(ref dir) => entry.path.as_ref().map_or(false, |p| p.starts_with(dir)),
+            BufferFilter::Extension(ref ext) => entry.path.as_ref()
+                .and_then(|p| p.extension())
+                .map_or(false, |e| e.to_string_lossy() == ext.as_str()),
+            BufferFilter::Regex(ref re) => re.is_match(&entry.title) ||
+                entry.path.as_ref().map_or(false, |p| re.is_match(&p.to_string_lossy()))
+        }
+    }
+}
+
+/// One row of the buffer list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferListEntry {
+    pub id: BufferId,
+    pub title: String,
+    pub path: Option<PathBuf>,
+    /// The size of the text in bytes, or of the file if the text has been evicted.
+    pub size: usize,
+    pub is_changed: bool,
+    /// How the file is encoded, such as "utf-8" or "utf-8+gzip".
+    pub encoding: String,
+    pub last_accessed: Tm
+}
+
+impl BufferListEntry {
+    pub fn of(buffer: &Buffer) -> BufferListEntry {
+        BufferListEntry {
+            id: buffer.id(),
+            title: buffer.title.clone(),
+            path: buffer.filename.clone(),
+            size: if buffer.is_evicted {
+                buffer.disk_stamp.as_ref().map_or(0, |s| s.len as usize)
+            } else {
+                buffer.data.len()
+            },
+            is_changed: buffer.is_changed,
+            encoding: match buffer.codec() {
+                Some(c) => format!("{}+{}", ENCODING, c.name()),
+                None => String::from(ENCODING)
+            },
+            last_accessed: buffer.last_accessed_time_utc
+        }
+    }
+}
+
+/// The state of the buffer list: how it is sorted and filtered, and which buffers are marked
+/// for a batch action such as saving or closing.
+#[derive(Debug)]
+pub struct BufferList {
+    sort: BufferListSort,
+    reverse: bool,
+    filters: Vec<BufferFilter>,
+    marks: BTreeSet<BufferId>
+}
+
+impl Default for BufferList {
+    fn default() -> BufferList {
+        BufferList {
+            sort: BufferListSort::Id,
+            reverse: false,
+            filters: Vec::new(),
+            marks: BTreeSet::new()
+        }
+    }
+}
+
+impl BufferList {
+    pub fn new() -> BufferList {
+        BufferList::default()
+    }
+
+    pub fn set_sort(&mut self, sort: BufferListSort, reverse: bool) {
+        self.sort = sort;
+        self.reverse = reverse;
+    }
+
+    /// Adds a filter. Buffers must pass every filter to be listed.
+    pub fn add_filter(&mut self, filter: BufferFilter) {
+        self.filters.push(filter);
+    }
+
+    pub fn clear_filters(&mut self) {
+        self.filters.clear();
+    }
+
+    pub fn mark(&mut self, buffer_id: BufferId) {
+        self.marks.insert(buffer_id);
+    }
+
+    pub fn unmark(&mut self, buffer_id: BufferId) {
+        self.marks.remove(&buffer_id);
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marks.clear();
+    }
+
+    /// The marked buffers, in id order.
+    pub fn marks(&self) -> Vec<BufferId> {
+        self.marks.iter().cloned().collect()
+    }
+
+    /// Lists the visible buffers which pass the filters, sorted. The buffer list itself is
+    /// left out.
+    pub fn entries(&self, buffers: &BufferCollection) -> Vec<BufferListEntry> {
+        let mut entries: Vec<_> = buffers.all_buffers().iter()
+            .filter(|b| b.title != BUFFER_LIST_TITLE || b.filename.is_some())
+            .filter(|b| buffers.is_visible(b.id()))
+            .map(|b| BufferListEntry::of(b))
+            .filter(|e| self.filters.iter().all(|f| f.matches(e)))
+            .collect();
+
+        entries.sort_by(|a, b| {
+            let ordering = match self.sort {
+                BufferListSort::Id => a.id.cmp(&b.id),
+                BufferListSort::Title => a.title.cmp(&b.title),
+                BufferListSort::Path => a.path.cmp(&b.path),
+                BufferListSort::Size => a.size.cmp(&b.size),
+                BufferListSort::Accessed => a.last_accessed.cmp(&b.last_accessed)
+            };
+            ordering.then(a.id.cmp(&b.id))
+        });
+        if self.reverse {
+            entries.reverse();
+        }
+
+        entries
+    }
+
+    /// Formats the entries as a table, one line per buffer after a heading. Marked buffers
+    /// have a '>' in the first column and modified buffers a '*' in the second.
+    pub fn render(&self, entries: &[BufferListEntry]) -> Vec<String> {
+        let id_width = entries.iter().map(|e| e.id.to_string().len()).max().unwrap_or(0).max(2);
+        let title_width = entries.iter().map(|e| e.title.chars().count()).max().unwrap_or(0).max(5);
+        let size_width = entries.iter().map(|e| e.size.to_string().len()).max().unwrap_or(0).max(4);
+        let encoding_width = entries.iter().map(|e| e.encoding.len()).max().unwrap_or(0).max(8);
+
+        let mut lines = vec![format!("MC {:>iw$} {:<tw$} {:>sw$} {:<ew$} {:<19} {}",
+                                     "Id", "Title", "Size", "Encoding", "Accessed", "Path",
+                                     iw = id_width, tw = title_width, sw = size_width, ew = encoding_width)];
+
+        for e in entries {
+            let accessed = strftime(TIME_FORMAT, &e.last_accessed).unwrap_or_default();
+            let path = e.path.as_ref().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+            lines.push(format!("{}{} {:>iw$} {:<tw$} {:>sw$} {:<ew$} {:<19} {}",
+                               if self.marks.contains(&e.id) { '>' } else { ' ' },
+                               if e.is_changed { '*' } else { ' ' },
+                               e.id, e.title, e.size, e.encoding, accessed, path,
+                               iw = id_width, tw = title_width, sw = size_width, ew = encoding_width).trim_end().to_string());
+        }
+
+        if self.filters.is_empty() {
+            lines.push(format!("{} buffers", entries.len()));
+        } else {
+            lines.push(format!("{} buffers match {} filters", entries.len(), self.filters.len()));
+        }
+
+        lines
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::BufferFactory;
+    use fs::{save_from_string, TestDir};
+
+    fn collection() -> (BufferCollection, Vec<BufferId>) {
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let ids = vec![
+            bc.open_file(&mut fac, "/q/src/main.rs").unwrap(),
+            bc.open_file(&mut fac, "/q/README.md").unwrap(),
+            bc.open_file(&mut fac, "/q/src/lib.rs").unwrap()
+        ];
+        bc[ids[1]].borrow_mut().replace(0..0, "hello");
+        (bc, ids)
+    }
+
+    fn ids(entries: &[BufferListEntry]) -> Vec<BufferId> {
+        entries.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn entries_are_sorted_by_the_chosen_column() {
+        let (bc, id) = collection();
+        let mut list = BufferList::new();
+        assert_eq!(ids(&list.entries(&bc)), vec![id[0], id[1], id[2]]);
+
+        list.set_sort(BufferListSort::Title, false);
+        assert_eq!(ids(&list.entries(&bc)), vec![id[1], id[2], id[0]]);
+
+        list.set_sort(BufferListSort::Size, true);
+        assert_eq!(ids(&list.entries(&bc))[0], id[1]);
+    }
+
+    #[test]
+    fn entries_only_include_buffers_passing_all_filters() {
+        let (bc, id) = collection();
+        let mut list = BufferList::new();
+
+        list.add_filter(BufferFilter::Modified);
+        assert_eq!(ids(&list.entries(&bc)), vec![id[1]]);
+
+        list.clear_filters();
+        list.add_filter(BufferFilter::Directory(PathBuf::from("/q/src")));
+        list.add_filter(BufferFilter::Extension(String::from("rs")));
+        list.add_filter(BufferFilter::Regex(Regex::new("^l").unwrap()));
+        assert_eq!(ids(&list.entries(&bc)), vec![id[2]]);
+    }
+
+    #[test]
+    fn render_shows_marks_and_modified_flags() {
+        let (bc, id) = collection();
+        let mut list = BufferList::new();
+        list.mark(id[0]);
+
+        let lines = list.render(&list.entries(&bc));
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("> "));
+        assert!(lines[2].starts_with(" *"));
+        assert!(lines[2].ends_with("/q/README.md"));
+        assert_eq!(lines[4], "3 buffers");
+    }
+
+    #[test]
+    fn entries_show_the_codec_and_the_file_size_of_evicted_buffers() {
+        let dir = TestDir::new();
+        save_from_string(&dir.join("a.txt"), "hello").unwrap();
+        let mut bc = BufferCollection::new();
+        let mut fac = BufferFactory::new();
+        let evicted = bc.open_file(&mut fac, dir.join("a.txt")).unwrap();
+        let compressed = bc.open_file(&mut fac, "/q/notes.txt.gz").unwrap();
+        bc.set_memory_limit(1);
+        assert_eq!(bc.enforce_memory_limit(), vec![evicted]);
+
+        let entries = BufferList::new().entries(&bc);
+        assert_eq!((entries[0].id, entries[0].size, entries[0].encoding.as_str()), (evicted, 5, "utf-8"));
+        assert_eq!((entries[1].id, entries[1].encoding.as_str()), (compressed, "utf-8+gzip"));
+    }
+}
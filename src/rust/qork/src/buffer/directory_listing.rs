@@ -0,0 +1,558 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use time::{empty_tm, strftime, Tm};
+use datetime::system_time_to_utc_tm;
+use regex::Regex;
+
+/// Flags an entry as marked, for bulk commands such as copying.
+pub const FLAG_MARK: char = '*';
+/// Flags an entry to be deleted when the edits are applied.
+pub const FLAG_DELETE: char = 'D';
+/// Flags an entry whose name has been edited to be copied to the new name, instead of
+/// being renamed.
+pub const FLAG_COPY: char = 'C';
+const FLAG_NONE: char = ' ';
+
+/// Lines added after the entries which start with this make new directories.
+const NEW_DIRECTORY_PREFIX: &'static str = "+ ";
+
+const PARENT_NAME: &'static str = "..";
+const TIME_FORMAT: &'static str = "%Y-%m-%d %H:%M";
+const TIME_WIDTH: usize = 16;
+const MODE_WIDTH: usize = 10;
+
+/// One file or directory in a listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub mode: u32,
+    pub modified: Tm
+}
+
+impl DirectoryEntry {
+    fn of(name: String, path: &Path) -> io::Result<DirectoryEntry> {
+        let metadata = fs::symlink_metadata(path)?;
+        let modified = metadata.modified().map(|t| system_time_to_utc_tm(&t)).unwrap_or_else(|_| empty_tm());
+        Ok(DirectoryEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            mode: metadata.permissions().mode(),
+            modified
+        })
+    }
+
+    /// The mode as `ls -l` shows it, such as "drwxr-xr-x".
+    fn mode_string(&self) -> String {
+        let mut s = String::with_capacity(MODE_WIDTH);
+        s.push(match self.mode & 0o170000 {
+            0o040000 => 'd',
+            0o120000 => 'l',
+            _ => '-'
+        });
+        for shift in &[6, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        s
+    }
+
+    fn display_name(&self) -> String {
+        if self.is_dir && self.name != PARENT_NAME {
+            format!("{}/", self.name)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// A change to the file system made by editing a directory listing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirectoryEdit {
+    Rename { from: PathBuf, to: PathBuf },
+    Copy { from: PathBuf, to: PathBuf },
+    Delete(PathBuf),
+    MakeDirectory(PathBuf)
+}
+
+impl fmt::Display for DirectoryEdit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DirectoryEdit::Rename { ref from, ref to } => write!(f, "rename {:?} to {:?}", from, to),
+            DirectoryEdit::Copy { ref from, ref to } => write!(f, "copy {:?} to {:?}", from, to),
+            DirectoryEdit::Delete(ref path) => write!(f, "delete {:?}", path),
+            DirectoryEdit::MakeDirectory(ref path) => write!(f, "make directory {:?}", path)
+        }
+    }
+}
+
+/// The contents of a directory as shown in a directory buffer, Emacs dired style. The first
+/// line is the directory and each following line is an entry, starting with "..":
+///
+///   * -rw-r--r--  1234 2018-03-01 12:00 main.rs
+///
+/// The listing is edited like any other text and the changes then applied by `parse_edits`
+/// and `apply_edits`: changing a name renames the entry (or copies it, if flagged 'C'),
+/// flagging it 'D' deletes it, and adding "+ name" lines after the entries makes directories.
+/// Only the flag and name columns may be changed, and entry lines must not be removed.
+#[derive(Debug, Clone)]
+pub struct DirectoryListing {
+    dir: PathBuf,
+    entries: Vec<DirectoryEntry>,
+    size_width: usize
+}
+
+impl DirectoryListing {
+    /// Reads a directory. Directories are listed before files, and both by name. Entries which
+    /// cannot be read, for example because they were deleted while the directory was being
+    /// read, are left out.
+    pub fn read<P: AsRef<Path>>(dir: P) -> io::Result<DirectoryListing> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let result = entry.and_then(|e| DirectoryEntry::of(e.file_name().to_string_lossy().into_owned(), &e.path()));
+            match result {
+                Ok(e) => entries.push(e),
+                Err(e) => warn!("Leaving an entry out of the listing of {:?}: {}", dir, e)
+            }
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+        if let Ok(parent) = DirectoryEntry::of(String::from(PARENT_NAME), &dir.join(PARENT_NAME)) {
+            entries.insert(0, parent);
+        }
+
+        let size_width = entries.iter().map(|e| e.size.to_string().len()).max().unwrap_or(0);
+        Ok(DirectoryListing { dir, entries, size_width })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entries(&self) -> &[DirectoryEntry] {
+        &self.entries
+    }
+
+    /// The title for the directory's buffer, its leaf name followed by '/'.
+    pub fn title(&self) -> String {
+        let name = self.dir.file_name().unwrap_or(self.dir.as_os_str()).to_string_lossy();
+        format!("{}/", name.trim_end_matches('/'))
+    }
+
+    /// The byte column at which names start on entry lines.
+    fn name_column(&self) -> usize {
+        2 + MODE_WIDTH + 1 + self.size_width + 1 + TIME_WIDTH + 1
+    }
+
+    fn render_entry(&self, entry: &DirectoryEntry) -> String {
+        let modified = strftime(TIME_FORMAT, &entry.modified).unwrap_or_default();
+        format!("{} {} {:>sw$} {:<tw$} {}", FLAG_NONE, entry.mode_string(), entry.size, modified, entry.display_name(),
+                sw = self.size_width, tw = TIME_WIDTH)
+    }
+
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = vec![format!("  {}:", self.dir.display())];
+        lines.extend(self.entries.iter().map(|e| self.render_entry(e)));
+        lines
+    }
+
+    /// The path of the entry shown on a (0-based) line of the listing.
+    pub fn path_at_line(&self, line: usize) -> Option<PathBuf> {
+        let entry = self.entries.get(line.checked_sub(1)?)?;
+        Some(self.dir.join(&entry.name))
+    }
+
+    /// Sets the flag of every entry whose name matches `re`, or clears it if `flag` is ' ',
+    /// returning the new lines. Lines which are not entries are left alone.
+    pub fn flag_matching(&self, lines: &[String], re: &Regex, flag: char) -> Vec<String> {
+        lines.iter().enumerate()
+            .map(|(i, line)| {
+                let matches = i > 0 && self.entries.get(i - 1)
+                    .map_or(false, |e| e.name != PARENT_NAME && re.is_match(&e.name));
+                if matches && !line.is_empty() {
+                    let mut flagged = String::with_capacity(line.len());
+                    flagged.push(flag);
+                    flagged.push_str(&line[line.chars().next().unwrap().len_utf8()..]);
+                    flagged
+                } else {
+                    line.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// The paths of the entries flagged with `flag` in the edited lines.
+    pub fn flagged(&self, lines: &[String], flag: char) -> Vec<PathBuf> {
+        self.entries.iter().zip(lines.iter().skip(1))
+            .filter(|&(e, line)| e.name != PARENT_NAME && line.starts_with(flag))
+            .map(|(e, _)| self.dir.join(&e.name))
+            .collect()
+    }
+
+    /// Works out the changes to make to the file system from the edited lines of the listing.
+    /// Nothing is changed if any line cannot be understood.
+    pub fn parse_edits(&self, lines: &[String]) -> Result<Vec<DirectoryEdit>, String> {
+        let name_column = self.name_column();
+        let mut edits = Vec::new();
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            let line = lines.get(i + 1)
+                .ok_or_else(|| format!("The line for {} has been removed, flag it with '{}' to delete it", entry.name, FLAG_DELETE))?;
+            let original = self.render_entry(entry);
+            if line.get(1..name_column) != original.get(1..name_column) {
+                return Err(format!("Line {}: only the flag and name of {} can be changed", i + 2, entry.name));
+            }
+
+            let flag = line.chars().next().unwrap_or(FLAG_NONE);
+            let name = line[name_column..].trim_end();
+            let name = if entry.is_dir { name.trim_end_matches('/') } else { name };
+            let from = self.dir.join(&entry.name);
+            let to = self.dir.join(name);
+
+            if entry.name == PARENT_NAME {
+                if name != PARENT_NAME {
+                    return Err(format!("Line {}: {} cannot be renamed", i + 2, PARENT_NAME));
+                }
+                continue;
+            }
+            if name.is_empty() {
+                return Err(format!("Line {}: the name of {} has been deleted", i + 2, entry.name));
+            }
+            if name != entry.name {
+                check_name(i + 2, name)?;
+            }
+
+            match flag {
+                FLAG_DELETE => edits.push(DirectoryEdit::Delete(from)),
+                FLAG_COPY if name == entry.name => return Err(format!("Line {}: change the name of {} to copy it", i + 2, entry.name)),
+                FLAG_COPY => edits.push(DirectoryEdit::Copy { from, to }),
+                FLAG_NONE | FLAG_MARK if name != entry.name => edits.push(DirectoryEdit::Rename { from, to }),
+                FLAG_NONE | FLAG_MARK => {},
+                other => return Err(format!("Line {}: unknown flag '{}'", i + 2, other))
+            }
+        }
+
+        for (i, line) in lines.iter().enumerate().skip(self.entries.len() + 1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !line.starts_with(NEW_DIRECTORY_PREFIX) {
+                return Err(format!("Line {}: new directories are added as '{}name'", i + 1, NEW_DIRECTORY_PREFIX));
+            }
+            let name = line[NEW_DIRECTORY_PREFIX.len()..].trim().trim_end_matches('/');
+            check_name(i + 1, name)?;
+            edits.push(DirectoryEdit::MakeDirectory(self.dir.join(name)));
+        }
+
+        self.check_targets(&edits)?;
+        Ok(edits)
+    }
+
+    /// Checks that no edit would overwrite an existing file, other than one which is itself
+    /// being renamed, or write to the same place as another edit, and that no directory would
+    /// be copied into itself.
+    fn check_targets(&self, edits: &[DirectoryEdit]) -> Result<(), String> {
+        let renamed: HashSet<&Path> = edits.iter()
+            .filter_map(|e| match *e {
+                DirectoryEdit::Rename { ref from, .. } => Some(from.as_path()),
+                _ => None
+            })
+            .collect();
+
+        let mut targets = HashSet::new();
+        for edit in edits {
+            let target = match *edit {
+                DirectoryEdit::Copy { ref from, ref to } => {
+                    check_copy(from, to)?;
+                    to
+                },
+                DirectoryEdit::Rename { ref to, .. } => to,
+                DirectoryEdit::MakeDirectory(ref path) => path,
+                DirectoryEdit::Delete(_) => continue
+            };
+            if !targets.insert(target) {
+                return Err(format!("More than one entry would be written to {:?}", target));
+            }
+            if fs::symlink_metadata(target).is_ok() && !renamed.contains(target.as_path()) {
+                return Err(format!("{:?} already exists", target));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a name typed into a listing names an entry in the listed directory, rather than
+/// somewhere else.
+fn check_name(line: usize, name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains('/') || name == "." || name == PARENT_NAME {
+        Err(format!("Line {}: {:?} is not a valid name, entries can only be renamed within the directory", line, name))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that `to` is not inside `from`, since copying a directory into itself would copy the
+/// copy again and again.
+fn check_copy(from: &Path, to: &Path) -> Result<(), String> {
+    let from = fs::canonicalize(from).unwrap_or_else(|_| from.to_path_buf());
+    // The copy does not exist yet, so only the directory it would be made in can be resolved.
+    let to = match (to.parent().and_then(|p| fs::canonicalize(p).ok()), to.file_name()) {
+        (Some(parent), Some(name)) => parent.join(name),
+        _ => to.to_path_buf()
+    };
+    if to.starts_with(&from) {
+        Err(format!("{:?} cannot be copied into itself", from))
+    } else {
+        Ok(())
+    }
+}
+
+/// The directories which are not empty among those the edits delete. Deleting them deletes
+/// everything in them, which the user should confirm first.
+pub fn non_empty_directories(edits: &[DirectoryEdit]) -> Vec<PathBuf> {
+    edits.iter()
+        .filter_map(|e| match *e {
+            DirectoryEdit::Delete(ref path) => Some(path),
+            _ => None
+        })
+        .filter(|path| fs::symlink_metadata(path).map_or(false, |m| m.is_dir()) &&
+                       fs::read_dir(path).map_or(false, |mut rd| rd.next().is_some()))
+        .cloned()
+        .collect()
+}
+
+/// Makes the changes to the file system, returning the result of each edit in the same order.
+/// Copies are made first, from the original names. Renames are done in two steps, via a
+/// temporary name, so that entries can swap names.
+pub fn apply_edits(edits: &[DirectoryEdit]) -> Vec<io::Result<()>> {
+    let mut results: Vec<Option<io::Result<()>>> = edits.iter().map(|_| None).collect();
+
+    for (i, edit) in edits.iter().enumerate() {
+        if let DirectoryEdit::Copy { ref from, ref to } = *edit {
+            results[i] = Some(copy_recursively(from, to));
+        }
+    }
+
+    let mut moved = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        if let DirectoryEdit::Rename { ref from, .. } = *edit {
+            let temporary = temporary_name(from, i);
+            match fs::rename(from, &temporary) {
+                Ok(_) => moved.push((i, temporary)),
+                Err(e) => results[i] = Some(Err(e))
+            }
+        }
+    }
+    for (i, temporary) in moved {
+        if let DirectoryEdit::Rename { ref from, ref to } = edits[i] {
+            let result = fs::rename(&temporary, to);
+            if result.is_err() {
+                let _ = fs::rename(&temporary, from);
+            }
+            results[i] = Some(result);
+        }
+    }
+
+    for (i, edit) in edits.iter().enumerate() {
+        match *edit {
+            DirectoryEdit::Delete(ref path) => results[i] = Some(delete(path)),
+            DirectoryEdit::MakeDirectory(ref path) => results[i] = Some(fs::create_dir_all(path)),
+            _ => {}
+        }
+    }
+
+    results.into_iter().map(|r| r.unwrap_or(Ok(()))).collect()
+}
+
+/// Copies every path into the directory `dest`, keeping their names. Nothing is copied if
+/// `dest` is inside one of the paths.
+pub fn copy_into(paths: &[PathBuf], dest: &Path) -> Result<Vec<DirectoryEdit>, String> {
+    let edits: Vec<DirectoryEdit> = paths.iter()
+        .filter_map(|p| p.file_name().map(|name| DirectoryEdit::Copy { from: p.clone(), to: dest.join(name) }))
+        .collect();
+    for edit in &edits {
+        if let DirectoryEdit::Copy { ref from, ref to } = *edit {
+            check_copy(from, to)?;
+        }
+    }
+    Ok(edits)
+}
+
+fn temporary_name(path: &Path, n: usize) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.qork-rename-{}", name, n))
+}
+
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", to)));
+    }
+
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn delete(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::{load_to_string, save_from_string, TestDir};
+
+    fn listing_of(dir: &TestDir) -> (DirectoryListing, Vec<String>) {
+        let listing = DirectoryListing::read(&dir.0).unwrap();
+        let lines = listing.render();
+        (listing, lines)
+    }
+
+    fn rename_line(line: &str, from: &str, to: &str) -> String {
+        format!("{}{}", &line[..line.len() - from.len()], to)
+    }
+
+    fn set_up() -> TestDir {
+        let dir = TestDir::new();
+        save_from_string(&dir.join("a.txt"), "a").unwrap();
+        save_from_string(&dir.join("b.txt"), "b").unwrap();
+        ::std::fs::create_dir(dir.join("sub")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn render_lists_parent_then_directories_then_files() {
+        let dir = set_up();
+        let (listing, lines) = listing_of(&dir);
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].ends_with(" .."));
+        assert!(lines[2].starts_with("  d"));
+        assert!(lines[2].ends_with(" sub/"));
+        assert!(lines[4].ends_with(" b.txt"));
+        assert_eq!(listing.path_at_line(3), Some(dir.join("a.txt")));
+        assert_eq!(listing.path_at_line(0), None);
+    }
+
+    #[test]
+    fn parse_edits_for_unchanged_listing_returns_no_edits() {
+        let dir = set_up();
+        let (listing, lines) = listing_of(&dir);
+        assert_eq!(listing.parse_edits(&lines), Ok(vec![]));
+    }
+
+    #[test]
+    fn apply_edits_renames_copies_deletes_and_makes_directories() {
+        let dir = set_up();
+        let (listing, mut lines) = listing_of(&dir);
+        lines[2] = rename_line(&lines[2], "sub/", "dir/");
+        lines[3] = format!("C{}", &rename_line(&lines[3], "a.txt", "c.txt")[1..]);
+        lines[4] = format!("D{}", &lines[4][1..]);
+        lines.push(String::from("+ new/"));
+
+        let edits = listing.parse_edits(&lines).unwrap();
+        assert_eq!(edits.len(), 4);
+        assert!(apply_edits(&edits).iter().all(|r| r.is_ok()));
+
+        let (listing, _) = listing_of(&dir);
+        let names: Vec<&str> = listing.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["..", "dir", "new", "a.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn apply_edits_can_swap_names() {
+        let dir = set_up();
+        let (listing, mut lines) = listing_of(&dir);
+        lines[3] = rename_line(&lines[3], "a.txt", "b.txt");
+        lines[4] = rename_line(&lines[4], "b.txt", "a.txt");
+
+        let edits = listing.parse_edits(&lines).unwrap();
+        assert!(apply_edits(&edits).iter().all(|r| r.is_ok()));
+        assert_eq!(load_to_string(&dir.join("a.txt")).unwrap(), "b");
+        assert_eq!(dir.file_count(), 3);
+    }
+
+    #[test]
+    fn parse_edits_for_invalid_edits_returns_error() {
+        let dir = set_up();
+        let (listing, lines) = listing_of(&dir);
+
+        let mut clash = lines.clone();
+        clash[3] = rename_line(&clash[3], "a.txt", "b.txt");
+        assert!(listing.parse_edits(&clash).is_err());
+
+        let mut removed = lines.clone();
+        removed.pop();
+        assert!(listing.parse_edits(&removed).is_err());
+
+        let mut size_changed = lines.clone();
+        size_changed[3] = size_changed[3].replacen(" 1 ", " 2 ", 1);
+        assert!(listing.parse_edits(&size_changed).is_err());
+
+        for name in &["../a.txt", "/tmp/a.txt", "sub/a.txt", "."] {
+            let mut moved = lines.clone();
+            moved[3] = rename_line(&moved[3], "a.txt", name);
+            assert!(listing.parse_edits(&moved).is_err(), "{}", name);
+        }
+
+        let mut made = lines.clone();
+        made.push(String::from("+ ../escaped"));
+        assert!(listing.parse_edits(&made).is_err());
+    }
+
+    #[test]
+    fn non_empty_directories_finds_deletions_which_need_confirming() {
+        let dir = set_up();
+        ::std::fs::create_dir(dir.join("empty")).unwrap();
+        save_from_string(&dir.join("sub/c.txt"), "c").unwrap();
+        let edits = vec![
+            DirectoryEdit::Delete(dir.join("a.txt")),
+            DirectoryEdit::Delete(dir.join("empty")),
+            DirectoryEdit::Delete(dir.join("sub"))
+        ];
+        assert_eq!(non_empty_directories(&edits), vec![dir.join("sub")]);
+    }
+
+    #[test]
+    fn copy_into_refuses_to_copy_a_directory_into_itself() {
+        let dir = set_up();
+        ::std::fs::create_dir(dir.join("sub/inner")).unwrap();
+        let marked = vec![dir.join("a.txt"), dir.join("sub")];
+
+        assert!(copy_into(&marked, &dir.join("sub")).is_err());
+        assert!(copy_into(&marked, &dir.join("sub/inner")).is_err());
+        assert!(copy_into(&marked, &dir.join("sub/../sub/inner")).is_err());
+        assert_eq!(copy_into(&marked[..1], &dir.join("sub")).unwrap().len(), 1);
+        assert!(check_copy(&dir.join("sub"), &dir.join("sub2")).is_ok());
+        assert_eq!(dir.file_count(), 3);
+    }
+
+    #[test]
+    fn flag_matching_sets_flags_on_matching_entries() {
+        let dir = set_up();
+        let (listing, lines) = listing_of(&dir);
+        let lines = listing.flag_matching(&lines, &Regex::new("\\.txt$").unwrap(), FLAG_MARK);
+        assert_eq!(listing.flagged(&lines, FLAG_MARK), vec![dir.join("a.txt"), dir.join("b.txt")]);
+        assert!(lines[2].starts_with(' '));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use super::BufferId;
+
+/// Identifies a file independently of how its name was written. Two names refer to the same
+/// file if they have the same canonical path, or if they are the same inode on the same
+/// device, which catches hard links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub path: PathBuf,
+    /// The (device, inode) of the file, if it exists.
+    pub inode: Option<(u64, u64)>
+}
+
+impl FileIdentity {
+    pub fn of<P: AsRef<Path>>(filename: P) -> FileIdentity {
+        let filename = filename.as_ref();
+        FileIdentity {
+            path: canonical_path(filename),
+            inode: fs::metadata(filename).ok().map(|m| (m.dev(), m.ino()))
+        }
+    }
+
+    pub fn is_same_file(&self, other: &FileIdentity) -> bool {
+        self.path == other.path || (self.inode.is_some() && self.inode == other.inode)
+    }
+}
+
+/// Makes `filename` absolute and resolves symlinks, ".." and ".". Unlike `fs::canonicalize`
+/// this works for files which do not exist yet, by canonicalizing the nearest ancestor that
+/// does exist and appending the rest of the path to it.
+pub fn canonical_path(filename: &Path) -> PathBuf {
+    let absolute = if filename.is_absolute() {
+        PathBuf::from(filename)
+    } else {
+        env::current_dir().map(|d| d.join(filename)).unwrap_or_else(|_| PathBuf::from(filename))
+    };
+
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(p) = fs::canonicalize(existing) {
+            return missing.iter().rev().fold(p, |p, c| append_component(p, c));
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(c)) => {
+                missing.push(c);
+                existing = parent;
+            }
+            _ => return missing.iter().rev().fold(PathBuf::from(existing), |p, c| append_component(p, c))
+        }
+    }
+}
+
+fn append_component(mut path: PathBuf, component: &Component) -> PathBuf {
+    match *component {
+        Component::CurDir => {},
+        Component::ParentDir => { path.pop(); },
+        ref c => path.push(c.as_os_str())
+    }
+    path
+}
+
+/// Maps files to the buffers that are open on them, by both canonical path and inode, so that
+/// finding the buffer for a filename does not need a scan of every buffer.
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    by_path: HashMap<PathBuf, BufferId>,
+    by_inode: HashMap<(u64, u64), BufferId>,
+    by_buffer: HashMap<BufferId, FileIdentity>
+}
+
+impl FileIndex {
+    pub fn new() -> FileIndex {
+        FileIndex::default()
+    }
+
+    /// Records that `buffer_id` is open on `filename`, replacing anything previously recorded
+    /// for the buffer. This should be called again whenever the file might have been replaced,
+    /// because that changes its inode.
+    pub fn insert<P: AsRef<Path>>(&mut self, buffer_id: BufferId, filename: P) {
+        self.remove(buffer_id);
+
+        let identity = FileIdentity::of(filename);
+        self.by_path.insert(identity.path.clone(), buffer_id);
+        if let Some(inode) = identity.inode {
+            self.by_inode.insert(inode, buffer_id);
+        }
+        self.by_buffer.insert(buffer_id, identity);
+    }
+
+    pub fn remove(&mut self, buffer_id: BufferId) {
+        if let Some(identity) = self.by_buffer.remove(&buffer_id) {
+            if self.by_path.get(&identity.path) == Some(&buffer_id) {
+                self.by_path.remove(&identity.path);
+            }
+            if let Some(inode) = identity.inode {
+                if self.by_inode.get(&inode) == Some(&buffer_id) {
+                    self.by_inode.remove(&inode);
+                }
+            }
+        }
+    }
+
+    pub fn identity(&self, buffer_id: BufferId) -> Option<&FileIdentity> {
+        self.by_buffer.get(&buffer_id)
+    }
+
+    pub fn find<P: AsRef<Path>>(&self, filename: P) -> Option<BufferId> {
+        let identity = FileIdentity::of(filename);
+        identity.inode.and_then(|inode| self.by_inode.get(&inode))
+            .or_else(|| self.by_path.get(&identity.path))
+            .cloned()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use fs::{save_from_string, TestDir};
+
+    #[test]
+    fn canonical_path_for_missing_file_resolves_existing_ancestors() {
+        let dir = TestDir::new();
+        let canonical_dir = fs::canonicalize(dir.join("")).unwrap();
+        assert_eq!(canonical_path(&dir.join("x/../y/./a.txt")), canonical_dir.join("y/a.txt"));
+    }
+
+    #[test]
+    fn find_matches_symlinks_and_hard_links() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        save_from_string(&filename, "hello").unwrap();
+        symlink(&filename, dir.join("link.txt")).unwrap();
+        fs::hard_link(&filename, dir.join("hard.txt")).unwrap();
+
+        let mut index = FileIndex::new();
+        index.insert(1, &filename);
+        assert_eq!(index.find(dir.join("./a.txt")), Some(1));
+        assert_eq!(index.find(dir.join("link.txt")), Some(1));
+        assert_eq!(index.find(dir.join("hard.txt")), Some(1));
+        assert_eq!(index.find(dir.join("b.txt")), None);
+
+        index.remove(1);
+        assert_eq!(index.find(&filename), None);
+    }
+}
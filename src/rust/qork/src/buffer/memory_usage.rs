@@ -0,0 +1,78 @@
+use std::ops::{Add, AddAssign};
+
+/// The approximate number of bytes of memory used by a buffer, or by several buffers when
+/// added together. Only the large, variable parts are counted, and allocator overhead is
+/// ignored, so this is an estimate that is good enough to decide what to evict.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MemoryUsage {
+    /// The text, including the overhead of the rope's tree of nodes.
+    pub text: usize,
+    /// The record of edits, used to keep views up to date.
+    pub history: usize,
+    /// State derived from the buffer and kept elsewhere, such as the folds of its views.
+    pub caches: usize
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.text + self.history + self.caches
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = MemoryUsage;
+
+    fn add(self, other: MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            text: self.text + other.text,
+            history: self.history + other.history,
+            caches: self.caches + other.caches
+        }
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: MemoryUsage) {
+        *self = *self + other;
+    }
+}
+
+/// Formats a number of bytes for people, such as "12.3 KiB".
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&'static str; 4] = ["bytes", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_sums_each_part() {
+        let a = MemoryUsage { text: 10, history: 2, caches: 1 };
+        let mut b = MemoryUsage { text: 5, history: 0, caches: 3 };
+        b += a;
+        assert_eq!(b, MemoryUsage { text: 15, history: 2, caches: 4 });
+        assert_eq!(b.total(), 21);
+    }
+
+    #[test]
+    fn format_bytes_uses_largest_whole_unit() {
+        assert_eq!(format_bytes(1000), "1000 bytes");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
@@ -1,16 +1,54 @@
 use std::path::PathBuf;
 use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
 use time::{Tm, now_utc};
 use xi_rope::Rope;
 
+use fs::{self, Codec, FileStamp};
+
 mod buffer_collection;
+mod buffer_error;
+mod buffer_list;
 mod buffer_factory;
+mod directory_listing;
+mod file_index;
+mod memory_usage;
+mod title;
 
-pub use buffer::buffer_collection::BufferCollection;
+pub use buffer::buffer_collection::{BufferCollection, DiskChange};
+pub use buffer::buffer_error::BufferError;
+pub use buffer::buffer_list::{BufferFilter, BufferList, BufferListSort, BUFFER_LIST_TITLE};
 pub use buffer::buffer_factory::BufferFactory;
+pub use buffer::memory_usage::{format_bytes, MemoryUsage};
+pub use buffer::directory_listing::{apply_edits, copy_into, non_empty_directories, DirectoryEdit, DirectoryListing, FLAG_COPY, FLAG_DELETE, FLAG_MARK};
+pub use buffer::title::TitleStyle;
 
 pub type BufferId = i64;
 
+/// The size of the leaves of a rope, and the approximate overhead of each of its nodes. Used
+/// to estimate the memory used by a buffer's text.
+const ROPE_LEAF_BYTES: usize = 1024;
+const ROPE_NODE_OVERHEAD: usize = 64;
+
+/// The most edits a buffer remembers. When there are more, the oldest half are forgotten, and
+/// anything which has not caught up with them since must recompute its state from the text.
+const MAX_EDITS: usize = 1000;
+
+/// Describes an edit in terms of the lines it affected: starting at `line`, `lines_removed`
+/// line breaks were removed and `lines_added` were inserted. `at_line_start` is true if the
+/// edit starts at the very start of `line`, so that text inserted there goes before the line
+/// rather than into it. Views use these to keep line-based state, such as folds, correct as
+/// the text changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineEdit {
+    pub line: usize,
+    pub lines_removed: usize,
+    pub lines_added: usize,
+    pub at_line_start: bool
+}
+
 /// A `Buffer` represents the in-process data structures of an open file. This includes the buffer
 /// contents and certain tracking information to support editing operations. It does not include
 /// things to do with display: a file can be opened in a `Buffer` without being currently displayed,
@@ -31,6 +69,42 @@ pub struct Buffer {
     /// Whether the buffer is changed.
     is_changed: bool,
 
+    /// The state of the file when the buffer was loaded or last saved, or None if the file
+    /// did not exist. Used to detect changes made to the file by other programs.
+    disk_stamp: Option<FileStamp>,
+
+    /// The state of the file (None if it had been deleted) when we last reported that it had
+    /// been changed by another program, so that each change is only reported once.
+    reported_stamp: Option<Option<FileStamp>>,
+
+    /// Whether a backup of the file has been made this session. Backups are only made before
+    /// the first save overwrites the file.
+    is_backed_up: bool,
+
+    /// How the file is decoded when loaded and encoded when saved, for example gzip. None
+    /// for plain files.
+    codec: Option<Rc<dyn Codec>>,
+
+    /// Whether the text has been dropped to save memory. An evicted buffer has no unsaved
+    /// changes, so its text is reloaded from its file when it is next needed.
+    is_evicted: bool,
+
+    /// Whether the user is prevented from editing the buffer. Special buffers such as
+    /// *messages* are read-only, though Qork itself can still update them.
+    is_read_only: bool,
+
+    /// The byte offset of the insertion point within `data`.
+    point: usize,
+
+    /// If the buffer is narrowed, the byte range of `data` which is accessible. Search, replace
+    /// and motion commands only operate within this range.
+    narrowing: Option<Range<usize>>,
+
+    /// The most recent edits made to the buffer, in order, starting with the edit which
+    /// made revision `first_revision` + 1.
+    edits: Vec<LineEdit>,
+    first_revision: usize,
+
     /// The time that the buffer was created. This is NOT the same as the file creation
     /// time (indeed, there might not even be a file).
     created_time_utc: Tm,
@@ -47,6 +121,169 @@ impl Buffer {
         &self.filename
     }
 
+    /// How the file is stored, such as gzip compressed, or None if it is plain text.
+    pub fn codec(&self) -> Option<&dyn Codec> {
+        self.codec.as_ref().map(|c| &**c)
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn point(&self) -> usize {
+        self.point
+    }
+
+    /// Moves the point, clamping it to the accessible part of the buffer.
+    pub fn set_point(&mut self, offset: usize) {
+        let accessible = self.accessible_range();
+        self.point = offset.max(accessible.start).min(accessible.end);
+    }
+
+    /// Returns the byte range of the buffer which is accessible, which is the whole buffer
+    /// unless it has been narrowed.
+    pub fn accessible_range(&self) -> Range<usize> {
+        match self.narrowing {
+            Some(ref r) => r.clone(),
+            None => 0..self.data.len()
+        }
+    }
+
+    /// Returns the 0-based lines which are at least partly accessible.
+    pub fn accessible_lines(&self) -> Range<usize> {
+        let r = self.accessible_range();
+        let last = self.data.line_of_offset(r.end);
+        let end = if r.end == self.data.offset_of_line(last) && r.end > r.start { last } else { last + 1 };
+        self.data.line_of_offset(r.start)..end
+    }
+
+    pub fn is_narrowed(&self) -> bool {
+        self.narrowing.is_some()
+    }
+
+    /// Restricts the accessible part of the buffer to `range`.
+    pub fn narrow(&mut self, range: Range<usize>) {
+        let len = self.data.len();
+        let start = range.start.min(len);
+        self.narrowing = Some(start..range.end.max(start).min(len));
+        let point = self.point;
+        self.set_point(point);
+    }
+
+    /// Makes the whole buffer accessible again.
+    pub fn widen(&mut self) {
+        self.narrowing = None;
+    }
+
+    /// Returns the offset of the start of the 0-based line `line`, or the end of the buffer if
+    /// there are not that many lines.
+    pub fn offset_of_line(&self, line: usize) -> usize {
+        self.data.offset_of_line(line)
+    }
+
+    pub fn move_point_to_start(&mut self) {
+        self.point = self.accessible_range().start;
+    }
+
+    pub fn move_point_to_end(&mut self) {
+        self.point = self.accessible_range().end;
+    }
+
+    /// Moves the point to the start of the 0-based line `line`. If the line is outside the
+    /// accessible part of the buffer the point is moved as close to it as possible.
+    pub fn move_point_to_line(&mut self, line: usize) {
+        let offset = self.data.offset_of_line(line);
+        self.set_point(offset);
+    }
+
+    /// Moves the point to the 0-based byte `column` of the 0-based line `line`. A column beyond
+    /// the end of the line moves the point to the end of the line.
+    pub fn move_point_to_line_and_column(&mut self, line: usize, column: usize) {
+        let start = self.data.offset_of_line(line);
+        let mut end = self.data.offset_of_line(line + 1);
+        if end > start && self.data.byte_at(end - 1) == b'\n' {
+            end -= 1;
+        }
+        let mut offset = (start + column).min(end);
+        // Step back out of the middle of a multi-byte character.
+        while offset > start && offset < end && self.data.byte_at(offset) & 0xC0 == 0x80 {
+            offset -= 1;
+        }
+        self.set_point(offset);
+    }
+
+    /// Replaces all of the text with `lines`, leaving the buffer unchanged. This is for
+    /// buffers whose contents are generated by Qork, such as the buffer list.
+    pub fn set_generated_lines<S: AsRef<str>>(&mut self, lines: &[S]) {
+        self.widen();
+        let len = self.data.len();
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line.as_ref());
+            text.push('\n');
+        }
+        self.edit(0..len, &text);
+        self.point = 0;
+        self.is_changed = false;
+    }
+
+    /// Returns the text of the accessible part of the buffer.
+    pub fn accessible_text(&self) -> String {
+        let r = self.accessible_range();
+        String::from(self.data.clone().slice(r.start, r.end))
+    }
+
+    /// Finds the first occurrence of `needle` in the accessible part of the buffer that starts
+    /// at or after `from`, returning its byte range.
+    pub fn find_forward(&self, needle: &str, from: usize) -> Option<Range<usize>> {
+        let r = self.accessible_range();
+        let from = from.max(r.start);
+        if needle.is_empty() || from > r.end {
+            return None;
+        }
+
+        let text = String::from(self.data.clone().slice(from, r.end));
+        text.find(needle).map(|idx| from + idx..from + idx + needle.len())
+    }
+
+    /// Finds the last occurrence of `needle` in the accessible part of the buffer that ends at
+    /// or before `from`, returning its byte range.
+    pub fn find_backward(&self, needle: &str, from: usize) -> Option<Range<usize>> {
+        let r = self.accessible_range();
+        let from = from.min(r.end);
+        if needle.is_empty() || from < r.start {
+            return None;
+        }
+
+        let text = String::from(self.data.clone().slice(r.start, from));
+        text.rfind(needle).map(|idx| r.start + idx..r.start + idx + needle.len())
+    }
+
+    /// Replaces every occurrence of `needle` in the accessible part of the buffer with
+    /// `replacement`, returning the number of replacements made.
+    pub fn replace_all(&mut self, needle: &str, replacement: &str) -> usize {
+        if needle.is_empty() {
+            return 0;
+        }
+
+        let start = self.accessible_range().start;
+        let text = self.accessible_text();
+        let matches: Vec<_> = text.match_indices(needle).map(|(idx, _)| start + idx).collect();
+
+        // Work backwards so that the offsets of earlier matches are not disturbed.
+        for &offset in matches.iter().rev() {
+            self.replace(offset..offset + needle.len(), replacement);
+        }
+
+        matches.len()
+    }
+
+    /// Returns the 0-based line and column (in bytes) of `offset`.
+    pub fn line_and_column(&self, offset: usize) -> (usize, usize) {
+        let line = self.data.line_of_offset(offset);
+        (line, offset - self.data.offset_of_line(line))
+    }
+
     pub fn set_changed(&mut self) {
         self.is_changed = true;
         self.last_changed_time_utc = now_utc();
@@ -60,6 +297,55 @@ impl Buffer {
         self.is_changed
     }
 
+    pub fn is_evicted(&self) -> bool {
+        self.is_evicted
+    }
+
+    /// Drops the text to save memory, along with the edit history, which is not needed to
+    /// reload the text. Only buffers with no unsaved changes should be evicted.
+    fn evict(&mut self) {
+        self.data = Rope::from("");
+        self.is_evicted = true;
+        let count = self.edits.len();
+        self.forget_edits(count);
+        self.edits.shrink_to_fit();
+    }
+
+    /// Reloads the text of an evicted buffer from its file. Does nothing if the buffer was
+    /// not evicted.
+    pub fn ensure_loaded(&mut self) -> Result<(), BufferError> {
+        if !self.is_evicted {
+            return Ok(());
+        }
+
+        let filename = self.filename.clone().ok_or(BufferError::NoFilename(self.id))?;
+        let contents = fs::load_with_codec(&filename, self.codec())
+            .map_err(|message| BufferError::Io { filename, message })?;
+        self.data = Rope::from(contents);
+        self.is_evicted = false;
+        Ok(())
+    }
+
+    /// Estimates the memory used by the buffer. The caches are not known to the buffer, so
+    /// they are always 0.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let len = self.data.len();
+        let nodes = (len + ROPE_LEAF_BYTES - 1) / ROPE_LEAF_BYTES;
+        MemoryUsage {
+            text: len + nodes * ROPE_NODE_OVERHEAD,
+            history: self.edits.capacity() * mem::size_of::<LineEdit>(),
+            caches: 0
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.is_read_only = read_only;
+    }
+
     pub fn created_time_utc(&self) -> &Tm {
         &self.created_time_utc
     }
@@ -71,6 +357,138 @@ impl Buffer {
     pub fn last_changed_time_utc(&self) -> &Tm {
         &self.last_changed_time_utc
     }
+
+    /// Returns a number which increases every time the buffer is edited.
+    pub fn revision(&self) -> usize {
+        self.first_revision + self.edits.len()
+    }
+
+    /// Returns the edits made since `revision`, oldest first, or None if some of them have
+    /// been forgotten.
+    pub fn edits_since(&self, revision: usize) -> Option<&[LineEdit]> {
+        if revision < self.first_revision {
+            return None;
+        }
+        Some(&self.edits[(revision - self.first_revision).min(self.edits.len())..])
+    }
+
+    /// Forgets the oldest `count` edits, keeping the revision.
+    fn forget_edits(&mut self, count: usize) {
+        let count = count.min(self.edits.len());
+        self.edits.drain(..count);
+        self.first_revision += count;
+    }
+
+    /// Returns the entire contents of the buffer.
+    pub fn text(&self) -> String {
+        String::from(&self.data)
+    }
+
+    /// Returns the lines of the buffer, without their line terminators.
+    pub fn lines(&self) -> Vec<String> {
+        self.data.lines().map(|l| l.into_owned()).collect()
+    }
+
+    /// Replaces the lines in the half-open range `lines` with `new_lines`, and marks the buffer
+    /// as changed. The line terminator of the last replaced line, if there was one, is kept.
+    /// Returns false, and does nothing, if the lines are not all accessible.
+    pub fn replace_lines<S: AsRef<str>>(&mut self, lines: Range<usize>, new_lines: &[S]) -> bool {
+        let start = self.data.offset_of_line(lines.start);
+        let end = self.data.offset_of_line(lines.end);
+        let had_terminator = end > start && self.data.byte_at(end - 1) == b'\n';
+
+        let mut text = new_lines.iter().map(|l| l.as_ref()).collect::<Vec<_>>().join("\n");
+        if had_terminator {
+            text.push('\n');
+        }
+
+        self.replace(start..end, &text)
+    }
+
+    /// Replaces the bytes in `range` with `text` and marks the buffer as changed. Returns
+    /// false, and does nothing, if the range is not all accessible.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) -> bool {
+        let accessible = self.accessible_range();
+        if range.start < accessible.start || range.end > accessible.end {
+            warn!("Buffer {} is narrowed, and bytes {} to {} are outside the narrowed range", self.id, range.start, range.end);
+            return false;
+        }
+
+        let is_edited = self.edit(range, text);
+        if is_edited {
+            self.set_changed();
+        }
+        is_edited
+    }
+
+    /// Replaces the bytes in `range` with `text`, keeping the point and the narrowed range
+    /// pointing at the same text. An edit at the very end of the narrowed range extends it,
+    /// so that text appended to a narrowed buffer remains visible. Returns whether the edit
+    /// was made.
+    fn edit(&mut self, range: Range<usize>, text: &str) -> bool {
+        // Editing the empty text of an evicted buffer would lose the edit when it is reloaded.
+        if let Err(e) = self.ensure_loaded() {
+            warn!("Buffer {} cannot be edited until it is reloaded: {}", self.id, e);
+            return false;
+        }
+
+        if self.edits.len() >= MAX_EDITS {
+            self.forget_edits(MAX_EDITS / 2);
+        }
+        let line = self.data.line_of_offset(range.start);
+        self.edits.push(LineEdit {
+            line,
+            lines_removed: self.data.line_of_offset(range.end) - line,
+            lines_added: text.matches('\n').count(),
+            at_line_start: range.start == self.data.offset_of_line(line)
+        });
+
+        self.data.edit_str(range.start, range.end, text);
+
+        self.point = adjust_offset_for_edit(self.point, &range, text.len(), true);
+        if let Some(ref mut r) = self.narrowing {
+            let start = adjust_offset_for_edit(r.start, &range, text.len(), false);
+            let end = adjust_offset_for_edit(r.end, &range, text.len(), true);
+            *r = start..end;
+        }
+        true
+    }
+
+    /// Appends `lines` to the end of the buffer, then discards lines from the beginning so
+    /// that no more than `max_lines` remain. This is intended for log-like special buffers
+    /// and so does not mark the buffer as changed.
+    pub fn append_lines_capped<S: AsRef<str>>(&mut self, lines: &[S], max_lines: usize) {
+        for line in lines {
+            let len = self.data.len();
+            self.edit(len..len, &(String::from(line.as_ref()) + "\n"));
+        }
+
+        // Every line is terminated, so the number of lines is the same as the number of newlines.
+        let num_lines = self.data.line_of_offset(self.data.len());
+        if num_lines > max_lines {
+            let end = self.data.offset_of_line(num_lines - max_lines);
+            self.edit(0..end, "");
+        }
+
+        self.move_point_to_end();
+    }
+}
+
+/// Works out where `offset` ends up after the bytes in `range` are replaced by `new_len` bytes.
+/// Offsets before the edit are unchanged and offsets after it are shifted. Offsets inside the
+/// replaced text move to its start, or to its end if `stick_to_end` is true; the same applies
+/// to an offset at exactly the position of a pure insertion.
+fn adjust_offset_for_edit(offset: usize, range: &Range<usize>, new_len: usize, stick_to_end: bool) -> usize {
+    let is_insertion = range.start == range.end;
+    if offset < range.start || (offset == range.start && !(stick_to_end && is_insertion)) {
+        offset
+    } else if offset >= range.end {
+        offset - (range.end - range.start) + new_len
+    } else if stick_to_end {
+        range.start + new_len
+    } else {
+        range.start
+    }
 }
 
 impl PartialEq for Buffer {
@@ -105,4 +523,160 @@ mod buffer_tests {
         assert!(b.is_changed());
         assert!(b.last_changed_time_utc() > b.created_time_utc());
     }
+
+    #[test]
+    fn replace_lines_replaces_only_the_specified_lines() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.data = Rope::from("a\nb\nc\nd\n");
+        b.replace_lines(1..3, &["x y"]);
+        assert_eq!(String::from(&b.data), "a\nx y\nd\n");
+        assert!(b.is_changed());
+    }
+
+    #[test]
+    fn replace_lines_for_last_line_without_terminator_does_not_add_one() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.data = Rope::from("a\nb");
+        b.replace_lines(1..2, &["x", "y"]);
+        assert_eq!(String::from(&b.data), "a\nx\ny");
+    }
+
+    #[test]
+    fn move_point_to_line_and_column_clamps_to_end_of_line() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.data = Rope::from("abc\ndef\n");
+        b.move_point_to_line_and_column(1, 2);
+        assert_eq!(b.point(), 6);
+        b.move_point_to_line_and_column(0, 10);
+        assert_eq!(b.point(), 3);
+    }
+
+    #[test]
+    fn append_lines_capped_discards_oldest_lines() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.append_lines_capped(&["a", "b"], 3);
+        b.append_lines_capped(&["c", "d"], 3);
+        assert_eq!(String::from(&b.data), "b\nc\nd\n");
+        assert!(!b.is_changed());
+    }
+
+    #[test]
+    fn replace_records_line_edit_and_increments_revision() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.data = Rope::from("a\nb\nc\nd\n");
+        let rev = b.revision();
+        b.replace_lines(1..3, &["x"]);
+        assert_eq!(b.revision(), rev + 1);
+        assert_eq!(b.edits_since(rev), Some(&[LineEdit { line: 1, lines_removed: 2, lines_added: 1, at_line_start: true }][..]));
+    }
+
+    #[test]
+    fn edits_are_forgotten_once_there_are_too_many() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        for _ in 0..MAX_EDITS * 3 {
+            b.append_lines_capped(&["a"], 10_000);
+        }
+        assert_eq!(b.revision(), MAX_EDITS * 3);
+        assert!(b.edits.len() <= MAX_EDITS);
+        assert!(b.edits_since(0).is_none());
+        assert_eq!(b.edits_since(b.revision() - 1).map(|e| e.len()), Some(1));
+    }
+
+    fn make_narrowed_buffer() -> Buffer {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.data = Rope::from("foo\nfoo bar foo\nfoo\n");
+        b.narrow(4..16);
+        b
+    }
+
+    #[test]
+    fn narrow_clamps_point_to_accessible_range() {
+        let b = make_narrowed_buffer();
+        assert_eq!(b.point(), 4);
+        assert_eq!(b.accessible_text(), "foo bar foo\n");
+    }
+
+    #[test]
+    fn set_point_for_narrowed_buffer_clamps_to_accessible_range() {
+        let mut b = make_narrowed_buffer();
+        b.set_point(100);
+        assert_eq!(b.point(), 16);
+        b.set_point(0);
+        assert_eq!(b.point(), 4);
+    }
+
+    #[test]
+    fn find_forward_for_narrowed_buffer_only_searches_accessible_range() {
+        let b = make_narrowed_buffer();
+        assert_eq!(b.find_forward("foo", 0), Some(4..7));
+        assert_eq!(b.find_forward("foo", 5), Some(12..15));
+        assert_eq!(b.find_forward("foo", 13), None);
+    }
+
+    #[test]
+    fn find_backward_for_narrowed_buffer_only_searches_accessible_range() {
+        let b = make_narrowed_buffer();
+        assert_eq!(b.find_backward("foo", 100), Some(12..15));
+        assert_eq!(b.find_backward("foo", 12), Some(4..7));
+        assert_eq!(b.find_backward("foo", 6), None);
+    }
+
+    #[test]
+    fn replace_all_for_narrowed_buffer_only_replaces_inside_range_and_tracks_end() {
+        let mut b = make_narrowed_buffer();
+        assert_eq!(b.replace_all("foo", "quux"), 2);
+        assert_eq!(String::from(&b.data), "foo\nquux bar quux\nfoo\n");
+        assert_eq!(b.accessible_text(), "quux bar quux\n");
+    }
+
+    #[test]
+    fn edit_before_narrowed_range_shifts_range() {
+        let mut b = make_narrowed_buffer();
+        b.edit(0..3, "x");
+        assert_eq!(b.accessible_range(), 2..14);
+        assert_eq!(b.accessible_text(), "foo bar foo\n");
+    }
+
+    #[test]
+    fn edit_after_narrowed_range_does_not_change_range() {
+        let mut b = make_narrowed_buffer();
+        b.edit(16..19, "xxxxxx");
+        assert_eq!(b.accessible_range(), 4..16);
+    }
+
+    #[test]
+    fn replace_outside_narrowed_range_is_refused() {
+        let mut b = make_narrowed_buffer();
+        assert!(!b.replace(0..5, "x"));
+        assert!(!b.replace_lines(2..3, &["x"]));
+        assert_eq!(String::from(&b.data), "foo\nfoo bar foo\nfoo\n");
+        assert!(!b.is_changed());
+        assert!(b.replace_lines(1..2, &["x"]));
+        assert_eq!(String::from(&b.data), "foo\nx\nfoo\n");
+    }
+
+    #[test]
+    fn accessible_lines_are_those_at_least_partly_in_the_narrowed_range() {
+        let mut b = make_narrowed_buffer();
+        assert_eq!(b.accessible_lines(), 1..2);
+        b.narrow(5..17);
+        assert_eq!(b.accessible_lines(), 1..3);
+        b.widen();
+        assert_eq!(b.accessible_lines(), 0..3);
+    }
+
+    #[test]
+    fn widen_makes_whole_buffer_accessible() {
+        let mut b = make_narrowed_buffer();
+        b.widen();
+        assert!(!b.is_narrowed());
+        assert_eq!(b.accessible_range(), 0..20);
+    }
 }
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use super::BufferId;
+
+/// How the titles of buffers on files with the same name are made unique.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TitleStyle {
+    /// "mod.rs", "mod.rs 1", "mod.rs 2". A number, once given, is never changed.
+    Numbered,
+    /// "mod.rs<buffer>", "mod.rs<commands>", using as few parent directories as needed.
+    Directories,
+    /// "buffer/mod.rs", "commands/mod.rs", using as few parent directories as needed.
+    Path
+}
+
+impl Default for TitleStyle {
+    fn default() -> TitleStyle {
+        TitleStyle::Directories
+    }
+}
+
+/// Gives each file a title based on its leaf name, adding the fewest parent directories
+/// needed to tell apart files with the same leaf name. Files whose leaf name is unique are
+/// titled with just the leaf name. Titles are returned in the same order as `files`.
+pub fn path_titles(files: &[(BufferId, PathBuf)], style: TitleStyle) -> Vec<(BufferId, String)> {
+    let mut by_leaf: HashMap<String, Vec<&Path>> = HashMap::new();
+    for file in files {
+        by_leaf.entry(leaf_name(&file.1)).or_default().push(&file.1);
+    }
+
+    files.iter()
+        .map(|&(id, ref path)| {
+            let leaf = leaf_name(path);
+            let others: Vec<&Path> = by_leaf[&leaf].iter().filter(|p| **p != path.as_path()).cloned().collect();
+            if others.is_empty() {
+                return (id, leaf);
+            }
+
+            let dirs = parent_names(path);
+            let depth = (1..dirs.len() + 1)
+                .find(|&n| others.iter().all(|o| !parent_names(o).starts_with(&dirs[..n])))
+                .unwrap_or(dirs.len());
+            let mut shown: Vec<&str> = dirs[..depth].iter().map(|d| d.as_str()).collect();
+            if shown.is_empty() {
+                return (id, leaf);
+            }
+            shown.reverse();
+
+            let title = match style {
+                TitleStyle::Path => format!("{}/{}", shown.join("/"), leaf),
+                _ => format!("{}<{}>", leaf, shown.join("/"))
+            };
+            (id, title)
+        })
+        .collect()
+}
+
+fn leaf_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// The names of the directories containing `path`, innermost first. The root is not a name,
+/// so it is left out.
+fn parent_names(path: &Path) -> Vec<String> {
+    path.parent()
+        .map(|p| p.components()
+            .rev()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> Vec<(BufferId, PathBuf)> {
+        paths.iter().enumerate().map(|(i, p)| (i as BufferId, PathBuf::from(p))).collect()
+    }
+
+    fn titles(paths: &[&str], style: TitleStyle) -> Vec<String> {
+        path_titles(&files(paths), style).into_iter().map(|(_, t)| t).collect()
+    }
+
+    #[test]
+    fn path_titles_for_unique_leaf_names_uses_leaf_names() {
+        assert_eq!(titles(&["/a/foo.rs", "/a/bar.rs"], TitleStyle::Directories), vec!["foo.rs", "bar.rs"]);
+    }
+
+    #[test]
+    fn path_titles_uses_smallest_distinguishing_directories() {
+        let paths = ["/q/src/buffer/mod.rs", "/q/src/commands/mod.rs", "/q/src/main.rs"];
+        assert_eq!(titles(&paths, TitleStyle::Directories), vec!["mod.rs<buffer>", "mod.rs<commands>", "main.rs"]);
+        assert_eq!(titles(&paths, TitleStyle::Path), vec!["buffer/mod.rs", "commands/mod.rs", "main.rs"]);
+    }
+
+    #[test]
+    fn path_titles_goes_deeper_when_directory_names_clash() {
+        let paths = ["/a/x/src/lib.rs", "/b/y/src/lib.rs"];
+        assert_eq!(titles(&paths, TitleStyle::Directories), vec!["lib.rs<x/src>", "lib.rs<y/src>"]);
+    }
+
+    #[test]
+    fn path_titles_does_not_show_the_root_directory() {
+        let paths = ["/a/mod.rs", "/b/a/mod.rs", "/mod.rs"];
+        assert_eq!(titles(&paths, TitleStyle::Directories), vec!["mod.rs<a>", "mod.rs<b/a>", "mod.rs"]);
+        assert_eq!(titles(&paths, TitleStyle::Path), vec!["a/mod.rs", "b/a/mod.rs", "mod.rs"]);
+    }
+}
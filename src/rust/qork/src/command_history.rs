@@ -0,0 +1,209 @@
+use std::path::Path;
+
+use mru_list::MRUList;
+use regex::Regex;
+
+/// The command lines the user has entered, most recent first, kept between sessions. A line
+/// which is entered again moves to the front rather than appearing twice. Lines matching one
+/// of the exclusion patterns, for example commands which contain passwords, are never kept.
+///
+/// The history also has a cursor, which recalls older and newer lines one at a time and is
+/// moved by reverse incremental search. It is reset whenever a line is recorded.
+pub struct CommandHistory {
+    entries: MRUList,
+    exclude: Vec<Regex>,
+    /// The index of the recalled line, 0 being the most recent, or None if nothing has been
+    /// recalled since the last line was recorded.
+    cursor: Option<usize>,
+    /// The text of the last search, so that repeating it finds older matches.
+    last_search: Option<String>
+}
+
+impl CommandHistory {
+    pub fn new(max_items: usize, exclude: Vec<Regex>) -> CommandHistory {
+        CommandHistory::from_entries(MRUList::new(max_items), exclude)
+    }
+
+    /// Makes a history from previously saved lines, dropping any which are now excluded.
+    pub fn from_entries(mut entries: MRUList, exclude: Vec<Regex>) -> CommandHistory {
+        let excluded: Vec<String> = entries.iter()
+            .filter(|line| exclude.iter().any(|re| re.is_match(line)))
+            .cloned()
+            .collect();
+        for line in excluded {
+            entries.remove(&line);
+        }
+
+        CommandHistory { entries, exclude, cursor: None, last_search: None }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.entries.is_changed()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The line at `index`, 0 being the most recent.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        if index < self.entries.len() { Some(&self.entries[index]) } else { None }
+    }
+
+    /// The lines, most recent first.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter()
+    }
+
+    pub fn is_excluded(&self, line: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(line))
+    }
+
+    /// Adds a line to the front of the history, unless it is blank or excluded, and resets
+    /// the cursor. Returns whether the line was kept.
+    pub fn record(&mut self, line: &str) -> bool {
+        self.reset();
+        let line = line.trim();
+        if line.is_empty() || self.is_excluded(line) {
+            return false;
+        }
+        self.entries.insert(line);
+        true
+    }
+
+    /// Forgets the recalled line and any search in progress.
+    pub fn reset(&mut self) {
+        self.cursor = None;
+        self.last_search = None;
+    }
+
+    /// The recalled line and its index, if there is one.
+    pub fn recalled(&self) -> Option<(usize, &str)> {
+        let index = self.cursor?;
+        self.get(index).map(|line| (index, line))
+    }
+
+    /// The text of the search in progress, if there is one.
+    pub fn last_search(&self) -> Option<&str> {
+        self.last_search.as_ref().map(|s| s.as_str())
+    }
+
+    /// Recalls the next older line, like pressing up. Stays on the oldest line once it is
+    /// reached.
+    pub fn previous(&mut self) -> Option<(usize, &str)> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let index = match self.cursor {
+            Some(i) => (i + 1).min(self.entries.len() - 1),
+            None => 0
+        };
+        self.cursor = Some(index);
+        self.recalled()
+    }
+
+    /// Recalls the next newer line, like pressing down. Moving past the most recent line
+    /// leaves nothing recalled, as at a fresh prompt.
+    pub fn next(&mut self) -> Option<(usize, &str)> {
+        self.cursor = match self.cursor {
+            Some(i) if i > 0 => Some(i - 1),
+            _ => None
+        };
+        self.recalled()
+    }
+
+    /// Searches back through the history for a line containing `query`, like Ctrl-R in a
+    /// shell. Searching again for the same text finds the next older match, while extending
+    /// the text keeps the current match if it still matches. If there is no match the cursor
+    /// does not move.
+    pub fn search(&mut self, query: &str) -> Option<(usize, &str)> {
+        let start = match (self.cursor, self.last_search.as_ref()) {
+            (Some(i), Some(last)) if last == query => i + 1,
+            (Some(i), Some(last)) if query.starts_with(last.as_str()) => i,
+            _ => 0
+        };
+        self.last_search = Some(String::from(query));
+
+        let found = self.entries.iter().enumerate().skip(start)
+            .find(|&(_, line)| line.contains(query))
+            .map(|(i, _)| i);
+        if found.is_some() {
+            self.cursor = found;
+        }
+        found.and_then(move |i| self.get(i).map(|line| (i, line)))
+    }
+
+    pub fn load<P: AsRef<Path>>(max_items: usize, exclude: Vec<Regex>, filename: P) -> Result<CommandHistory, String> {
+        MRUList::load(max_items, filename).map(|entries| CommandHistory::from_entries(entries, exclude))
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize, String> {
+        self.entries.save(filename)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn history(lines: &[&str]) -> CommandHistory {
+        let mut h = CommandHistory::new(10, vec![Regex::new("^secret ").unwrap()]);
+        for line in lines {
+            h.record(line);
+        }
+        h
+    }
+
+    #[test]
+    fn record_removes_duplicates_and_skips_blank_and_excluded_lines() {
+        let h = history(&["o a.txt", "s 1", "  ", "secret hunter2", "o a.txt  "]);
+        assert_eq!(h.iter().cloned().collect::<Vec<_>>(), vec!["o a.txt", "s 1"]);
+    }
+
+    #[test]
+    fn previous_and_next_move_through_history() {
+        let mut h = history(&["a", "b", "c"]);
+        assert_eq!(h.next(), None);
+        assert_eq!(h.previous(), Some((0, "c")));
+        assert_eq!(h.previous(), Some((1, "b")));
+        assert_eq!(h.previous(), Some((2, "a")));
+        assert_eq!(h.previous(), Some((2, "a")));
+        assert_eq!(h.next(), Some((1, "b")));
+        assert_eq!(h.next(), Some((0, "c")));
+        assert_eq!(h.next(), None);
+
+        h.previous();
+        h.record("d");
+        assert_eq!(h.recalled(), None);
+    }
+
+    #[test]
+    fn search_is_incremental_and_repeating_finds_older_matches() {
+        let mut h = history(&["save 1", "open b", "save 22", "open a"]);
+        assert_eq!(h.search("s"), Some((1, "save 22")));
+        assert_eq!(h.search("sa"), Some((1, "save 22")));
+        assert_eq!(h.search("sa"), Some((3, "save 1")));
+        assert_eq!(h.search("sa"), None);
+        assert_eq!(h.recalled(), Some((3, "save 1")));
+        assert_eq!(h.search("open"), Some((0, "open a")));
+    }
+
+    #[test]
+    fn load_drops_lines_which_are_now_excluded() {
+        let mut h = CommandHistory::new(10, vec![]);
+        h.record("secret x");
+        h.record("o a.txt");
+        let file = NamedTempFile::new().unwrap();
+        h.save(file.path()).unwrap();
+
+        let loaded = CommandHistory::load(10, vec![Regex::new("^secret").unwrap()], file.path()).unwrap();
+        assert_eq!(loaded.iter().cloned().collect::<Vec<_>>(), vec!["o a.txt"]);
+        assert!(loaded.is_changed());
+    }
+}
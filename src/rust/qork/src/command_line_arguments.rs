@@ -1,3 +1,5 @@
+use std::env;
+use std::ffi::OsString;
 use std::fmt;
 use clap::{Arg, App};
 
@@ -11,6 +13,13 @@ pub struct CommandLineArguments {
 
 impl CommandLineArguments {
     pub fn new() -> CommandLineArguments {
+        CommandLineArguments::from_args(env::args_os())
+    }
+
+    /// Parses the given arguments, the first of which is the program name.
+    pub fn from_args<I, T>(args: I) -> CommandLineArguments
+        where I: IntoIterator<Item = T>, T: Into<OsString> + Clone
+    {
         let matches = App::new(::PKG_NAME)
                         .version(::PKG_VERSION)
                         .author(::PKG_AUTHORS)
@@ -28,7 +37,7 @@ impl CommandLineArguments {
                                 .long("no-config")
                                 .help("Prevent the loading of the configuration directory")
                         )
-                        .get_matches();
+                        .get_matches_from(args);
 
         CommandLineArguments {
             // Flip this so that we express what we want to do as a positive boolean (double
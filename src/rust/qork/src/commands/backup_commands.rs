@@ -0,0 +1,59 @@
+use commands::with_current_buffer;
+use context::Context;
+use datetime;
+use fs;
+
+pub fn handle_list_backups(context: &Context) {
+    let backups = context.buffers().backups().clone();
+
+    with_current_buffer(context, |buffer| {
+        let filename = match *buffer.filename() {
+            Some(ref f) => f.clone(),
+            None => { warn!("Buffer {} has no filename, so it has no backups", buffer.id()); return; }
+        };
+
+        let list = backups.list(&filename);
+        info!("There are {} backups of {:?}", list.len(), filename);
+        for (idx, backup) in list.iter().enumerate() {
+            info!("{}: {} {:?}", idx + 1, datetime::format_system_time_as_utc(&backup.modified), backup.path);
+        }
+    });
+}
+
+/// Replaces the contents of the current buffer with the `index`th backup (1-based, newest
+/// first, as listed by `handle_list_backups`). The file itself is not changed until the
+/// buffer is saved.
+pub fn handle_restore_backup(context: &Context, index: usize) {
+    let backups = context.buffers().backups().clone();
+
+    with_current_buffer(context, |buffer| {
+        if buffer.is_read_only() {
+            warn!("Buffer {} is read-only", buffer.id());
+            return;
+        }
+
+        let filename = match *buffer.filename() {
+            Some(ref f) => f.clone(),
+            None => { warn!("Buffer {} has no filename, so it has no backups", buffer.id()); return; }
+        };
+
+        let list = backups.list(&filename);
+        let backup = match index.checked_sub(1).and_then(|i| list.get(i)) {
+            Some(b) => b,
+            None => { warn!("There is no backup number {} of {:?}", index, filename); return; }
+        };
+
+        // Backups are copies of the file as it was on disk, so a compressed file's backups are
+        // compressed too.
+        match fs::load_with_codec(&backup.path, buffer.codec()) {
+            Ok(contents) => {
+                buffer.widen();
+                let len = buffer.text().len();
+                buffer.replace(0..len, &contents);
+                buffer.move_point_to_start();
+                info!("Restored buffer {} from {:?}", buffer.id(), backup.path);
+            }
+            Err(e) => warn!("Could not read backup {:?}: {}", backup.path, e)
+        }
+    });
+}
@@ -0,0 +1,94 @@
+use buffer::{BufferFilter, BufferId, BufferListSort, BUFFER_LIST_TITLE};
+use context::Context;
+use commands::file_commands::{handle_close_buffer, handle_reload_buffer, handle_save_buffer};
+
+/// Something done to every marked buffer in the buffer list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferListAction {
+    Save,
+    Close { force: bool },
+    Revert
+}
+
+/// Shows the buffer list and makes it the current buffer.
+pub fn handle_show_buffer_list(context: &Context) {
+    let id = refresh_buffer_list(context);
+    context.buffers().set_current_buffer(id);
+}
+
+pub fn handle_sort_buffer_list(context: &Context, sort: BufferListSort, reverse: bool) {
+    context.buffer_list().set_sort(sort, reverse);
+    handle_show_buffer_list(context);
+}
+
+/// Adds a filter to the buffer list, or removes all filters if `filter` is None.
+pub fn handle_filter_buffer_list(context: &Context, filter: Option<BufferFilter>) {
+    match filter {
+        Some(f) => context.buffer_list().add_filter(f),
+        None => context.buffer_list().clear_filters()
+    }
+    handle_show_buffer_list(context);
+}
+
+/// Marks or unmarks buffers. Unmarking with no ids unmarks everything.
+pub fn handle_mark_buffers(context: &Context, buffer_ids: Vec<BufferId>, mark: bool) {
+    {
+        let bc = context.buffers();
+        let mut list = context.buffer_list();
+        if !mark && buffer_ids.is_empty() {
+            list.clear_marks();
+        }
+        for id in buffer_ids {
+            match (bc.get(id).is_some(), mark) {
+                (false, _) => warn!("No buffer with an id of {} exists", id),
+                (true, true) => list.mark(id),
+                (true, false) => list.unmark(id)
+            }
+        }
+    }
+    handle_show_buffer_list(context);
+}
+
+/// Saves, closes or reverts every marked buffer, then clears the marks. Each buffer is handled
+/// as if its own command had been given, so failures are reported per buffer and do not stop
+/// the others.
+pub fn handle_buffer_list_action(context: &Context, action: BufferListAction) {
+    let marks = context.buffer_list().marks();
+    if marks.is_empty() {
+        warn!("No buffers are marked");
+        return;
+    }
+
+    for id in marks {
+        match action {
+            BufferListAction::Save => handle_save_buffer(context, id, false),
+            BufferListAction::Close { force } => handle_close_buffer(context, id, force),
+            BufferListAction::Revert => handle_reload_buffer(context, id)
+        }
+    }
+
+    context.buffer_list().clear_marks();
+    handle_show_buffer_list(context);
+}
+
+/// Rewrites the buffer list buffer, creating it if necessary, and returns its id.
+fn refresh_buffer_list(context: &Context) -> BufferId {
+    let mut bc = context.buffers();
+    let lines = {
+        let list = context.buffer_list();
+        list.render(&list.entries(&bc))
+    };
+
+    let id = match bc.find_by_title(BUFFER_LIST_TITLE) {
+        Some(id) => id,
+        None => {
+            let b = context.buffer_factory().new_special_buffer(BUFFER_LIST_TITLE);
+            let id = b.id();
+            bc.insert(b);
+            id
+        }
+    };
+
+    bc[id].borrow_mut().set_generated_lines(&lines);
+    id
+}
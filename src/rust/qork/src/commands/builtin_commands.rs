@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use commands::{handle_command, run_command_line, parse_buffer_list_command, parse_flag_directory_entries,
+               parse_replace_all, parse_workspace_command, Command};
+use commands::command_line::{tokenize, ArgKind, Args, ParseError};
+use commands::completion_commands::handle_complete;
+use commands::fold_commands::FoldAction;
+use commands::help_commands::{handle_help, handle_list_commands};
+use commands::history_commands::*;
+use commands::registry::{ArgSpec, CommandRegistry, CommandSpec};
+
+/// Turns the arguments of a built-in command into a `Command`.
+type Parser = fn(&mut Args) -> Result<Command, ParseError>;
+
+/// A built-in command, which parses its arguments into a `Command` and runs it with
+/// `handle_command`.
+fn builtin(name: &str, alias: &str, doc: &str, args: Vec<ArgSpec>, parse: Parser) -> CommandSpec {
+    CommandSpec::new(name, doc, args, move |context, mut args| {
+        let command = parse(&mut args)?;
+        args.finish()?;
+        Ok(handle_command(context, command))
+    }).alias(alias)
+}
+
+fn id() -> Vec<ArgSpec> {
+    vec![ArgSpec::required("id", ArgKind::BufferId)]
+}
+
+fn line() -> Vec<ArgSpec> {
+    vec![ArgSpec::required("line", ArgKind::Int)]
+}
+
+/// Registers all of Qork's own commands.
+pub fn register_builtin_commands(registry: &mut CommandRegistry) {
+    let commands = vec![
+        builtin("quit", "q", "Quits Qork, first asking about each buffer with unsaved changes.", vec![],
+                |_| Ok(Command::Quit{ force: false })),
+        builtin("force-quit", "q!", "Quits Qork, discarding all unsaved changes.", vec![],
+                |_| Ok(Command::Quit{ force: true })),
+        builtin("save-all", "sA", "Saves every buffer with unsaved changes.", vec![],
+                |_| Ok(Command::SaveAll)),
+        builtin("close-all", "xA", "Closes every buffer without unsaved changes.", vec![],
+                |_| Ok(Command::CloseAll{ force: false })),
+        builtin("force-close-all", "xA!", "Closes every buffer, discarding unsaved changes.", vec![],
+                |_| Ok(Command::CloseAll{ force: true })),
+        builtin("open", "o", "Opens a file or directory, or switches to its buffer if it is already open.",
+                vec![ArgSpec::required("filename", ArgKind::Path)],
+                |a| Ok(Command::OpenFile{ filename: a.path("filename")? })),
+        builtin("save", "s", "Saves a buffer to its file.\nThe file is not overwritten if another program has changed it; use force-save for that.",
+                id(), |a| Ok(Command::SaveBuffer{ buffer_id: a.buffer_id("id")?, force: false })),
+        builtin("force-save", "s!", "Saves a buffer to its file, even if another program has changed the file.",
+                id(), |a| Ok(Command::SaveBuffer{ buffer_id: a.buffer_id("id")?, force: true })),
+        builtin("reload", "R", "Replaces a buffer with the contents of its file, discarding any changes.",
+                id(), |a| Ok(Command::ReloadBuffer{ buffer_id: a.buffer_id("id")? })),
+        builtin("diff", "D", "Shows the differences between a buffer and its file.",
+                id(), |a| Ok(Command::DiffBuffer{ buffer_id: a.buffer_id("id")? })),
+        builtin("save-as", "S", "Saves a buffer under a new filename, which it is then backed by.",
+                vec![ArgSpec::required("id", ArgKind::BufferId), ArgSpec::required("filename", ArgKind::Path)],
+                |a| Ok(Command::SaveBufferAs{ buffer_id: a.buffer_id("id")?, filename: a.path("filename")? })),
+        builtin("close", "x", "Closes a buffer, unless it has unsaved changes.",
+                id(), |a| Ok(Command::CloseBuffer{ buffer_id: a.buffer_id("id")?, force: false })),
+        builtin("force-close", "x!", "Closes a buffer, discarding any unsaved changes.",
+                id(), |a| Ok(Command::CloseBuffer{ buffer_id: a.buffer_id("id")?, force: true })),
+        builtin("list-backups", "bl", "Lists the backups of the current buffer's file.", vec![],
+                |_| Ok(Command::ListBackups)),
+        builtin("restore-backup", "br", "Replaces the current buffer with one of its backups, as numbered by list-backups.",
+                vec![ArgSpec::required("index", ArgKind::Int)],
+                |a| Ok(Command::RestoreBackup{ index: a.int("index")? })),
+        builtin("switch-buffer", "c", "Makes a buffer the current buffer.",
+                id(), |a| Ok(Command::SetCurrentBuffer{ buffer_id: a.buffer_id("id")? })),
+        builtin("previous-buffer", "bb", "Switches back to the buffer that was current before this one.", vec![],
+                |_| Ok(Command::PreviousBuffer)),
+        builtin("older-buffer", "bp", "Cycles to the next older buffer in the history.", vec![],
+                |_| Ok(Command::CycleBuffers{ older: true })),
+        builtin("newer-buffer", "bn", "Cycles to the next newer buffer in the history.", vec![],
+                |_| Ok(Command::CycleBuffers{ older: false })),
+        builtin("buffer-list", "ls", "Shows, sorts, filters and acts on the buffer list.\n\
+                 ls                                  show the buffer list\n\
+                 ls sort id|title|path|size|accessed sort it, reversed if the column is prefixed by '-'\n\
+                 ls filter modified|dir D|ext E|re R add a filter, or with no arguments remove all filters\n\
+                 ls mark ID...  /  ls unmark [ID...]  mark buffers, or unmark them\n\
+                 ls save|close|close!|revert         act on every marked buffer",
+                vec![ArgSpec::optional("subcommand", ArgKind::String), ArgSpec::rest("args", ArgKind::String)],
+                parse_buffer_list_command),
+        builtin("open-entry", "do", "Opens the file or directory on a line of the current directory buffer.",
+                line(), |a| Ok(Command::OpenDirectoryEntry{ line: a.line_number("line")? })),
+        builtin("apply-directory-edits", "da", "Makes the changes described by the edits to the current directory buffer.\n\
+                 Directories which are not empty are only deleted by 'da!'.", vec![],
+                |_| Ok(Command::ApplyDirectoryEdits{ force: false })),
+        builtin("force-apply-directory-edits", "da!", "Makes the changes described by the edits to the current directory buffer, deleting directories with everything in them.",
+                vec![], |_| Ok(Command::ApplyDirectoryEdits{ force: true })),
+        builtin("refresh-directory", "dg", "Lists the current directory again, discarding any edits.", vec![],
+                |_| Ok(Command::RefreshDirectory)),
+        builtin("flag-entries", "dm", "Flags the entries of the current directory buffer whose names match a regular expression.\n\
+                 The flag is '*' to mark, 'D' to delete, 'C' to copy or '-' to clear.",
+                vec![ArgSpec::required("flag", ArgKind::String), ArgSpec::rest("regex", ArgKind::String)],
+                parse_flag_directory_entries),
+        builtin("copy-marked", "dc", "Copies the entries marked '*' in the current directory buffer into a directory.",
+                vec![ArgSpec::required("destination", ArgKind::Path)],
+                |a| Ok(Command::CopyMarkedEntries{ dest: a.path("destination")? })),
+        builtin("workspace", "ws", "Lists, switches to, saves, deletes or leaves workspaces.\n\
+                 ws                   list the workspaces\n\
+                 ws NAME              switch to a workspace\n\
+                 ws save NAME [ROOT]  save the visible buffers as a workspace and make it active\n\
+                 ws rm NAME           delete a workspace\n\
+                 ws -                 leave the active workspace, making all buffers visible",
+                vec![ArgSpec::optional("name", ArgKind::String), ArgSpec::rest("args", ArgKind::String)],
+                parse_workspace_command),
+        builtin("memory", "mem", "Reports the approximate memory used by each buffer and in total.", vec![],
+                |_| Ok(Command::ShowMemoryUsage)),
+        builtin("check-spelling", "sb", "Checks the spelling of the current buffer.", vec![],
+                |_| Ok(Command::CheckSpelling)),
+        builtin("next-misspelling", "sn", "Moves to the next misspelt word.", vec![],
+                |_| Ok(Command::NextMisspelling)),
+        builtin("previous-misspelling", "sp", "Moves to the previous misspelt word.", vec![],
+                |_| Ok(Command::PreviousMisspelling)),
+        builtin("add-word", "sa", "Adds a word to the personal word list.",
+                vec![ArgSpec::required("word", ArgKind::String)],
+                |a| Ok(Command::AddToPersonalWords{ word: a.string("word")? })),
+        builtin("narrow", "n", "Restricts editing of the current buffer to a range of lines.",
+                vec![ArgSpec::required("first line", ArgKind::Int), ArgSpec::required("last line", ArgKind::Int)],
+                |a| Ok(Command::NarrowToLines{ first_line: a.line_number("first line")?, last_line: a.line_number("last line")? })),
+        builtin("widen", "w", "Removes the narrowing of the current buffer.", vec![],
+                |_| Ok(Command::Widen)),
+        builtin("start", "<", "Moves to the start of the current buffer.", vec![],
+                |_| Ok(Command::MoveToStart)),
+        builtin("end", ">", "Moves to the end of the current buffer.", vec![],
+                |_| Ok(Command::MoveToEnd)),
+        builtin("goto-line", "g", "Moves to a line of the current buffer.",
+                line(), |a| Ok(Command::GotoLine{ line: a.line_number("line")? })),
+        builtin("search", "/", "Searches forward for some text.",
+                vec![ArgSpec::rest("needle", ArgKind::String)],
+                |a| Ok(Command::SearchForward{ needle: a.text("needle")? })),
+        builtin("search-backward", "?", "Searches backward for some text.",
+                vec![ArgSpec::rest("needle", ArgKind::String)],
+                |a| Ok(Command::SearchBackward{ needle: a.text("needle")? })),
+        builtin("replace-all", "r", "Replaces all occurrences of FROM with TO, written as FROM/TO.",
+                vec![ArgSpec::rest("from/to", ArgKind::String)],
+                parse_replace_all),
+        builtin("fold-close", "zc", "Collapses the fold starting on a line.",
+                line(), |a| Ok(Command::Fold{ line: a.line_number("line")?, action: FoldAction::Collapse })),
+        builtin("fold-open", "zo", "Expands the fold starting on a line.",
+                line(), |a| Ok(Command::Fold{ line: a.line_number("line")?, action: FoldAction::Expand })),
+        builtin("fold-toggle", "za", "Collapses or expands the fold starting on a line.",
+                line(), |a| Ok(Command::Fold{ line: a.line_number("line")?, action: FoldAction::Toggle })),
+        builtin("fold-all", "zM", "Collapses every fold.", vec![],
+                |_| Ok(Command::FoldAll)),
+        builtin("unfold-all", "zR", "Expands every fold.", vec![],
+                |_| Ok(Command::UnfoldAll)),
+        builtin("fill", "f", "Fills the paragraph containing a line to the fill column.",
+                line(), |a| Ok(Command::FillParagraph{ line: a.line_number("line")? })),
+        builtin("unfill", "u", "Joins the paragraph containing a line into a single line.",
+                line(), |a| Ok(Command::UnfillParagraph{ line: a.line_number("line")? })),
+        CommandSpec::new("help", "Describes a command, or how to get help if no command is given.",
+                         vec![ArgSpec::optional("command", ArgKind::Command)],
+                         |context, mut args| { handle_help(context, args.optional_string()?); Ok(false) }).alias("h"),
+        CommandSpec::new("commands", "Lists all the commands.", vec![],
+                         |context, _| { handle_list_commands(context); Ok(false) }),
+        CommandSpec::new("history", "Lists the most recent command lines, 20 unless a count is given.",
+                         vec![ArgSpec::optional("count", ArgKind::Int)],
+                         |context, mut args| {
+                             let count = if args.is_empty() { 20 } else { args.int("count")? };
+                             handle_show_history(context, count);
+                             Ok(false)
+                         }).alias("hi").not_recorded(),
+        CommandSpec::new("history-previous", "Recalls the next older command line, like pressing up.\n\
+                          Use history-run to run it.", vec![],
+                         |context, _| { handle_history_previous(context); Ok(false) }).alias("hp").not_recorded(),
+        CommandSpec::new("history-next", "Recalls the next newer command line, like pressing down.", vec![],
+                         |context, _| { handle_history_next(context); Ok(false) }).alias("hn").not_recorded(),
+        CommandSpec::new("history-search", "Searches back through the command history for a line containing some text.\n\
+                          Searching again for the same text finds older matches, and with no text repeats the last search.",
+                         vec![ArgSpec::rest("text", ArgKind::String)],
+                         |context, mut args| { handle_history_search(context, args.rest()); Ok(false) }).alias("hs").not_recorded(),
+        CommandSpec::new("history-run", "Runs the recalled command line, or the one numbered by the history command.",
+                         vec![ArgSpec::optional("number", ArgKind::Int)],
+                         |context, mut args| {
+                             let number = if args.is_empty() { None } else { Some(args.int("number")?) };
+                             handle_history_run(context, number)
+                         }).alias("!").not_recorded(),
+        CommandSpec::new("complete", "Lists the completions of the last word of a partly typed command line.\n\
+                          Completes command names, filenames, buffer ids and titles. End with a space to complete a new word.",
+                         vec![ArgSpec::rest("line", ArgKind::String)],
+                         |context, mut args| { handle_complete(context, args.rest_as_typed()); Ok(false) }).alias("tab").not_recorded()
+    ];
+
+    for command in commands {
+        registry.register(command).expect("the built-in commands have unique names");
+    }
+}
+
+/// Registers the commands defined in the configuration, each of which runs a command line
+/// with any arguments it is given appended. The definitions are registered in order of name,
+/// which is how the configuration keeps them, so a command may use the built-in commands and
+/// configured commands whose names sort before its own, but may not replace an existing
+/// command. A command which runs one that is not kept in the command history, such as
+/// history-run, is not kept either.
+pub fn register_configured_commands(registry: &mut CommandRegistry, definitions: &BTreeMap<String, String>) {
+    for (name, expansion) in definitions {
+        if registry.find(name).is_some() {
+            warn!("The configured command {} has the same name as an existing command", name);
+            continue;
+        }
+        let target = match tokenize(expansion) {
+            Ok(ref words) if !words.is_empty() => words[0].clone(),
+            _ => { warn!("The configured command {} has an invalid definition {:?}", name, expansion); continue; }
+        };
+        let is_recorded = match registry.find(&target) {
+            Some(spec) => spec.is_recorded(),
+            None => { warn!("The configured command {} uses the unknown command {}", name, target); continue; }
+        };
+
+        let line = expansion.clone();
+        let spec = CommandSpec::new(name, &format!("Runs `{}`, followed by any arguments.", expansion),
+                                    vec![ArgSpec::rest("args", ArgKind::String)],
+                                    move |context, mut args| {
+            let mut full_line = line.clone();
+            if !args.is_empty() {
+                full_line.push(' ');
+                full_line.push_str(&args.rest());
+            }
+            run_command_line(context, &full_line, false)
+        });
+        let spec = if is_recorded { spec } else { spec.not_recorded() };
+        if let Err(e) = registry.register(spec) {
+            warn!("Could not add the configured command {}: {}", name, e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configured(definitions: &[(&str, &str)]) -> CommandRegistry {
+        let definitions: BTreeMap<String, String> = definitions.iter()
+            .map(|&(name, line)| (String::from(name), String::from(line)))
+            .collect();
+        let mut registry = CommandRegistry::new();
+        register_builtin_commands(&mut registry);
+        register_configured_commands(&mut registry, &definitions);
+        registry
+    }
+
+    #[test]
+    fn register_configured_commands_adds_valid_definitions_in_order_of_name() {
+        let registry = configured(&[
+            ("ff", "ls filter modified"),
+            ("fm", "ff"),
+            ("aa", "zz"),
+            ("zz", "ls"),
+            ("save", "ls"),
+            ("bad", "'unclosed"),
+            ("nothing", "  ")
+        ]);
+
+        assert!(registry.find("ff").unwrap().summary().contains("ls filter modified"));
+        assert!(registry.find("fm").is_some());
+        assert!(registry.find("zz").is_some());
+        // Defined before the command it uses.
+        assert!(registry.find("aa").is_none());
+        // Built-in commands cannot be replaced.
+        assert_eq!(registry.find("save").unwrap().name(), "save");
+        assert!(registry.find("save").unwrap().summary().starts_with("Saves a buffer"));
+        assert!(registry.find("bad").is_none());
+        assert!(registry.find("nothing").is_none());
+    }
+
+    #[test]
+    fn configured_commands_are_recorded_like_the_commands_they_run() {
+        let registry = configured(&[("again", "! 1"), ("keep", "s 1")]);
+        assert!(!registry.find("again").unwrap().is_recorded());
+        assert!(registry.find("keep").unwrap().is_recorded());
+    }
+}
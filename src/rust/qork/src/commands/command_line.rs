@@ -0,0 +1,395 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use buffer::BufferId;
+
+/// The kinds of value a command argument can have, used to check arguments as they are parsed
+/// and to describe them when they are wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A non-negative whole number, such as a count or a line number.
+    Int,
+    /// A file or directory name, which may contain '~' or environment variables.
+    Path,
+    /// The id of a buffer.
+    BufferId,
+    /// The name of a command.
+    Command,
+    /// Any text.
+    String
+}
+
+impl fmt::Display for ArgKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            ArgKind::Int => "a number",
+            ArgKind::Path => "a filename",
+            ArgKind::BufferId => "a buffer id",
+            ArgKind::Command => "a command name",
+            ArgKind::String => "some text"
+        };
+        f.write_str(s)
+    }
+}
+
+/// The ways in which a command line can fail to parse.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A quoted argument has no closing quote.
+    UnterminatedQuote(char),
+    /// The line ends with a backslash, so there is nothing for it to escape.
+    TrailingBackslash,
+    /// The first word of the line is not a command.
+    UnknownCommand(String),
+    /// A required argument was not given.
+    MissingArgument { command: String, name: &'static str, kind: ArgKind },
+    /// An argument is not of the kind the command expects.
+    InvalidArgument { command: String, name: &'static str, kind: ArgKind, value: String },
+    /// More arguments were given than the command takes.
+    TooManyArguments { command: String, extra: Vec<String> },
+    /// The arguments are well-formed but do not make sense to the command, for example an
+    /// unknown sort column or an invalid regular expression.
+    Invalid { command: String, message: String }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnterminatedQuote(quote) => write!(f, "There is no closing {} quote", quote),
+            ParseError::TrailingBackslash => write!(f, "The line ends with a '\\' which does not escape anything"),
+            ParseError::UnknownCommand(ref command) => write!(f, "Unknown command {}", command),
+            ParseError::MissingArgument { ref command, name, kind } =>
+                write!(f, "{}: the {} argument is missing, it should be {}", command, name, kind),
+            ParseError::InvalidArgument { ref command, name, kind, ref value } =>
+                write!(f, "{}: the {} argument should be {}, not {:?}", command, name, kind, value),
+            ParseError::TooManyArguments { ref command, ref extra } =>
+                write!(f, "{}: unexpected arguments {:?}, quote arguments which contain spaces", command, extra),
+            ParseError::Invalid { ref command, ref message } => write!(f, "{}: {}", command, message)
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Splits a command line into words separated by whitespace. Inside single quotes every
+/// character is taken literally; inside double quotes, and outside quotes, a backslash takes
+/// the next character literally, so `"a \"b\""` and `a\ \"b\"` are both `a "b"`. Quotes may
+/// appear in the middle of a word, and `''` is an empty word.
+pub fn tokenize(line: &str) -> Result<Vec<String>, ParseError> {
+    split_words(line, false).map(|(words, _)| words)
+}
+
+/// Splits a command line which is still being typed, for completion. Unlike `tokenize` it
+/// accepts an unfinished quote or escape, and the last word is always the one being typed,
+/// which is empty if the line ends with whitespace. Also returns the byte offset at which
+/// the last word starts, which is where a completion replaces the line.
+pub fn tokenize_partial(line: &str) -> (Vec<String>, usize) {
+    split_words(line, true).expect("partial lines are always split")
+}
+
+fn split_words(line: &str, partial: bool) -> Result<(Vec<String>, usize), ParseError> {
+    let mut words = Vec::new();
+    let mut pos = 0;
+    let mut last_start = line.len();
+    while let Some((word, span)) = split_word(&line[pos..], partial)? {
+        words.push(word);
+        last_start = pos + span.start;
+        pos += span.end;
+    }
+
+    if partial && (words.is_empty() || pos < line.len()) {
+        words.push(String::new());
+        last_start = line.len();
+    }
+    Ok((words, last_start))
+}
+
+/// Reads the first word of `text`, returning it and the byte range it came from, or None if
+/// there are only spaces. See `tokenize` for the quoting rules.
+fn split_word(text: &str, partial: bool) -> Result<Option<(String, Range<usize>)>, ParseError> {
+    let start = match text.find(|c: char| !c.is_whitespace()) {
+        Some(i) => i,
+        None => return Ok(None)
+    };
+
+    let mut word = String::new();
+    let mut chars = text[start..].char_indices().map(|(i, c)| (start + i, c));
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => return Ok(Some((word, start..i))),
+            '\\' => match chars.next() {
+                Some((_, c)) => word.push(c),
+                None if partial => {},
+                None => return Err(ParseError::TrailingBackslash)
+            },
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some((_, c)) => word.push(c),
+                    None if partial => break,
+                    None => return Err(ParseError::UnterminatedQuote('\''))
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => word.push(c),
+                        None if partial => break,
+                        None => return Err(ParseError::UnterminatedQuote('"'))
+                    },
+                    Some((_, c)) => word.push(c),
+                    None if partial => break,
+                    None => return Err(ParseError::UnterminatedQuote('"'))
+                }
+            },
+            c => word.push(c)
+        }
+    }
+    Ok(Some((word, start..text.len())))
+}
+
+/// Splits a command line into its first word, which names the command, and the rest of the
+/// line, or returns None for a blank line.
+pub fn split_command(line: &str) -> Result<Option<(String, &str)>, ParseError> {
+    Ok(split_word(line, false)?.map(|(name, span)| (name, &line[span.end..])))
+}
+
+/// Quotes a word, if necessary, so that `tokenize` reads it back unchanged.
+pub fn quote(word: &str) -> String {
+    let is_plain = !word.is_empty() && word.chars().all(|c| !c.is_whitespace() && !"'\"\\".contains(c));
+    if is_plain {
+        String::from(word)
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// The arguments of a command, which are taken off the front one at a time as values of the
+/// kind the command expects. Any problem is returned as a `ParseError` naming the command
+/// and the argument. Words are only split off as they are taken, so commands which take free
+/// text, such as a search string or a regular expression, can have the rest of the line
+/// exactly as it was typed.
+#[derive(Debug, Clone)]
+pub struct Args {
+    command: String,
+    /// The command line after the command, and how much of it has been taken.
+    line: String,
+    pos: usize
+}
+
+impl Args {
+    pub fn new<S: Into<String>>(command: S, line: &str) -> Args {
+        Args { command: command.into(), line: String::from(line), pos: 0 }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remainder().is_empty()
+    }
+
+    /// The next argument, without taking it.
+    pub fn peek(&self) -> Result<Option<String>, ParseError> {
+        Ok(split_word(&self.line[self.pos..], false)?.map(|(word, _)| word))
+    }
+
+    pub fn string(&mut self, name: &'static str) -> Result<String, ParseError> {
+        self.next(name, ArgKind::String)
+    }
+
+    pub fn optional_string(&mut self) -> Result<Option<String>, ParseError> {
+        match split_word(&self.line[self.pos..], false)? {
+            Some((word, span)) => {
+                self.pos += span.end;
+                Ok(Some(word))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn path(&mut self, name: &'static str) -> Result<String, ParseError> {
+        let path = self.next(name, ArgKind::Path)?;
+        if path.is_empty() {
+            return Err(self.invalid_argument(name, ArgKind::Path, path));
+        }
+        Ok(path)
+    }
+
+    pub fn optional_path(&mut self, name: &'static str) -> Result<Option<String>, ParseError> {
+        if self.is_empty() { Ok(None) } else { self.path(name).map(Some) }
+    }
+
+    pub fn int(&mut self, name: &'static str) -> Result<usize, ParseError> {
+        let word = self.next(name, ArgKind::Int)?;
+        word.parse().map_err(|_| self.invalid_argument(name, ArgKind::Int, word))
+    }
+
+    /// A line number, which is typed 1-based, as the user sees it, but is returned 0-based.
+    pub fn line_number(&mut self, name: &'static str) -> Result<usize, ParseError> {
+        self.int(name).map(|n| n.saturating_sub(1))
+    }
+
+    pub fn buffer_id(&mut self, name: &'static str) -> Result<BufferId, ParseError> {
+        let word = self.next(name, ArgKind::BufferId)?;
+        match word.parse() {
+            Ok(id) if id >= 0 => Ok(id),
+            _ => Err(self.invalid_argument(name, ArgKind::BufferId, word))
+        }
+    }
+
+    /// All the remaining arguments, as buffer ids.
+    pub fn buffer_ids(&mut self, name: &'static str) -> Result<Vec<BufferId>, ParseError> {
+        let mut ids = Vec::new();
+        while !self.is_empty() {
+            ids.push(self.buffer_id(name)?);
+        }
+        Ok(ids)
+    }
+
+    /// The rest of the line as it was typed, without leading and trailing whitespace, for
+    /// commands which take free text such as a search string. Quotes and backslashes are
+    /// kept, and the text may not be empty.
+    pub fn text(&mut self, name: &'static str) -> Result<String, ParseError> {
+        if self.is_empty() {
+            return Err(self.missing_argument(name, ArgKind::String));
+        }
+        Ok(self.rest())
+    }
+
+    /// The rest of the line as it was typed, like `text`, but which may be empty.
+    pub fn rest(&mut self) -> String {
+        let rest = String::from(self.remainder());
+        self.pos = self.line.len();
+        rest
+    }
+
+    /// The rest of the line as it was typed, including any trailing whitespace, for commands
+    /// such as complete where that matters.
+    pub fn rest_as_typed(&mut self) -> String {
+        let rest = String::from(self.line[self.pos..].trim_start());
+        self.pos = self.line.len();
+        rest
+    }
+
+    /// Checks that every argument has been used.
+    pub fn finish(self) -> Result<(), ParseError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let extra = tokenize(self.remainder()).unwrap_or_else(|_| vec![String::from(self.remainder())]);
+        Err(ParseError::TooManyArguments { command: self.command, extra })
+    }
+
+    /// An error for arguments which are well-formed but make no sense to the command.
+    pub fn invalid<S: Into<String>>(&self, message: S) -> ParseError {
+        ParseError::Invalid { command: self.command.clone(), message: message.into() }
+    }
+
+    fn remainder(&self) -> &str {
+        self.line[self.pos..].trim()
+    }
+
+    fn next(&mut self, name: &'static str, kind: ArgKind) -> Result<String, ParseError> {
+        match self.optional_string()? {
+            Some(word) => Ok(word),
+            None => Err(self.missing_argument(name, kind))
+        }
+    }
+
+    fn missing_argument(&self, name: &'static str, kind: ArgKind) -> ParseError {
+        ParseError::MissingArgument { command: self.command.clone(), name, kind }
+    }
+
+    fn invalid_argument(&self, name: &'static str, kind: ArgKind, value: String) -> ParseError {
+        ParseError::InvalidArgument { command: self.command.clone(), name, kind, value }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line).unwrap()
+    }
+
+    #[test]
+    fn tokenize_splits_on_any_whitespace() {
+        assert_eq!(words("  o   a.txt\tb "), vec!["o", "a.txt", "b"]);
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn tokenize_handles_quotes_and_escapes() {
+        assert_eq!(words(r#"o "my file.txt""#), vec!["o", "my file.txt"]);
+        assert_eq!(words(r#"/ 'a \b' "c \"d\"" e\ f"#), vec!["/", r"a \b", r#"c "d""#, "e f"]);
+        assert_eq!(words(r#"x'y z'"w" '' """#), vec!["xy zw", "", ""]);
+    }
+
+    #[test]
+    fn tokenize_for_unbalanced_line_returns_error() {
+        assert_eq!(tokenize("o 'a.txt"), Err(ParseError::UnterminatedQuote('\'')));
+        assert_eq!(tokenize("o \"a.txt\\"), Err(ParseError::UnterminatedQuote('"')));
+        assert_eq!(tokenize("o a.txt\\"), Err(ParseError::TrailingBackslash));
+    }
+
+    #[test]
+    fn tokenize_partial_returns_the_word_being_typed_and_where_it_starts() {
+        assert_eq!(tokenize_partial(""), (vec![String::new()], 0));
+        assert_eq!(tokenize_partial("o "), (vec![String::from("o"), String::new()], 2));
+        assert_eq!(tokenize_partial("S 1 \"my fi"), (vec![String::from("S"), String::from("1"), String::from("my fi")], 4));
+        assert_eq!(tokenize_partial("o a\\"), (vec![String::from("o"), String::from("a")], 2));
+    }
+
+    #[test]
+    fn quote_round_trips_through_tokenize() {
+        for word in &["plain", "two words", "it's", r#"back\slash "quoted""#, ""] {
+            assert_eq!(words(&quote(word)), vec![word.to_string()]);
+        }
+        assert_eq!(quote("plain"), "plain");
+    }
+
+    #[test]
+    fn args_check_kinds_and_counts() {
+        let mut args = Args::new("s", "abc");
+        assert_eq!(args.buffer_id("id"), Err(ParseError::InvalidArgument {
+            command: String::from("s"), name: "id", kind: ArgKind::BufferId, value: String::from("abc")
+        }));
+
+        let mut args = Args::new("n", "3");
+        assert_eq!(args.line_number("first"), Ok(2));
+        assert_eq!(args.line_number("last"), Err(ParseError::MissingArgument {
+            command: String::from("n"), name: "last", kind: ArgKind::Int
+        }));
+
+        let mut args = Args::new("S", "1 a.txt b.txt");
+        assert_eq!(args.buffer_id("id"), Ok(1));
+        assert_eq!(args.path("filename"), Ok(String::from("a.txt")));
+        assert_eq!(args.finish(), Err(ParseError::TooManyArguments {
+            command: String::from("S"), extra: vec![String::from("b.txt")]
+        }));
+    }
+
+    #[test]
+    fn args_text_is_the_rest_of_the_line_as_typed() {
+        let mut args = Args::new("/", " fn  main 'a  b' ");
+        assert_eq!(args.text("needle"), Ok(String::from("fn  main 'a  b'")));
+        assert!(args.finish().is_ok());
+
+        let mut args = Args::new("tab", " o \"my fi ");
+        assert_eq!(args.rest_as_typed(), "o \"my fi ");
+        assert!(args.is_empty());
+
+        let mut args = Args::new("dm", r" D \.bak$ it's");
+        assert_eq!(args.string("flag"), Ok(String::from("D")));
+        assert_eq!(args.text("regex"), Ok(String::from(r"\.bak$ it's")));
+
+        let mut args = Args::new("x", "it's");
+        assert_eq!(args.optional_string(), Err(ParseError::UnterminatedQuote('\'')));
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+
+use context::Context;
+use fs::complete_path;
+use text::fuzzy_score;
+use commands::command_line::{quote, tokenize_partial, ArgKind};
+use commands::file_commands::filename_resolver;
+
+/// A possible completion of the word being typed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// The word to put in place of the one being typed, unquoted.
+    pub text: String,
+    /// What the word refers to, such as a command's summary or a buffer's title.
+    pub description: String,
+    score: i32
+}
+
+/// The candidates for completing the last word of a command line, best first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completions {
+    /// The byte offset in the line where the word being typed starts.
+    pub start: usize,
+    pub candidates: Vec<Candidate>
+}
+
+impl Completions {
+    /// The line with the word being typed replaced by `candidate`, quoted if necessary.
+    pub fn apply(&self, line: &str, candidate: &Candidate) -> String {
+        format!("{}{}", &line[..self.start], quote(&candidate.text))
+    }
+}
+
+/// Finds the completions of the last word of a partly typed command line, which may be empty
+/// if the line ends in whitespace. The first word completes to the name of a command, and
+/// later words according to the kind of argument the command expects there: files and
+/// recently used files for filenames, buffers by id or title for buffer ids, and command
+/// names. Other kinds of argument have no completions.
+pub fn complete(context: &Context, line: &str) -> Completions {
+    let (words, start) = tokenize_partial(line);
+    let word = words.last().map(|w| w.as_str()).unwrap_or("");
+
+    let candidates = if words.len() <= 1 {
+        command_candidates(context, word)
+    } else {
+        let kind = context.commands().find(&words[0])
+            .and_then(|spec| spec.arg_at(words.len() - 2).map(|arg| arg.kind));
+        match kind {
+            Some(ArgKind::Command) => command_candidates(context, word),
+            Some(ArgKind::Path) => path_candidates(context, word),
+            Some(ArgKind::BufferId) => buffer_candidates(context, word),
+            _ => Vec::new()
+        }
+    };
+
+    Completions { start, candidates: rank(candidates) }
+}
+
+fn command_candidates(context: &Context, word: &str) -> Vec<Candidate> {
+    let registry = context.commands();
+    registry.names()
+        .filter_map(|name| {
+            let summary = registry.find(name).map(|spec| spec.summary().to_string()).unwrap_or_default();
+            candidate(word, name, summary, false)
+        })
+        .collect()
+}
+
+/// Entries of the directory being typed, scored on the part after the last '/', and recently
+/// used files, scored on the whole filename.
+fn path_candidates(context: &Context, word: &str) -> Vec<Candidate> {
+    let entries = complete_path(word, filename_resolver(context).search_dirs());
+    let leaf = entries.leaf;
+    let mut candidates: Vec<Candidate> = entries.paths.into_iter().zip(entries.names.iter())
+        .filter_map(|(path, name)| {
+            let description = if path.ends_with('/') { "directory" } else { "file" };
+            fuzzy_score(&leaf, name).map(|score| Candidate { text: path, description: String::from(description), score })
+        })
+        .collect();
+
+    let mut state = context.state();
+    candidates.extend(state.mru().iter().filter_map(|filename| candidate(word, filename, String::from("recent file"), false)));
+    candidates
+}
+
+fn buffer_candidates(context: &Context, word: &str) -> Vec<Candidate> {
+    context.buffers().all_buffers().iter()
+        .filter_map(|b| candidate(word, &b.id().to_string(), b.title().to_string(), true))
+        .collect()
+}
+
+/// Makes a candidate if `text`, or if `match_description` is set its description, matches the
+/// word being typed.
+fn candidate(word: &str, text: &str, description: String, match_description: bool) -> Option<Candidate> {
+    let description_score = if match_description { fuzzy_score(word, &description) } else { None };
+    let score = match (fuzzy_score(word, text), description_score) {
+        (Some(a), Some(b)) => a.max(b),
+        (a, b) => a.or(b)?
+    };
+    Some(Candidate { text: String::from(text), description, score })
+}
+
+/// Sorts candidates best first, shorter and then alphabetically earlier text winning ties, and
+/// drops all but the best of any with the same text.
+fn rank(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| match b.score.cmp(&a.score) {
+        Ordering::Equal => a.text.len().cmp(&b.text.len()).then_with(|| a.text.cmp(&b.text)),
+        ordering => ordering
+    });
+
+    let mut ranked: Vec<Candidate> = Vec::with_capacity(candidates.len());
+    for c in candidates {
+        if !ranked.iter().any(|r| r.text == c.text) {
+            ranked.push(c);
+        }
+    }
+    ranked
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn rank_orders_by_score_and_removes_duplicates() {
+        let candidates = ["close-all", "save-as", "save", "s", "save", "open"].iter()
+            .filter_map(|name| candidate("sa", name, String::new(), false))
+            .collect();
+        assert_eq!(texts(&rank(candidates)), vec!["save", "save-as", "close-all"]);
+    }
+
+    #[test]
+    fn candidate_can_match_on_description() {
+        assert!(candidate("main", "3", String::from("main.rs"), false).is_none());
+        let c = candidate("main", "3", String::from("main.rs"), true).unwrap();
+        assert_eq!(c.text, "3");
+        assert!(candidate("3", "3", String::from("main.rs"), true).is_some());
+    }
+
+    #[test]
+    fn apply_replaces_the_word_being_typed() {
+        let completions = Completions {
+            start: 8,
+            candidates: vec![Candidate { text: String::from("my file.txt"), description: String::new(), score: 0 }]
+        };
+        assert_eq!(completions.apply("save-as my", &completions.candidates[0]), "save-as 'my file.txt'");
+    }
+}
@@ -0,0 +1,24 @@
+use context::Context;
+use commands::completion::complete;
+
+/// The most completions listed at once.
+const MAX_LISTED: usize = 20;
+
+/// Lists the completions of the last word of `line`, a partly typed command line, which may
+/// end part way through a quoted word. A line ending in whitespace completes a new word. A
+/// single completion is shown as the completed line.
+pub fn handle_complete(context: &Context, line: String) {
+    let completions = complete(context, &line);
+    match completions.candidates.len() {
+        0 => info!("No completions for {:?}", line),
+        1 => info!("{}", completions.apply(&line, &completions.candidates[0])),
+        n => {
+            for c in completions.candidates.iter().take(MAX_LISTED) {
+                info!("{:<40} {}", c.text, c.description);
+            }
+            if n > MAX_LISTED {
+                info!("... and {} more", n - MAX_LISTED);
+            }
+        }
+    }
+}
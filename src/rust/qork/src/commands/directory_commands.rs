@@ -0,0 +1,168 @@
+use std::path::Path;
+use buffer::{apply_edits, copy_into, non_empty_directories, BufferId, DirectoryEdit, DirectoryListing, FLAG_MARK};
+use context::Context;
+use commands::file_commands::handle_open_file;
+use fs;
+use regex::Regex;
+
+/// Opens a directory in a directory buffer and makes it the current buffer. If the directory
+/// is already open then its buffer is made current instead.
+pub fn handle_open_directory(context: &Context, dir: &Path) {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+
+    let existing = context.directories().iter()
+        .find(|&(_, listing)| listing.dir() == dir.as_path())
+        .map(|(&id, _)| id);
+    if let Some(id) = existing {
+        if context.buffers().set_current_buffer(id) {
+            info!("Buffer {} is open on {:?}", id, dir);
+            return;
+        }
+    }
+
+    match DirectoryListing::read(&dir) {
+        Ok(listing) => {
+            let id = context.buffers().new_buffer(&mut context.buffer_factory(), &listing.title());
+            context.buffers()[id].borrow_mut().set_generated_lines(&listing.render());
+            context.directories().insert(id, listing);
+            info!("Buffer {} is open on {:?}", id, dir);
+        },
+        Err(e) => warn!("Could not read the directory {:?}: {}", dir, e)
+    }
+}
+
+/// Opens the file or directory on a line of the current directory buffer.
+pub fn handle_open_directory_entry(context: &Context, line: usize) {
+    let path = match current_directory(context) {
+        Some((_, listing, _)) => listing.path_at_line(line),
+        None => return
+    };
+
+    match path {
+        Some(path) => handle_open_file(context, path.to_string_lossy().into_owned()),
+        None => warn!("There is no file on line {}", line + 1)
+    }
+}
+
+/// Makes the changes to the file system described by the edits made to the current directory
+/// buffer, then lists the directory again.
+pub fn handle_apply_directory_edits(context: &Context, force: bool) {
+    let id = context.buffers().current_buffer();
+    if context.directories().contains_key(&id) {
+        apply_directory_edits(context, id, force);
+    } else {
+        warn!("The current buffer is not a directory buffer");
+    }
+}
+
+/// Makes the changes described by the edits made to a directory buffer, which is how directory
+/// buffers are saved, then lists the directory again. If any edit cannot be understood, or if
+/// the edits would delete a directory which is not empty and `force` is false, nothing is
+/// done. Returns true if the edits were applied.
+pub fn apply_directory_edits(context: &Context, id: BufferId, force: bool) -> bool {
+    let listing = match context.directories().get(&id).cloned() {
+        Some(listing) => listing,
+        None => return false
+    };
+    let lines = match context.buffers().get(id) {
+        Some(rc) => rc.borrow().lines(),
+        None => return false
+    };
+
+    match listing.parse_edits(&lines) {
+        Ok(ref edits) if edits.is_empty() => info!("There are no changes to {:?}", listing.dir()),
+        Ok(edits) => {
+            let non_empty = non_empty_directories(&edits);
+            if !force && !non_empty.is_empty() {
+                warn!("These directories are not empty: {:?}. Use 'da!' to delete them with everything in them", non_empty);
+                return false;
+            }
+            apply_and_report(&edits);
+        },
+        Err(e) => { warn!("Could not apply the changes to {:?}: {}", listing.dir(), e); return false; }
+    }
+
+    refresh_directory(context, id, listing.dir());
+    true
+}
+
+/// Lists the current directory again, discarding any edits which have not been applied.
+pub fn handle_refresh_directory(context: &Context) {
+    if let Some((id, listing, _)) = current_directory(context) {
+        refresh_directory(context, id, listing.dir());
+    }
+}
+
+/// Sets the flag of every entry in the current directory buffer whose name matches `pattern`.
+/// A flag of ' ' clears the flags.
+pub fn handle_flag_directory_entries(context: &Context, flag: char, pattern: Regex) {
+    let (id, listing, lines) = match current_directory(context) {
+        Some(d) => d,
+        None => return
+    };
+
+    // Lines are replaced one at a time, so that only the narrowed part of a narrowed buffer
+    // is flagged.
+    let flagged = listing.flag_matching(&lines, &pattern, flag);
+    let bc = context.buffers();
+    let mut buffer = bc[id].borrow_mut();
+    let accessible = buffer.accessible_lines();
+    let mut num_changed = 0;
+    for (i, (old, new)) in lines.iter().zip(flagged.iter()).enumerate() {
+        if old != new && accessible.contains(&i) && buffer.replace_lines(i..i + 1, &[new]) {
+            num_changed += 1;
+        }
+    }
+    info!("Changed the flag of {} entries", num_changed);
+}
+
+/// Copies every entry marked with '*' in the current directory buffer into the directory
+/// `dest`, which is relative to the listed directory.
+pub fn handle_copy_marked_entries(context: &Context, dest: String) {
+    let (_, listing, lines) = match current_directory(context) {
+        Some(d) => d,
+        None => return
+    };
+
+    let dest = listing.dir().join(fs::expand_filename(&dest));
+    let edits = match copy_into(&listing.flagged(&lines, FLAG_MARK), &dest) {
+        Ok(edits) => edits,
+        Err(e) => { warn!("{}", e); return; }
+    };
+    if edits.is_empty() {
+        warn!("No entries are marked with '{}'", FLAG_MARK);
+        return;
+    }
+    apply_and_report(&edits);
+}
+
+/// Returns the id, listing and lines of the current buffer if it is a directory buffer.
+fn current_directory(context: &Context) -> Option<(BufferId, DirectoryListing, Vec<String>)> {
+    let bc = context.buffers();
+    let id = bc.current_buffer();
+    let listing = context.directories().get(&id).cloned();
+    match (listing, bc.get(id)) {
+        (Some(listing), Some(rc)) => Some((id, listing, rc.borrow().lines())),
+        _ => { warn!("The current buffer is not a directory buffer"); None }
+    }
+}
+
+fn apply_and_report(edits: &[DirectoryEdit]) {
+    for (edit, result) in edits.iter().zip(apply_edits(edits)) {
+        match result {
+            Ok(_) => info!("Applied: {}", edit),
+            Err(e) => warn!("Could not {}: {}", edit, e)
+        }
+    }
+}
+
+/// Reads the directory again and shows it in its buffer, which is left unchanged.
+fn refresh_directory(context: &Context, id: BufferId, dir: &Path) {
+    match DirectoryListing::read(dir) {
+        Ok(listing) => {
+            context.buffers()[id].borrow_mut().set_generated_lines(&listing.render());
+            context.directories().insert(id, listing);
+        },
+        Err(e) => warn!("Could not read the directory {:?}: {}", dir, e)
+    }
+}
@@ -0,0 +1,46 @@
+use commands::with_current_buffer;
+use context::Context;
+use text::{fill_paragraph, find_paragraph, unfill_paragraph};
+
+pub fn handle_fill_paragraph(context: &Context, line: usize) {
+    let column = context.configuration().fill_column();
+    reformat_paragraph(context, line, |lines| fill_paragraph(lines, column));
+}
+
+pub fn handle_unfill_paragraph(context: &Context, line: usize) {
+    reformat_paragraph(context, line, |lines| vec![unfill_paragraph(lines)]);
+}
+
+/// Finds the paragraph around `line` in the current buffer and replaces it with the
+/// result of calling `reformat` on its lines. In a narrowed buffer, only the narrowed lines
+/// are part of paragraphs.
+fn reformat_paragraph<F>(context: &Context, line: usize, reformat: F)
+    where F: Fn(&[String]) -> Vec<String>
+{
+    with_current_buffer(context, |buffer| {
+        if buffer.is_read_only() {
+            warn!("Buffer {} is read-only", buffer.id());
+            return;
+        }
+
+        let accessible = buffer.accessible_lines();
+        if !accessible.contains(&line) {
+            warn!("Line {} is outside the narrowed part of buffer {}", line + 1, buffer.id());
+            return;
+        }
+
+        let lines = buffer.lines();
+        let lines = &lines[accessible.start..accessible.end.min(lines.len())];
+        match find_paragraph(lines, line - accessible.start) {
+            Some(para) => {
+                let new_lines = reformat(&lines[para.clone()]);
+                let para = para.start + accessible.start..para.end + accessible.start;
+                if buffer.replace_lines(para.clone(), &new_lines) {
+                    info!("Reformatted lines {} to {} of buffer {} into {} lines",
+                        para.start + 1, para.end, buffer.id(), new_lines.len());
+                }
+            },
+            None => info!("Line {} is not part of a paragraph", line + 1)
+        }
+    });
+}
@@ -1,32 +1,183 @@
 use context::Context;
-use utils;
-use buffer::BufferId;
+use fs;
+use std::path::PathBuf;
+use buffer::{BufferError, BufferId, DiskChange};
+use text::{diff_lines, format_unified_diff};
+use commands::directory_commands::{apply_directory_edits, handle_open_directory};
 
+/// The number of unchanged lines shown around each change in a diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Opens a file, resolving its name with a `FilenameResolver`. A "file:line:col" suffix moves
+/// the point to that position, and similarly named files are suggested if the file does not
+/// exist (a new, empty buffer is still created for it). Directories are opened in directory
+/// buffers.
 pub fn handle_open_file(context: &Context, filename: String) {
-    let filename = utils::expand_variables(&filename).to_string();
+    let resolved = filename_resolver(context).resolve(&filename);
+    let path = resolved.path.to_string_lossy().into_owned();
+
+    if resolved.path.is_dir() {
+        handle_open_directory(context, &resolved.path);
+        return;
+    }
+
+    if !resolved.exists && !resolved.suggestions.is_empty() {
+        let suggestions: Vec<_> = resolved.suggestions.iter().map(|s| s.to_string_lossy().into_owned()).collect();
+        info!("{} does not exist, did you mean one of: {}", &path, suggestions.join(", "));
+    }
+
+    let result = context.buffers().open_file(&mut context.buffer_factory(), &resolved.path);
+    match result {
+        Ok(id) => {
+            if let Some(line) = resolved.line {
+                let bc = context.buffers();
+                let mut b = bc.get(id).unwrap().borrow_mut();
+                let column = resolved.column.unwrap_or(1);
+                b.move_point_to_line_and_column(line.saturating_sub(1), column.saturating_sub(1));
+            }
+            info!("Buffer {} is open on {}", id, &path);
+            context.state().mru().insert(path);
+        },
+        Err(e) => warn!("Could not open {}: {}", &path, e)
+    }
+}
+
+/// Creates a resolver which looks for relative filenames in the directory of the current
+/// buffer's file, then the project root, then the working directory.
+pub fn filename_resolver(context: &Context) -> fs::FilenameResolver {
+    let buffer_dir = {
+        let bc = context.buffers();
+        let current = bc.get(bc.current_buffer());
+        current.and_then(|rc| rc.borrow().filename().as_ref().and_then(|f| f.parent().map(PathBuf::from)))
+    };
+    let cwd = context.working_dir();
+    fs::FilenameResolver::new(buffer_dir.as_ref().map(|d| d.as_path()), &cwd, context.configuration().project_root_markers())
+}
+
+/// Saves a buffer to its file. Saving a directory buffer applies its edits instead.
+pub fn handle_save_buffer(context: &Context, buffer_id: BufferId, force: bool) {
+    if context.directories().contains_key(&buffer_id) {
+        apply_directory_edits(context, buffer_id, force);
+        return;
+    }
+
+    let result = context.buffers().save_buffer(buffer_id, force);
+    match result {
+        Ok(num_bytes) => {
+            context.discard_recovery(buffer_id);
+            info!("Saved buffer {} ({} bytes)", buffer_id, num_bytes);
+        },
+        Err(e @ BufferError::ModifiedOnDisk(_)) => warn!("Could not save buffer {}: {}. {}", buffer_id, e, conflict_help(buffer_id)),
+        Err(e) => warn!("Could not save buffer {}: {}", buffer_id, e)
+    }
+}
+
+/// Explains the commands which resolve a conflict between a buffer and its changed file.
+pub fn conflict_help(buffer_id: BufferId) -> String {
+    format!("Use 's! {0}' to overwrite it, 'R {0}' to reload it or 'D {0}' to see the differences", buffer_id)
+}
+
+/// Checks whether any files have been changed by other programs, reloading the buffers that
+/// can safely be reloaded and telling the user about the rest.
+pub fn handle_disk_changes(context: &Context) {
+    let changes = context.buffers().check_disk_changes();
+    for change in changes {
+        match change {
+            DiskChange::Reloaded(id) => info!("Buffer {} was reloaded because its file was changed by another program", id),
+            DiskChange::ReloadFailed(id, e) => warn!("Could not reload buffer {} after its file was changed: {}", id, e),
+            DiskChange::Conflict(id) => warn!("The file of buffer {} has been changed by another program, but the buffer has unsaved changes. {}",
+                                              id, conflict_help(id)),
+            DiskChange::Deleted(id) => warn!("The file of buffer {} has been deleted or renamed by another program", id)
+        }
+    }
+}
 
+pub fn handle_reload_buffer(context: &Context, buffer_id: BufferId) {
+    let result = context.buffers().reload_buffer(buffer_id);
+    match result {
+        Ok(_) => {
+            context.discard_recovery(buffer_id);
+            info!("Reloaded buffer {}", buffer_id);
+        },
+        Err(e) => warn!("Could not reload buffer {}: {}", buffer_id, e)
+    }
+}
+
+/// Shows the differences between a buffer and its file in a special buffer, which becomes
+/// the current buffer.
+pub fn handle_diff_buffer(context: &Context, buffer_id: BufferId) {
     let mut bc = context.buffers();
-    if let Some(_buffer) = bc.find_by_filename(&filename) {
-        info!("Buffer for {} already exists.", &filename);
+    if let Err(e) = bc.ensure_loaded(buffer_id) {
+        warn!("Could not load buffer {}: {}", buffer_id, e);
+        return;
+    }
+
+    let (title, diff) = {
+        let b = match bc.get(buffer_id) {
+            Some(rc) => rc.borrow(),
+            None => { warn!("No buffer with an id of {} exists", buffer_id); return; }
+        };
+        let filename = match *b.filename() {
+            Some(ref f) => f.clone(),
+            None => { warn!("Buffer {} has no file to compare with", buffer_id); return; }
+        };
+
+        let on_disk: Vec<String> = match fs::load_with_codec(&filename, b.codec()) {
+            Ok(text) => text.lines().map(String::from).collect(),
+            Err(e) => { warn!("Could not read {:?} to compare with buffer {}: {}", filename, buffer_id, e); return; }
+        };
+        let in_buffer = b.lines();
+        let old_name = filename.to_string_lossy().into_owned();
+        let new_name = format!("{} (buffer {})", b.title(), buffer_id);
+        let diff = format_unified_diff(&diff_lines(&on_disk, &in_buffer), &old_name, &new_name, DIFF_CONTEXT_LINES);
+        (format!("*diff {}*", b.title()), diff)
+    };
+
+    if diff.is_empty() {
+        info!("Buffer {} is the same as its file", buffer_id);
         return;
     }
 
-    let mut fac = context.buffer_factory();
-    let b = fac.open_file(&filename);
-    let id = b.id();
-    info!("Buffer for {} does not exist, creating new buffer with id of {}", &filename, b.id());
-    bc.insert(b);
-    bc.set_current_buffer(id);
-    context.state().mru().insert(filename);
+    // Replace any previous diff of the same buffer.
+    let old_id = bc.find_by_title(&title);
+    if let Some(old_id) = old_id {
+        bc.remove(old_id);
+        context.remove_views(old_id);
+    }
+
+    let mut diff_buffer = context.buffer_factory().new_special_buffer(&title);
+    diff_buffer.append_lines_capped(&diff, diff.len());
+    diff_buffer.move_point_to_start();
+    let diff_id = diff_buffer.id();
+    bc.insert(diff_buffer);
+    bc.set_current_buffer(diff_id);
+    info!("Showing differences between buffer {} and its file in buffer {}", buffer_id, diff_id);
 }
 
-pub fn handle_save_buffer(context: &Context, buffer_id: BufferId) {
-    match context.buffers().get(buffer_id) {
-        Some(rc) => {
-            let buffer = rc.borrow_mut();
-            info!("Saving buffer with id of {} and filename of {:?}", buffer.id(), buffer.filename());
+pub fn handle_save_buffer_as(context: &Context, buffer_id: BufferId, filename: String) {
+    let filename = context.absolute_path(&filename).to_string_lossy().into_owned();
+
+    let result = context.buffers().save_buffer_as(buffer_id, &filename);
+    match result {
+        Ok(num_bytes) => {
+            context.discard_recovery(buffer_id);
+            info!("Saved buffer {} as {} ({} bytes)", buffer_id, &filename, num_bytes);
+            context.state().mru().insert(filename);
         },
-        None => { warn!("No buffer with an id of {} exists", buffer_id) }
+        Err(e) => warn!("Could not save buffer {} as {}: {}", buffer_id, &filename, e)
+    }
+}
+
+pub fn handle_close_buffer(context: &Context, buffer_id: BufferId, force: bool) {
+    let result = context.buffers().close_buffer(buffer_id, force);
+    match result {
+        Ok(buffer) => {
+            context.remove_views(buffer_id);
+            context.discard_recovery(buffer_id);
+            context.directories().remove(&buffer_id);
+            info!("Closed buffer {} ({})", buffer_id, buffer.title());
+        },
+        Err(e) => warn!("Could not close buffer {}: {}", buffer_id, e)
     }
 }
 
@@ -36,4 +187,23 @@ pub fn handle_set_current_buffer(context: &Context, buffer_id: BufferId) {
         true => info!("Current buffer changed to {}", buffer_id),
         false => info!("The buffer {} does not exist", buffer_id)
     }
-}
\ No newline at end of file
+}
+
+/// Switches to the buffer that was current before this one.
+pub fn handle_previous_buffer(context: &Context) {
+    let previous = context.buffers().switch_to_previous_buffer();
+    match previous {
+        Some(id) => info!("Current buffer changed to {}", id),
+        None => info!("There is no other buffer to switch to")
+    }
+}
+
+/// Steps through the buffers in the order they were last used, older first unless `older`
+/// is false.
+pub fn handle_cycle_buffers(context: &Context, older: bool) {
+    let next = context.buffers().cycle_buffers(older);
+    match next {
+        Some(id) => info!("Current buffer changed to {}", id),
+        None => info!("There are no buffers")
+    }
+}
@@ -0,0 +1,49 @@
+use commands::with_current_buffer;
+use context::Context;
+use text::{compute_folds, fold_at_line};
+
+/// Which way to change a single fold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FoldAction {
+    Collapse,
+    Expand,
+    Toggle
+}
+
+pub fn handle_fold(context: &Context, line: usize, action: FoldAction) {
+    with_current_buffer(context, |buffer| {
+        let folds = compute_folds(&buffer.lines(), context.configuration().fold_method());
+        let fold = match fold_at_line(&folds, line) {
+            Some(fold) => fold,
+            None => {
+                warn!("There is no fold at line {}", line + 1);
+                return;
+            }
+        };
+
+        let mut state = context.folds(buffer);
+        let collapsed = match action {
+            FoldAction::Collapse => { state.collapse(&fold); true },
+            FoldAction::Expand => { state.expand(&fold); false },
+            FoldAction::Toggle => state.toggle(&fold)
+        };
+
+        info!("{} fold at lines {} to {}", if collapsed { "Collapsed" } else { "Expanded" },
+              fold.start_line + 1, fold.end_line + 1);
+    });
+}
+
+pub fn handle_fold_all(context: &Context) {
+    with_current_buffer(context, |buffer| {
+        let folds = compute_folds(&buffer.lines(), context.configuration().fold_method());
+        context.folds(buffer).collapse_all(&folds);
+        info!("Collapsed {} folds", folds.len());
+    });
+}
+
+pub fn handle_unfold_all(context: &Context) {
+    with_current_buffer(context, |buffer| {
+        context.folds(buffer).expand_all();
+        info!("Expanded all folds");
+    });
+}
@@ -0,0 +1,40 @@
+use context::Context;
+
+/// Describes a command: its usage, aliases and documentation. With no command, explains how
+/// to find the commands.
+pub fn handle_help(context: &Context, name: Option<String>) {
+    let name = match name {
+        Some(n) => n,
+        None => {
+            info!("Type 'commands' to list the commands and 'help <command>' to describe one");
+            return;
+        }
+    };
+
+    let spec = match context.commands().find(&name) {
+        Some(spec) => spec,
+        None => { warn!("There is no command called {}", name); return; }
+    };
+
+    info!("{}", spec.usage());
+    if !spec.aliases().is_empty() {
+        info!("  Also: {}", spec.aliases().join(", "));
+    }
+    for line in spec.doc().lines() {
+        info!("  {}", line);
+    }
+}
+
+/// Lists every command, with its aliases and a summary.
+pub fn handle_list_commands(context: &Context) {
+    let registry = context.commands();
+    for spec in registry.iter() {
+        let name = if spec.aliases().is_empty() {
+            spec.name().to_string()
+        } else {
+            format!("{} ({})", spec.name(), spec.aliases().join(", "))
+        };
+        info!("{:<32} {}", name, spec.summary());
+    }
+    info!("{} commands", registry.len());
+}
@@ -0,0 +1,88 @@
+use context::Context;
+use commands::execute_command_line;
+use commands::command_line::ParseError;
+
+/// Lists the `count` most recent command lines, numbered so that history-run can run them.
+pub fn handle_show_history(context: &Context, count: usize) {
+    let mut state = context.state();
+    let history = state.command_history();
+    if history.is_empty() {
+        info!("The command history is empty");
+        return;
+    }
+
+    let mut lines: Vec<_> = history.iter().take(count).enumerate().collect();
+    lines.reverse();
+    for (index, line) in lines {
+        info!("{:>4}  {}", index + 1, line);
+    }
+}
+
+pub fn handle_history_previous(context: &Context) {
+    let mut state = context.state();
+    match state.command_history().previous() {
+        Some((index, line)) => info!("{:>4}  {}", index + 1, line),
+        None => info!("The command history is empty")
+    }
+}
+
+pub fn handle_history_next(context: &Context) {
+    let mut state = context.state();
+    match state.command_history().next() {
+        Some((index, line)) => info!("{:>4}  {}", index + 1, line),
+        None => info!("Back at the end of the command history")
+    }
+}
+
+/// Searches back through the command history. With no text, the last search is repeated.
+pub fn handle_history_search(context: &Context, text: String) {
+    let mut state = context.state();
+    let history = state.command_history();
+    let text = if text.is_empty() {
+        match history.last_search() {
+            Some(last) => last.to_string(),
+            None => { warn!("There is no search to repeat"); return; }
+        }
+    } else {
+        text
+    };
+
+    match history.search(&text) {
+        Some((index, line)) => info!("{:>4}  {}", index + 1, line),
+        None => info!("No older command line contains {:?}", text)
+    }
+}
+
+/// Runs the command line numbered `number` by the history command, or the recalled line if
+/// no number is given. A line run this way cannot run history-run again.
+pub fn handle_history_run(context: &Context, number: Option<usize>) -> Result<bool, ParseError> {
+    if *context.running_history() {
+        warn!("A command line run from the history cannot run another one");
+        return Ok(false);
+    }
+
+    let line = {
+        let mut state = context.state();
+        let history = state.command_history();
+        let line = match number {
+            Some(n) => n.checked_sub(1).and_then(|i| history.get(i)),
+            None => history.recalled().map(|(_, line)| line)
+        };
+        match line {
+            Some(l) => l.to_string(),
+            None => {
+                match number {
+                    Some(n) => warn!("There is no command line numbered {} in the history", n),
+                    None => warn!("No command line has been recalled, use history-previous or history-search")
+                }
+                return Ok(false);
+            }
+        }
+    };
+
+    info!("Running {}", line);
+    *context.running_history() = true;
+    let result = execute_command_line(context, &line);
+    *context.running_history() = false;
+    result
+}
@@ -0,0 +1,33 @@
+use buffer::{format_bytes, MemoryUsage};
+use context::Context;
+
+/// Logs the approximate memory used by each buffer and by all of them together, and the
+/// configured limit.
+pub fn handle_show_memory_usage(context: &Context) {
+    let mut ids: Vec<_> = context.buffers().keys().cloned().collect();
+    ids.sort();
+
+    let mut total = MemoryUsage::default();
+    for id in ids {
+        let usage = match context.memory_usage(id) {
+            Some(u) => u,
+            None => continue
+        };
+        let (title, is_evicted) = {
+            let bc = context.buffers();
+            let b = bc[id].borrow();
+            (b.title().to_string(), b.is_evicted())
+        };
+        info!("{:>4} {:<30} text {:>10}  history {:>10}  caches {:>10}  total {:>10}{}",
+              id, title, format_bytes(usage.text), format_bytes(usage.history),
+              format_bytes(usage.caches), format_bytes(usage.total()),
+              if is_evicted { "  (evicted)" } else { "" });
+        total += usage;
+    }
+
+    let limit = context.buffers().memory_limit();
+    let limit = if limit == 0 { String::from("no limit") } else { format!("limit {}", format_bytes(limit)) };
+    info!("Total {} (text {}, history {}, caches {}), {}",
+          format_bytes(total.total()), format_bytes(total.text), format_bytes(total.history),
+          format_bytes(total.caches), limit);
+}
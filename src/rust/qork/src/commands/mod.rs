@@ -1,51 +1,279 @@
+mod backup_commands;
+mod buffer_list_commands;
+mod builtin_commands;
+mod command_line;
+mod completion;
+mod completion_commands;
+mod directory_commands;
+mod edit_commands;
 mod file_commands;
+mod fold_commands;
+mod help_commands;
+mod history_commands;
+mod memory_commands;
+mod motion_commands;
+mod quit_commands;
+mod registry;
+mod search_commands;
+mod spelling_commands;
+mod workspace_commands;
 
-use buffer::BufferId;
+use buffer::{Buffer, BufferFilter, BufferId, BufferListSort, FLAG_COPY, FLAG_DELETE, FLAG_MARK};
 use context::Context;
+use regex::Regex;
+use commands::backup_commands::*;
+use commands::buffer_list_commands::*;
+use commands::command_line::{Args, ParseError};
+use commands::directory_commands::*;
+use commands::edit_commands::*;
 use commands::file_commands::*;
+use commands::fold_commands::*;
+use commands::memory_commands::*;
+use commands::motion_commands::*;
+use commands::quit_commands::*;
+use commands::search_commands::*;
+use commands::spelling_commands::*;
+use commands::workspace_commands::*;
+
+pub use commands::builtin_commands::{register_builtin_commands, register_configured_commands};
+pub use commands::file_commands::handle_disk_changes;
+pub use commands::quit_commands::{answer_quit_prompt, handle_input_closed};
+pub use commands::registry::CommandRegistry;
 
 #[derive(Debug)]
 pub enum Command {
     NoOp,
-    Quit,
+    Quit { force: bool },
+    SaveAll,
+    CloseAll { force: bool },
     OpenFile { filename: String },
-    SaveBuffer { buffer_id: BufferId },
-    SetCurrentBuffer { buffer_id: BufferId }
+    SaveBuffer { buffer_id: BufferId, force: bool },
+    ReloadBuffer { buffer_id: BufferId },
+    DiffBuffer { buffer_id: BufferId },
+    SaveBufferAs { buffer_id: BufferId, filename: String },
+    CloseBuffer { buffer_id: BufferId, force: bool },
+    ListBackups,
+    RestoreBackup { index: usize },
+    SetCurrentBuffer { buffer_id: BufferId },
+    PreviousBuffer,
+    CycleBuffers { older: bool },
+    ShowBufferList,
+    SortBufferList { sort: BufferListSort, reverse: bool },
+    FilterBufferList { filter: Option<BufferFilter> },
+    MarkBuffers { buffer_ids: Vec<BufferId>, mark: bool },
+    BufferListAction { action: BufferListAction },
+    OpenDirectoryEntry { line: usize },
+    ApplyDirectoryEdits { force: bool },
+    RefreshDirectory,
+    FlagDirectoryEntries { flag: char, pattern: Regex },
+    CopyMarkedEntries { dest: String },
+    ListWorkspaces,
+    SaveWorkspace { name: String, root: Option<String> },
+    SwitchWorkspace { name: String },
+    LeaveWorkspace,
+    DeleteWorkspace { name: String },
+    ShowMemoryUsage,
+    FillParagraph { line: usize },
+    UnfillParagraph { line: usize },
+    CheckSpelling,
+    NextMisspelling,
+    PreviousMisspelling,
+    AddToPersonalWords { word: String },
+    NarrowToLines { first_line: usize, last_line: usize },
+    Widen,
+    MoveToStart,
+    MoveToEnd,
+    GotoLine { line: usize },
+    SearchForward { needle: String },
+    SearchBackward { needle: String },
+    ReplaceAll { needle: String, replacement: String },
+    Fold { line: usize, action: FoldAction },
+    FoldAll,
+    UnfoldAll
 }
 
-fn get_arg(line: &str) -> String {
-    line.chars().skip(2).collect()
+/// Parses the "ls" buffer list commands:
+///
+///   ls                                  show the buffer list
+///   ls sort id|title|path|size|accessed sort it, reversed if the column is prefixed by '-'
+///   ls filter modified|dir D|ext E|re R add a filter, or with no arguments remove all filters
+///   ls mark ID...  /  ls unmark [ID...]  mark buffers, or unmark them (all of them if no ids)
+///   ls save|close|close!|revert         act on every marked buffer
+fn parse_buffer_list_command(args: &mut Args) -> Result<Command, ParseError> {
+    let subcommand = match args.optional_string()? {
+        Some(w) => w,
+        None => return Ok(Command::ShowBufferList)
+    };
+
+    let command = match subcommand.as_str() {
+        "sort" => {
+            let column = args.optional_string()?.unwrap_or_else(|| String::from("id"));
+            let reverse = column.starts_with('-');
+            match BufferListSort::parse(column.trim_start_matches('-')) {
+                Some(sort) => Command::SortBufferList{ sort, reverse },
+                None => return Err(args.invalid(format!("cannot sort the buffer list by {}", column)))
+            }
+        },
+        "filter" => {
+            let kind = args.optional_string()?;
+            let filter = match kind.as_ref().map(|k| k.as_str()) {
+                None => None,
+                Some("modified") => Some(BufferFilter::Modified),
+                Some("dir") => Some(BufferFilter::Directory(args.path("directory")?.into())),
+                Some("ext") => Some(BufferFilter::Extension(args.string("extension")?.trim_start_matches('.').to_string())),
+                Some("re") => {
+                    let value = args.text("regex")?;
+                    match Regex::new(&value) {
+                        Ok(re) => Some(BufferFilter::Regex(re)),
+                        Err(e) => return Err(args.invalid(format!("invalid regular expression {}: {}", value, e)))
+                    }
+                },
+                Some(other) => return Err(args.invalid(format!("unknown buffer list filter {}", other)))
+            };
+            Command::FilterBufferList{ filter }
+        },
+        "mark" | "unmark" => Command::MarkBuffers{ buffer_ids: args.buffer_ids("id")?, mark: subcommand == "mark" },
+        "save" => Command::BufferListAction{ action: BufferListAction::Save },
+        "close" => Command::BufferListAction{ action: BufferListAction::Close{ force: false } },
+        "close!" => Command::BufferListAction{ action: BufferListAction::Close{ force: true } },
+        "revert" => Command::BufferListAction{ action: BufferListAction::Revert },
+        other => return Err(args.invalid(format!("unknown buffer list command {}", other)))
+    };
+
+    Ok(command)
 }
 
-pub fn parse_command(line: &str) -> Command {
-    if line == "q" {
-        Command::Quit
+/// Parses the "ws" workspace commands:
+///
+///   ws                   list the workspaces
+///   ws NAME              switch to a workspace
+///   ws save NAME [ROOT]  save the visible buffers as a workspace and make it active
+///   ws rm NAME           delete a workspace
+///   ws -                 leave the active workspace, making all buffers visible
+fn parse_workspace_command(args: &mut Args) -> Result<Command, ParseError> {
+    match args.optional_string()? {
+        None => Ok(Command::ListWorkspaces),
+        Some(ref w) if w == "-" => Ok(Command::LeaveWorkspace),
+        Some(ref w) if w == "save" => Ok(Command::SaveWorkspace{ name: args.string("name")?, root: args.optional_path("root")? }),
+        Some(ref w) if w == "rm" => Ok(Command::DeleteWorkspace{ name: args.string("name")? }),
+        Some(name) => Ok(Command::SwitchWorkspace{ name })
     }
-    else if line.starts_with("o ") {
-        Command::OpenFile{ filename: get_arg(line) }
+}
+
+/// Parses "dm F RE", which sets the flag F ('*', 'D' or 'C', or '-' to clear it) on every
+/// entry in the current directory buffer whose name matches the regular expression RE.
+fn parse_flag_directory_entries(args: &mut Args) -> Result<Command, ParseError> {
+    let word = args.string("flag")?;
+    let flag = if word == "-" { ' ' } else { word.chars().next().unwrap_or(' ') };
+    if word.chars().count() != 1 || ![' ', FLAG_MARK, FLAG_DELETE, FLAG_COPY].contains(&flag) {
+        return Err(args.invalid(format!("unknown directory flag '{}', use {}, {}, {} or -", word, FLAG_MARK, FLAG_DELETE, FLAG_COPY)));
     }
-    else if line.starts_with("s ") {
-        let arg = get_arg(line);
-        let id: BufferId = arg.parse().unwrap();
-        Command::SaveBuffer{ buffer_id: id }
+
+    let pattern = args.text("regex")?;
+    match Regex::new(&pattern) {
+        Ok(pattern) => Ok(Command::FlagDirectoryEntries{ flag, pattern }),
+        Err(e) => Err(args.invalid(format!("invalid regular expression {}: {}", pattern, e)))
     }
-    else if line.starts_with("c ") {
-        let arg = get_arg(line);
-        let id: BufferId = arg.parse().unwrap();
-        Command::SetCurrentBuffer{ buffer_id: id }
+}
+
+/// Parses "r FROM/TO", which replaces all occurrences of FROM with TO.
+fn parse_replace_all(args: &mut Args) -> Result<Command, ParseError> {
+    let arg = args.text("from/to")?;
+    let mut parts = arg.splitn(2, '/');
+    let needle = String::from(parts.next().unwrap());
+    let replacement = String::from(parts.next().unwrap_or(""));
+    Ok(Command::ReplaceAll{ needle: needle, replacement: replacement })
+}
+
+/// Runs `f` against the current buffer, or logs a warning if there is no current buffer.
+fn with_current_buffer<F>(context: &Context, f: F)
+    where F: FnOnce(&mut Buffer)
+{
+    let bc = context.buffers();
+    match bc.get(bc.current_buffer()) {
+        Some(rc) => f(&mut rc.borrow_mut()),
+        None => warn!("There is no current buffer")
     }
-    else {
-        Command::NoOp
+}
+
+/// Parses and runs a command line, returning true if Qork should quit. The first word is
+/// the name or alias of a command in the registry and the rest are its arguments, which are
+/// separated by whitespace and may be quoted (see `tokenize`), except for free text, which
+/// is the rest of the line as typed. A blank line is a no-op.
+/// Mistakes, such as an unknown command or a buffer id which is not a number, are returned
+/// as errors for the caller to report. Lines which run without an error are kept in the
+/// command history.
+pub fn execute_command_line(context: &Context, line: &str) -> Result<bool, ParseError> {
+    run_command_line(context, line, true)
+}
+
+/// Parses and runs a command line, only keeping it in the command history if `record` is
+/// true. Used to run the lines behind configured commands, which are already recorded
+/// under their own name.
+fn run_command_line(context: &Context, line: &str, record: bool) -> Result<bool, ParseError> {
+    let parsed = context.commands().parse(line)?;
+    match parsed {
+        Some((spec, args)) => {
+            let result = spec.run(context, args);
+            if result.is_ok() && record && spec.is_recorded() {
+                context.state().command_history().record(line);
+            }
+            result
+        },
+        None => Ok(handle_command(context, Command::NoOp))
     }
 }
 
 pub fn handle_command(context: &Context, command: Command) -> bool {
     match command {
-        Command::NoOp => println!("No-op command"),
-        Command::Quit => { println!("Quitting"); return true; }
+        Command::NoOp => info!("No-op command"),
+        Command::Quit{force} => return handle_quit(context, force),
+        Command::SaveAll => handle_save_all(context),
+        Command::CloseAll{force} => handle_close_all(context, force),
         Command::OpenFile{filename} => handle_open_file(context, filename),
-        Command::SaveBuffer{buffer_id} => handle_save_buffer(context, buffer_id),
-        Command::SetCurrentBuffer{buffer_id} => handle_set_current_buffer(context, buffer_id)
+        Command::SaveBuffer{buffer_id, force} => handle_save_buffer(context, buffer_id, force),
+        Command::ReloadBuffer{buffer_id} => handle_reload_buffer(context, buffer_id),
+        Command::DiffBuffer{buffer_id} => handle_diff_buffer(context, buffer_id),
+        Command::SaveBufferAs{buffer_id, filename} => handle_save_buffer_as(context, buffer_id, filename),
+        Command::CloseBuffer{buffer_id, force} => handle_close_buffer(context, buffer_id, force),
+        Command::ListBackups => handle_list_backups(context),
+        Command::RestoreBackup{index} => handle_restore_backup(context, index),
+        Command::SetCurrentBuffer{buffer_id} => handle_set_current_buffer(context, buffer_id),
+        Command::PreviousBuffer => handle_previous_buffer(context),
+        Command::CycleBuffers{older} => handle_cycle_buffers(context, older),
+        Command::ShowBufferList => handle_show_buffer_list(context),
+        Command::SortBufferList{sort, reverse} => handle_sort_buffer_list(context, sort, reverse),
+        Command::FilterBufferList{filter} => handle_filter_buffer_list(context, filter),
+        Command::MarkBuffers{buffer_ids, mark} => handle_mark_buffers(context, buffer_ids, mark),
+        Command::BufferListAction{action} => handle_buffer_list_action(context, action),
+        Command::OpenDirectoryEntry{line} => handle_open_directory_entry(context, line),
+        Command::ApplyDirectoryEdits{force} => handle_apply_directory_edits(context, force),
+        Command::RefreshDirectory => handle_refresh_directory(context),
+        Command::FlagDirectoryEntries{flag, pattern} => handle_flag_directory_entries(context, flag, pattern),
+        Command::CopyMarkedEntries{dest} => handle_copy_marked_entries(context, dest),
+        Command::ListWorkspaces => handle_list_workspaces(context),
+        Command::SaveWorkspace{name, root} => handle_save_workspace(context, name, root),
+        Command::SwitchWorkspace{name} => handle_switch_workspace(context, name),
+        Command::LeaveWorkspace => handle_leave_workspace(context),
+        Command::DeleteWorkspace{name} => handle_delete_workspace(context, name),
+        Command::ShowMemoryUsage => handle_show_memory_usage(context),
+        Command::FillParagraph{line} => handle_fill_paragraph(context, line),
+        Command::UnfillParagraph{line} => handle_unfill_paragraph(context, line),
+        Command::CheckSpelling => handle_check_spelling(context),
+        Command::NextMisspelling => handle_next_misspelling(context),
+        Command::PreviousMisspelling => handle_previous_misspelling(context),
+        Command::AddToPersonalWords{word} => handle_add_to_personal_words(context, word),
+        Command::NarrowToLines{first_line, last_line} => handle_narrow_to_lines(context, first_line, last_line),
+        Command::Widen => handle_widen(context),
+        Command::MoveToStart => handle_move_to_start(context),
+        Command::MoveToEnd => handle_move_to_end(context),
+        Command::GotoLine{line} => handle_goto_line(context, line),
+        Command::SearchForward{needle} => handle_search_forward(context, needle),
+        Command::SearchBackward{needle} => handle_search_backward(context, needle),
+        Command::ReplaceAll{needle, replacement} => handle_replace_all(context, needle, replacement),
+        Command::Fold{line, action} => handle_fold(context, line, action),
+        Command::FoldAll => handle_fold_all(context),
+        Command::UnfoldAll => handle_unfold_all(context)
     }
 
     false
@@ -0,0 +1,34 @@
+use commands::with_current_buffer;
+use context::Context;
+
+pub fn handle_narrow_to_lines(context: &Context, first_line: usize, last_line: usize) {
+    with_current_buffer(context, |buffer| {
+        let start = buffer.offset_of_line(first_line);
+        let end = buffer.offset_of_line(last_line + 1);
+        buffer.narrow(start..end);
+        info!("Narrowed buffer {} to lines {} to {}", buffer.id(), first_line + 1, last_line + 1);
+    });
+}
+
+pub fn handle_widen(context: &Context) {
+    with_current_buffer(context, |buffer| {
+        buffer.widen();
+        info!("Widened buffer {}", buffer.id());
+    });
+}
+
+pub fn handle_move_to_start(context: &Context) {
+    with_current_buffer(context, |buffer| buffer.move_point_to_start());
+}
+
+pub fn handle_move_to_end(context: &Context) {
+    with_current_buffer(context, |buffer| buffer.move_point_to_end());
+}
+
+pub fn handle_goto_line(context: &Context, line: usize) {
+    with_current_buffer(context, |buffer| {
+        buffer.move_point_to_line(line);
+        let (line, column) = buffer.line_and_column(buffer.point());
+        info!("Point is at {}:{}", line + 1, column + 1);
+    });
+}
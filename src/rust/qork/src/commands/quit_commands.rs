@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use buffer::BufferId;
+use context::Context;
+use commands::file_commands::{handle_close_buffer, handle_save_buffer};
+
+/// Saves every buffer with unsaved changes, applying the edits to directory buffers. Other
+/// buffers without a filename are reported, since they need 'S' to give them one.
+pub fn handle_save_all(context: &Context) {
+    for id in unsaved_buffers(context) {
+        let has_filename = context.buffers()[id].borrow().filename().is_some();
+        if has_filename || context.directories().contains_key(&id) {
+            handle_save_buffer(context, id, false);
+        } else {
+            warn!("Buffer {} has no filename, use 'S {} <filename>' to save it", id, id);
+        }
+    }
+}
+
+/// Closes every buffer except special buffers such as *messages*. Unless `force` is true,
+/// buffers with unsaved changes are left open.
+pub fn handle_close_all(context: &Context, force: bool) {
+    let mut ids: Vec<BufferId> = context.buffers().all_buffers().iter()
+        .filter(|b| !(b.is_read_only() && b.filename().is_none()))
+        .map(|b| b.id())
+        .collect();
+    ids.sort();
+
+    for id in ids {
+        handle_close_buffer(context, id, force);
+    }
+}
+
+/// Starts quitting. Returns true if Qork can quit straight away, either because `force` is
+/// true or because there are no unsaved changes. Otherwise the user is asked about each
+/// modified buffer in turn, and their answers are given to `answer_quit_prompt`.
+pub fn handle_quit(context: &Context, force: bool) -> bool {
+    let unsaved = unsaved_buffers(context);
+    if force || unsaved.is_empty() {
+        if !unsaved.is_empty() {
+            warn!("Quitting without saving {} modified buffers", unsaved.len());
+        }
+        info!("Quitting");
+        return true;
+    }
+
+    let titles: Vec<String> = unsaved.iter()
+        .map(|&id| format!("{} ({})", id, context.buffers()[id].borrow().title()))
+        .collect();
+    info!("These buffers have unsaved changes: {}", titles.join(", "));
+
+    *context.quit_queue() = Some(unsaved.into_iter().collect::<VecDeque<_>>());
+    ask_next(context)
+}
+
+/// Handles the answer to "save, discard or cancel?" for the first buffer in the quit queue.
+/// Returns true once every buffer has been dealt with and Qork should quit.
+pub fn answer_quit_prompt(context: &Context, answer: &str) -> bool {
+    let id = match context.quit_queue().as_ref().and_then(|q| q.front().cloned()) {
+        Some(id) => id,
+        None => return false
+    };
+
+    match answer.trim() {
+        "s" => {
+            handle_save_buffer(context, id, false);
+            if context.buffers().get(id).map_or(false, |rc| rc.borrow().is_changed()) {
+                warn!("Quit cancelled because buffer {} could not be saved", id);
+                *context.quit_queue() = None;
+                return false;
+            }
+        },
+        "d" => info!("Discarding the changes to buffer {}", id),
+        "c" => {
+            info!("Quit cancelled");
+            *context.quit_queue() = None;
+            return false;
+        },
+        _ => {
+            info!("Please answer s, d or c");
+            return false;
+        }
+    }
+
+    if let Some(ref mut q) = *context.quit_queue() {
+        q.pop_front();
+    }
+    ask_next(context)
+}
+
+/// Prompts about the next buffer in the quit queue, or finishes quitting if there are none.
+fn ask_next(context: &Context) -> bool {
+    let next = context.quit_queue().as_ref().and_then(|q| q.front().cloned());
+    match next {
+        Some(id) => {
+            let title = context.buffers()[id].borrow().title().to_string();
+            info!("Buffer {} ({}) has unsaved changes. [s]ave, [d]iscard or [c]ancel?", id, title);
+            false
+        },
+        None => {
+            *context.quit_queue() = None;
+            info!("Quitting");
+            true
+        }
+    }
+}
+
+/// Handles standard input being closed, which ends Qork without a chance to ask about unsaved
+/// changes. Rather than losing them, the recovery files of modified buffers are brought up to
+/// date and kept, so they are offered for recovery next time. Returns true if nothing was
+/// unsaved, in which case the recovery files can be deleted as on a normal quit.
+pub fn handle_input_closed(context: &Context) -> bool {
+    let unsaved = unsaved_buffers(context);
+    if unsaved.is_empty() {
+        info!("Input closed, quitting");
+        return true;
+    }
+
+    for &id in &unsaved {
+        context.write_recovery(id);
+    }
+    warn!("Input closed with {} modified buffers, their changes have been kept for recovery", unsaved.len());
+    false
+}
+
+/// The buffers with unsaved changes, in id order.
+fn unsaved_buffers(context: &Context) -> Vec<BufferId> {
+    let mut ids: Vec<BufferId> = context.buffers().all_buffers().iter()
+        .filter(|b| b.is_changed())
+        .map(|b| b.id())
+        .collect();
+    ids.sort();
+    ids
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs as stdfs;
+    use libc::pid_t;
+    use fs::TestDir;
+    use recovery::{find_orphans, RecoveryJournal};
+
+    fn modified_file(context: &Context, dir: &TestDir, name: &str) -> BufferId {
+        let path = dir.join(name);
+        stdfs::write(&path, "old").unwrap();
+        let id = context.buffers().open_file(&mut context.buffer_factory(), &path).unwrap();
+        assert!(context.buffers()[id].borrow_mut().replace(0..0, "new "));
+        id
+    }
+
+    fn modified_untitled(context: &Context) -> BufferId {
+        let id = context.buffers().new_buffer(&mut context.buffer_factory(), "notes");
+        assert!(context.buffers()[id].borrow_mut().replace(0..0, "notes"));
+        id
+    }
+
+    #[test]
+    fn quit_without_unsaved_changes_quits_straight_away() {
+        let context = Context::new_for_tests();
+        assert!(handle_quit(&context, false));
+        assert!(!context.is_quitting());
+    }
+
+    #[test]
+    fn force_quit_ignores_unsaved_changes() {
+        let context = Context::new_for_tests();
+        modified_untitled(&context);
+        assert!(handle_quit(&context, true));
+        assert!(!context.is_quitting());
+    }
+
+    #[test]
+    fn quit_prompt_asks_about_each_unsaved_buffer() {
+        let dir = TestDir::new();
+        let context = Context::new_for_tests();
+        let file = modified_file(&context, &dir, "a.txt");
+        let untitled = modified_untitled(&context);
+
+        assert!(!handle_quit(&context, false));
+        assert!(context.is_quitting());
+        assert!(!answer_quit_prompt(&context, "x"));
+        assert_eq!(context.quit_queue().as_ref().unwrap().front(), Some(&file));
+
+        assert!(!answer_quit_prompt(&context, "s"));
+        assert_eq!(stdfs::read_to_string(dir.join("a.txt")).unwrap(), "new old");
+        assert_eq!(context.quit_queue().as_ref().unwrap().front(), Some(&untitled));
+
+        assert!(answer_quit_prompt(&context, "d"));
+        assert!(!context.is_quitting());
+    }
+
+    #[test]
+    fn quit_prompt_can_be_cancelled() {
+        let context = Context::new_for_tests();
+        let id = modified_untitled(&context);
+
+        assert!(!handle_quit(&context, false));
+        assert!(!answer_quit_prompt(&context, "c"));
+        assert!(!context.is_quitting());
+        assert!(context.buffers()[id].borrow().is_changed());
+        assert!(!answer_quit_prompt(&context, "d"));
+    }
+
+    #[test]
+    fn quit_is_cancelled_if_a_buffer_cannot_be_saved() {
+        let context = Context::new_for_tests();
+        modified_untitled(&context);
+
+        assert!(!handle_quit(&context, false));
+        assert!(!answer_quit_prompt(&context, "s"));
+        assert!(!context.is_quitting());
+    }
+
+    #[test]
+    fn input_closed_keeps_recovery_files_for_unsaved_buffers() {
+        let dir = TestDir::new();
+        let context = Context::new_for_tests();
+        assert!(handle_input_closed(&context));
+
+        // A journal with a dead pid, so that its files look like those of an earlier Qork.
+        context.set_recovery_journal(RecoveryJournal::new(Some(dir.join("r")), pid_t::max_value(), 0, 1000));
+        modified_file(&context, &dir, "a.txt");
+        assert!(!handle_input_closed(&context));
+
+        let orphans = find_orphans(&dir.join("r"));
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].load_text().unwrap(), "new old");
+    }
+
+    #[test]
+    fn save_all_saves_buffers_with_filenames() {
+        let dir = TestDir::new();
+        let context = Context::new_for_tests();
+        let file = modified_file(&context, &dir, "a.txt");
+        let untitled = modified_untitled(&context);
+
+        handle_save_all(&context);
+        assert_eq!(stdfs::read_to_string(dir.join("a.txt")).unwrap(), "new old");
+        assert!(!context.buffers()[file].borrow().is_changed());
+        assert!(context.buffers()[untitled].borrow().is_changed());
+    }
+
+    #[test]
+    fn close_all_keeps_special_and_unsaved_buffers_unless_forced() {
+        let dir = TestDir::new();
+        let context = Context::new_for_tests();
+        stdfs::write(dir.join("b.txt"), "b").unwrap();
+        let saved = context.buffers().open_file(&mut context.buffer_factory(), dir.join("b.txt")).unwrap();
+        let unsaved = modified_untitled(&context);
+
+        handle_close_all(&context, false);
+        assert!(context.buffers().get(saved).is_none());
+        assert!(context.buffers().get(unsaved).is_some());
+        assert!(context.buffers().get(context.messages_buffer_id()).is_some());
+
+        handle_close_all(&context, true);
+        assert!(context.buffers().get(unsaved).is_none());
+        assert!(context.buffers().get(context.messages_buffer_id()).is_some());
+    }
+}
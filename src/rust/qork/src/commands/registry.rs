@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use context::Context;
+use commands::command_line::{split_command, ArgKind, Args, ParseError};
+
+/// How many values an argument takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Required,
+    Optional,
+    /// Zero or more values, taking all the remaining words. Only valid for the last argument.
+    /// A rest argument of kind String is free text, and takes the rest of the line as it was
+    /// typed rather than as words.
+    Rest
+}
+
+/// Describes one argument of a command, for checking command lines, for help and for
+/// completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub arity: Arity
+}
+
+impl ArgSpec {
+    pub fn required(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec { name, kind, arity: Arity::Required }
+    }
+
+    pub fn optional(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec { name, kind, arity: Arity::Optional }
+    }
+
+    pub fn rest(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec { name, kind, arity: Arity::Rest }
+    }
+
+    /// Whether the argument is free text, which is not split into words.
+    pub fn is_text(&self) -> bool {
+        self.arity == Arity::Rest && self.kind == ArgKind::String
+    }
+
+    /// Checks that a word is a valid value for the argument.
+    fn check(&self, command: &str, word: &str) -> Result<(), ParseError> {
+        let is_valid = match self.kind {
+            ArgKind::Int => word.parse::<usize>().is_ok(),
+            ArgKind::BufferId => word.parse::<i64>().map(|id| id >= 0).unwrap_or(false),
+            ArgKind::Path => !word.is_empty(),
+            ArgKind::Command | ArgKind::String => true
+        };
+        if is_valid {
+            Ok(())
+        } else {
+            Err(ParseError::InvalidArgument {
+                command: String::from(command), name: self.name, kind: self.kind, value: String::from(word)
+            })
+        }
+    }
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.name.replace(' ', "-");
+        match self.arity {
+            Arity::Required => write!(f, "<{}>", name),
+            Arity::Optional => write!(f, "[{}]", name),
+            Arity::Rest => write!(f, "[{}...]", name)
+        }
+    }
+}
+
+/// Runs a command whose arguments have been checked against its `ArgSpec`s. Returns true if
+/// Qork should quit.
+pub type Handler = Rc<dyn Fn(&Context, Args) -> Result<bool, ParseError>>;
+
+/// A command which can be typed at the command line: its name, any short aliases, its
+/// documentation, the arguments it takes and the function that runs it.
+pub struct CommandSpec {
+    name: String,
+    aliases: Vec<String>,
+    doc: String,
+    args: Vec<ArgSpec>,
+    handler: Handler,
+    /// Whether command lines using this command are kept in the command history.
+    is_recorded: bool
+}
+
+impl CommandSpec {
+    /// The first line of `doc` is used as a summary when the commands are listed.
+    pub fn new<F>(name: &str, doc: &str, args: Vec<ArgSpec>, handler: F) -> CommandSpec
+        where F: Fn(&Context, Args) -> Result<bool, ParseError> + 'static
+    {
+        CommandSpec {
+            name: String::from(name),
+            aliases: Vec::new(),
+            doc: String::from(doc),
+            args: args,
+            handler: Rc::new(handler),
+            is_recorded: true
+        }
+    }
+
+    /// Adds another, usually shorter, name for the command.
+    pub fn alias(mut self, alias: &str) -> CommandSpec {
+        self.aliases.push(String::from(alias));
+        self
+    }
+
+    /// Keeps the command out of the command history, for commands such as those which move
+    /// through the history itself.
+    pub fn not_recorded(mut self) -> CommandSpec {
+        self.is_recorded = false;
+        self
+    }
+
+    pub fn is_recorded(&self) -> bool {
+        self.is_recorded
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    pub fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    pub fn summary(&self) -> &str {
+        self.doc.lines().next().unwrap_or("")
+    }
+
+    pub fn args(&self) -> &[ArgSpec] {
+        &self.args
+    }
+
+    /// The command and its arguments, such as "save-as <id> <filename>".
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in &self.args {
+            usage.push(' ');
+            usage.push_str(&arg.to_string());
+        }
+        usage
+    }
+
+    /// The spec of the argument at `index`, counting the rest argument as repeating.
+    pub fn arg_at(&self, index: usize) -> Option<&ArgSpec> {
+        self.args.get(index).or_else(|| self.args.last().filter(|a| a.arity == Arity::Rest))
+    }
+
+    /// Checks the number and kinds of the words given as arguments, up to any free text.
+    fn check_args(&self, args: &Args) -> Result<(), ParseError> {
+        let mut args = args.clone();
+        let mut count = 0;
+        while !args.is_empty() {
+            match self.arg_at(count) {
+                Some(spec) if spec.is_text() => break,
+                Some(spec) => {
+                    let word = args.string(spec.name)?;
+                    spec.check(args.command(), &word)?;
+                },
+                None => return args.finish()
+            }
+            count += 1;
+        }
+
+        let command = args.command();
+        match self.args.iter().skip(count).find(|a| a.arity == Arity::Required) {
+            Some(missing) => Err(ParseError::MissingArgument {
+                command: String::from(command), name: missing.name, kind: missing.kind
+            }),
+            None => Ok(())
+        }
+    }
+
+    pub fn run(&self, context: &Context, args: Args) -> Result<bool, ParseError> {
+        (self.handler)(context, args)
+    }
+}
+
+/// All the commands which can be typed, found by name or alias. Qork registers its built-in
+/// commands at startup, and other modules, the configuration and plugins can add more.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Rc<CommandSpec>>,
+    /// Every name and alias, mapped to the name of its command.
+    names: BTreeMap<String, String>
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry::default()
+    }
+
+    /// Adds a command, replacing any command with the same name. It is an error for the name
+    /// or one of the aliases to belong to a different command.
+    pub fn register(&mut self, spec: CommandSpec) -> Result<(), String> {
+        for name in Some(&spec.name).into_iter().chain(spec.aliases.iter()) {
+            if name.is_empty() || name.chars().any(char::is_whitespace) {
+                return Err(format!("{:?} is not a valid command name", name));
+            }
+            match self.names.get(name) {
+                Some(owner) if *owner != spec.name => return Err(format!("{} is already the name of the {} command", name, owner)),
+                _ => {}
+            }
+        }
+
+        if let Some(old) = self.commands.remove(&spec.name) {
+            for alias in &old.aliases {
+                self.names.remove(alias);
+            }
+        }
+        for name in Some(&spec.name).into_iter().chain(spec.aliases.iter()) {
+            self.names.insert(name.clone(), spec.name.clone());
+        }
+        self.commands.insert(spec.name.clone(), Rc::new(spec));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Finds a command by its name or one of its aliases.
+    pub fn find(&self, name: &str) -> Option<Rc<CommandSpec>> {
+        self.names.get(name).and_then(|n| self.commands.get(n)).cloned()
+    }
+
+    /// The commands, in name order.
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<CommandSpec>> {
+        self.commands.values()
+    }
+
+    /// Every name and alias, in order.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.names.keys()
+    }
+
+    /// Splits a command line into its command and arguments, checking the arguments against
+    /// the command's spec. Returns None for a blank line.
+    pub fn parse(&self, line: &str) -> Result<Option<(Rc<CommandSpec>, Args)>, ParseError> {
+        let (name, rest) = match split_command(line)? {
+            Some(parsed) => parsed,
+            None => return Ok(None)
+        };
+
+        let spec = self.find(&name).ok_or_else(|| ParseError::UnknownCommand(name.clone()))?;
+        let args = Args::new(name, rest);
+        spec.check_args(&args)?;
+        Ok(Some((spec, args)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, args: Vec<ArgSpec>) -> CommandSpec {
+        CommandSpec::new(name, "Does something.\nAt length.", args, |_, _| Ok(false))
+    }
+
+    #[test]
+    fn register_finds_commands_by_name_and_alias() {
+        let mut registry = CommandRegistry::new();
+        registry.register(spec("save", vec![]).alias("s")).unwrap();
+        registry.register(spec("open", vec![]).alias("o")).unwrap();
+
+        assert_eq!(registry.find("s").map(|c| c.name().to_string()), Some(String::from("save")));
+        assert!(registry.find("x").is_none());
+        assert_eq!(registry.names().cloned().collect::<Vec<_>>(), vec!["o", "open", "s", "save"]);
+        assert!(registry.register(spec("sync", vec![]).alias("s")).is_err());
+
+        // Replacing a command drops its old aliases.
+        registry.register(spec("save", vec![]).alias("w")).unwrap();
+        assert!(registry.find("s").is_none());
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn parse_checks_arguments_against_spec() {
+        let mut registry = CommandRegistry::new();
+        registry.register(spec("save-as", vec![
+            ArgSpec::required("id", ArgKind::BufferId), ArgSpec::required("filename", ArgKind::Path)
+        ]).alias("S")).unwrap();
+        registry.register(spec("search", vec![ArgSpec::rest("words", ArgKind::String)])).unwrap();
+
+        assert!(registry.parse("  ").unwrap().is_none());
+        assert!(registry.parse("S 1 'a b.txt'").is_ok());
+        assert_eq!(registry.parse("nope").err(), Some(ParseError::UnknownCommand(String::from("nope"))));
+        assert_eq!(registry.parse("S x a.txt").err(), Some(ParseError::InvalidArgument {
+            command: String::from("S"), name: "id", kind: ArgKind::BufferId, value: String::from("x")
+        }));
+        assert_eq!(registry.parse("S 1").err(), Some(ParseError::MissingArgument {
+            command: String::from("S"), name: "filename", kind: ArgKind::Path
+        }));
+        assert!(registry.parse("S 1 a b").is_err());
+        assert!(registry.parse("search a b c").is_ok());
+
+        // Free text is taken as it was typed, so it need not be quoted.
+        let (_, mut args) = registry.parse("search  it's  a\\b ").unwrap().unwrap();
+        assert_eq!(args.rest(), "it's  a\\b");
+        assert_eq!(registry.parse("S 'a").err(), Some(ParseError::UnterminatedQuote('\'')));
+    }
+
+    #[test]
+    fn usage_and_summary_describe_the_command() {
+        let c = spec("workspace", vec![ArgSpec::optional("name", ArgKind::String), ArgSpec::rest("first line", ArgKind::Int)]);
+        assert_eq!(c.usage(), "workspace [name] [first-line...]");
+        assert_eq!(c.summary(), "Does something.");
+        assert_eq!(c.arg_at(5).map(|a| a.name), Some("first line"));
+    }
+}
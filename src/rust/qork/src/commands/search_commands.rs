@@ -0,0 +1,44 @@
+use commands::with_current_buffer;
+use context::Context;
+
+/// Searches forward from the point, leaving the point after the match as Emacs does.
+pub fn handle_search_forward(context: &Context, needle: String) {
+    with_current_buffer(context, |buffer| {
+        let point = buffer.point();
+        match buffer.find_forward(&needle, point) {
+            Some(r) => {
+                buffer.set_point(r.end);
+                let (line, column) = buffer.line_and_column(r.start);
+                info!("Found '{}' at {}:{}", needle, line + 1, column + 1);
+            },
+            None => info!("'{}' not found", needle)
+        }
+    });
+}
+
+/// Searches backward from the point, leaving the point at the start of the match.
+pub fn handle_search_backward(context: &Context, needle: String) {
+    with_current_buffer(context, |buffer| {
+        let point = buffer.point();
+        match buffer.find_backward(&needle, point) {
+            Some(r) => {
+                buffer.set_point(r.start);
+                let (line, column) = buffer.line_and_column(r.start);
+                info!("Found '{}' at {}:{}", needle, line + 1, column + 1);
+            },
+            None => info!("'{}' not found", needle)
+        }
+    });
+}
+
+pub fn handle_replace_all(context: &Context, needle: String, replacement: String) {
+    with_current_buffer(context, |buffer| {
+        if buffer.is_read_only() {
+            warn!("Buffer {} is read-only", buffer.id());
+            return;
+        }
+
+        let count = buffer.replace_all(&needle, &replacement);
+        info!("Replaced {} occurrences of '{}' with '{}'", count, needle, replacement);
+    });
+}
@@ -0,0 +1,82 @@
+use context::Context;
+use spelling::{next_misspelling, previous_misspelling, syntax_for_path, Misspelling};
+
+/// Spell checks the current buffer, returning the misspellings found, or None if there is no
+/// current buffer or no dictionaries could be loaded.
+fn current_buffer_misspellings(context: &Context) -> Option<Vec<Misspelling>> {
+    let checker = context.spell_checker();
+    if !checker.has_dictionaries() {
+        warn!("No spelling dictionaries are loaded");
+        return None;
+    }
+
+    let bc = context.buffers();
+    match bc.get(bc.current_buffer()) {
+        Some(rc) => {
+            let buffer = rc.borrow();
+            let syntax = buffer.filename().as_ref().and_then(|f| syntax_for_path(f));
+            let mut state = context.state();
+            Some(checker.misspellings(&buffer.text(), syntax, state.personal_words()))
+        },
+        None => {
+            warn!("There is no current buffer");
+            None
+        }
+    }
+}
+
+pub fn handle_check_spelling(context: &Context) {
+    if let Some(misspellings) = current_buffer_misspellings(context) {
+        let bc = context.buffers();
+        let buffer = bc[bc.current_buffer()].borrow();
+        info!("Found {} misspellings in buffer {}", misspellings.len(), buffer.id());
+        for m in &misspellings {
+            let (line, column) = buffer.line_and_column(m.range.start);
+            info!("{}:{}: {}", line + 1, column + 1, m.word);
+        }
+    }
+}
+
+/// Moves to the next misspelling, which may start at the point unless the point was put there
+/// by moving to that misspelling.
+pub fn handle_next_misspelling(context: &Context) {
+    move_to_misspelling(context, |misspellings, point, moved_here| {
+        next_misspelling(misspellings, if moved_here { point + 1 } else { point })
+    });
+}
+
+pub fn handle_previous_misspelling(context: &Context) {
+    move_to_misspelling(context, |misspellings, point, _| previous_misspelling(misspellings, point));
+}
+
+/// Moves the point of the current buffer to the misspelling chosen by `find`, and reports
+/// the suggested corrections for it. `find` is also told whether the point is where the last
+/// move to a misspelling left it.
+fn move_to_misspelling<F>(context: &Context, find: F)
+    where F: for<'a> Fn(&'a [Misspelling], usize, bool) -> Option<&'a Misspelling>
+{
+    if let Some(misspellings) = current_buffer_misspellings(context) {
+        let bc = context.buffers();
+        let mut buffer = bc[bc.current_buffer()].borrow_mut();
+        let moved_here = *context.misspelling_point() == Some((buffer.id(), buffer.point()));
+
+        match find(&misspellings, buffer.point(), moved_here) {
+            Some(m) => {
+                buffer.set_point(m.range.start);
+                *context.misspelling_point() = Some((buffer.id(), m.range.start));
+                let (line, column) = buffer.line_and_column(m.range.start);
+                let suggestions = context.spell_checker().suggest(&m.word);
+                info!("{}:{}: {} (suggestions: {})", line + 1, column + 1, m.word, suggestions.join(", "));
+            },
+            None => info!("No more misspellings in buffer {}", buffer.id())
+        }
+    }
+}
+
+pub fn handle_add_to_personal_words(context: &Context, word: String) {
+    if context.state().personal_words().insert(word.clone()) {
+        info!("Added '{}' to the personal word list", word);
+    } else {
+        info!("'{}' is already in the personal word list", word);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use context::Context;
+
+/// Lists the workspaces, marking the active one with '*'.
+pub fn handle_list_workspaces(context: &Context) {
+    let active = context.workspace().clone();
+    let mut state = context.state();
+    let workspaces = state.workspaces();
+    if workspaces.is_empty() {
+        info!("There are no workspaces, use 'ws save <name>' to create one");
+        return;
+    }
+
+    for (name, workspace) in workspaces.iter() {
+        let marker = if active.as_ref() == Some(name) { '*' } else { ' ' };
+        info!("{} {} {:?} ({} files)", marker, name, workspace.root, workspace.files.len());
+    }
+}
+
+/// Saves the visible buffers as the workspace `name`, replacing any workspace of that name,
+/// and makes it the active workspace. The root directory is `root` if given, otherwise the
+/// workspace keeps its existing root, or a new one uses the working directory. The state of
+/// any other active workspace is remembered first.
+pub fn handle_save_workspace(context: &Context, name: String, root: Option<String>) {
+    context.store_workspace();
+    let root = match root {
+        Some(r) => context.absolute_path(&r),
+        None => {
+            let existing = context.state().workspaces().get(&name).map(|w| w.root.clone());
+            existing.unwrap_or_else(|| context.working_dir())
+        }
+    };
+    if !root.is_dir() {
+        warn!("The workspace root {:?} is not a directory", root);
+        return;
+    }
+
+    let workspace = context.capture_workspace(root);
+    let num_files = workspace.files.len();
+    context.state().workspaces().set(&name, workspace);
+
+    {
+        let mut bc = context.buffers();
+        let visible: HashSet<_> = bc.visible_history().into_iter().collect();
+        bc.set_visible_buffers(Some(visible));
+    }
+    *context.workspace() = Some(name.clone());
+    info!("Saved workspace {} with {} files", name, num_files);
+}
+
+/// Makes `name` the active workspace. The state of the workspace being left is remembered,
+/// then the files of the new workspace are opened (or reused if they are already open), all
+/// other buffers are hidden, and relative filenames are resolved against the workspace's root.
+pub fn handle_switch_workspace(context: &Context, name: String) {
+    let workspace = match context.state().workspaces().get(&name).cloned() {
+        Some(w) => w,
+        None => { warn!("There is no workspace called {}", name); return; }
+    };
+    context.store_workspace();
+
+    let mut visible = HashSet::new();
+    for filename in &workspace.files {
+        if !filename.exists() {
+            warn!("{:?} in workspace {} no longer exists", filename, name);
+            continue;
+        }
+        let result = context.buffers().open_file(&mut context.buffer_factory(), filename);
+        match result {
+            Ok(id) => { visible.insert(id); },
+            Err(e) => warn!("Could not open {:?}: {}", filename, e)
+        }
+    }
+
+    {
+        let mut bc = context.buffers();
+        bc.set_visible_buffers(Some(visible));
+        let current = workspace.current.as_ref()
+            .and_then(|f| bc.find_by_filename(f).map(|rc| rc.borrow().id()))
+            .filter(|&id| bc.is_visible(id))
+            .or_else(|| bc.visible_history().first().cloned());
+        if let Some(id) = current {
+            bc.set_current_buffer(id);
+        }
+    }
+
+    *context.workspace() = Some(name.clone());
+    info!("Switched to workspace {} in {:?}", name, workspace.root);
+}
+
+/// Remembers the state of the active workspace, then makes every buffer visible again.
+pub fn handle_leave_workspace(context: &Context) {
+    context.store_workspace();
+    context.buffers().set_visible_buffers(None);
+    match context.workspace().take() {
+        Some(name) => info!("Left workspace {}", name),
+        None => info!("There is no active workspace")
+    }
+}
+
+/// Deletes a workspace, leaving it first if it is active. Its buffers stay open.
+pub fn handle_delete_workspace(context: &Context, name: String) {
+    let is_active = context.workspace().as_ref() == Some(&name);
+    if is_active {
+        handle_leave_workspace(context);
+    }
+
+    if context.state().workspaces().remove(&name) {
+        info!("Deleted workspace {}", name);
+    } else {
+        warn!("There is no workspace called {}", name);
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
 use std::io::prelude::*;
 use toml;
 use fs::{BaseDir, ConfigDir};
+use backup::BackupStyle;
+use buffer::TitleStyle;
+use text::FoldMethod;
 
 // Stores the configuration. Will be read from config.toml. Any values not
 // present in the file will be defaulted using the 'default' method below.
@@ -8,23 +12,135 @@ use fs::{BaseDir, ConfigDir};
 #[serde(default)]
 pub struct Configuration {
     max_mru_items: usize,
+    fill_column: usize,
+    spell_languages: Vec<String>,
+    max_messages_lines: usize,
+    fold_method: FoldMethod,
+    save_folds: bool,
+    backup_style: BackupStyle,
+    max_backups: usize,
+    max_backup_age_days: u64,
+    autosave_interval_secs: u64,
+    autosave_edits: usize,
+    project_root_markers: Vec<String>,
+    title_style: TitleStyle,
+    max_buffer_memory_mb: usize,
+    commands: BTreeMap<String, String>,
+    max_command_history: usize,
+    command_history_exclude: Vec<String>,
 }
 
 impl Default for Configuration {
     fn default() -> Configuration {
         Configuration {
-            max_mru_items: 20
+            max_mru_items: 20,
+            fill_column: 80,
+            spell_languages: vec![String::from("en_US")],
+            max_messages_lines: 1000,
+            fold_method: FoldMethod::Indent,
+            save_folds: true,
+            backup_style: BackupStyle::Tilde,
+            max_backups: 10,
+            max_backup_age_days: 30,
+            autosave_interval_secs: 30,
+            autosave_edits: 200,
+            project_root_markers: vec![String::from(".git"), String::from(".hg"), String::from(".svn"), String::from("Cargo.toml")],
+            title_style: TitleStyle::default(),
+            max_buffer_memory_mb: 0,
+            commands: BTreeMap::new(),
+            max_command_history: 500,
+            command_history_exclude: Vec::new()
         }
     }
 }
 
-const CONFIG_FILE : &'static str = "config.toml";
+pub const CONFIG_FILE : &'static str = "config.toml";
+pub const LOGGING_FILE : &'static str = "logging.toml";
 
 impl Configuration {
     pub fn max_mru_items(&self) -> usize {
         self.max_mru_items
     }
 
+    pub fn fill_column(&self) -> usize {
+        self.fill_column
+    }
+
+    pub fn spell_languages(&self) -> &Vec<String> {
+        &self.spell_languages
+    }
+
+    pub fn max_messages_lines(&self) -> usize {
+        self.max_messages_lines
+    }
+
+    pub fn fold_method(&self) -> FoldMethod {
+        self.fold_method
+    }
+
+    /// Whether collapsed folds are remembered between sessions.
+    pub fn save_folds(&self) -> bool {
+        self.save_folds
+    }
+
+    pub fn backup_style(&self) -> BackupStyle {
+        self.backup_style
+    }
+
+    /// The number of numbered backups kept per file, 0 for no limit.
+    pub fn max_backups(&self) -> usize {
+        self.max_backups
+    }
+
+    /// The age after which numbered backups are deleted, 0 for no limit.
+    pub fn max_backup_age_days(&self) -> u64 {
+        self.max_backup_age_days
+    }
+
+    /// How often modified buffers are written to recovery files, 0 to disable.
+    pub fn autosave_interval_secs(&self) -> u64 {
+        self.autosave_interval_secs
+    }
+
+    /// The number of edits after which a buffer is written to its recovery file, 0 to disable.
+    pub fn autosave_edits(&self) -> usize {
+        self.autosave_edits
+    }
+
+    /// Files or directories whose presence marks the root of a project, which is one of the
+    /// places relative filenames are looked for.
+    pub fn project_root_markers(&self) -> &Vec<String> {
+        &self.project_root_markers
+    }
+
+    /// How the titles of buffers on files with the same name are made unique.
+    pub fn title_style(&self) -> TitleStyle {
+        self.title_style
+    }
+
+    /// The memory the buffers may use before unmodified, least recently used buffers are
+    /// evicted and reloaded when next needed, 0 for no limit.
+    pub fn max_buffer_memory_mb(&self) -> usize {
+        self.max_buffer_memory_mb
+    }
+
+    /// Extra commands, each a name and the command line it runs. For example
+    /// `ff = "ls filter modified"` adds an `ff` command. A command can only run configured
+    /// commands whose names sort before its own.
+    pub fn commands(&self) -> &BTreeMap<String, String> {
+        &self.commands
+    }
+
+    pub fn max_command_history(&self) -> usize {
+        self.max_command_history
+    }
+
+    /// Regular expressions for command lines which are never kept in the command history,
+    /// such as commands containing passwords.
+    pub fn command_history_exclude(&self) -> &Vec<String> {
+        &self.command_history_exclude
+    }
+
     pub fn load_user_configuration(cd: &ConfigDir) -> Configuration {
         let _timer = timer!("load_user_configuration");
 
@@ -1,33 +1,93 @@
 use std::cell::{RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
+use std::env;
 use std::rc::Rc;
-use configuration::Configuration;
-use buffer::{BufferCollection, BufferFactory};
-use fs::ConfigDir;
+use backup::{Backups, BACKUP_DIR};
+use std::path::{Path, PathBuf};
+use configuration::{Configuration, CONFIG_FILE, LOGGING_FILE};
+use commands::{register_builtin_commands, register_configured_commands, CommandRegistry};
+use buffer::{Buffer, BufferCollection, BufferFactory, BufferId, BufferList, DirectoryListing, MemoryUsage};
+use fs::{self, BaseDir, ConfigDir, DataDir};
+use messages::{MessageSink, MESSAGES_BUFFER_TITLE};
 use persistent_state::PersistentState;
 use program_info::ProgramInfo;
+use recovery::{find_orphans, Orphan, RecoveryJournal, RECOVERY_DIR};
+use spelling::SpellChecker;
 use system_info::SystemInfo;
+use view::{FoldState, ViewCollection};
+use workspace::Workspace;
 
 /// The complete execution context of Qork.
 pub struct Context {
     system_info: SystemInfo,
     program_info: ProgramInfo,
     config_dir: ConfigDir,
+    data_dir: DataDir,
     configuration: Configuration,
     state: RefCell<PersistentState>,
     buffer_factory: RefCell<BufferFactory>,
-    buffers: Rc<RefCell<BufferCollection>>
+    buffers: Rc<RefCell<BufferCollection>>,
+    views: RefCell<ViewCollection>,
+    commands: RefCell<CommandRegistry>,
+    buffer_list: RefCell<BufferList>,
+    // The listings shown in directory buffers, by buffer id.
+    directories: RefCell<HashMap<BufferId, DirectoryListing>>,
+    // The name of the active workspace, if there is one.
+    workspace: RefCell<Option<String>>,
+    // The buffers with unsaved changes still to be asked about before quitting, or None when
+    // the user is not trying to quit.
+    quit_queue: RefCell<Option<VecDeque<BufferId>>>,
+    // Whether a line from the command history is being run.
+    running_history: RefCell<bool>,
+    // The buffer and offset that the last move to a misspelling went to.
+    misspelling_point: RefCell<Option<(BufferId, usize)>>,
+    journal: RefCell<RecoveryJournal>,
+    messages: MessageSink,
+    messages_buffer_id: BufferId,
+    // Loading dictionaries is relatively expensive, so this is not done until it is first needed.
+    spell_checker: RefCell<Option<SpellChecker>>
 }
 
 impl Context {
-    pub fn new(pi: ProgramInfo, config_dir: ConfigDir, config: Configuration, state: PersistentState) -> Context {
+    pub fn new(pi: ProgramInfo, config_dir: ConfigDir, data_dir: DataDir, config: Configuration,
+               state: PersistentState, messages: MessageSink) -> Context {
+        let mut buffer_factory = BufferFactory::new();
+        let mut buffers = BufferCollection::new();
+        let backup_dir = if data_dir.is_valid() { Some(data_dir.home().join(BACKUP_DIR)) } else { None };
+        buffers.set_backups(Backups::new(config.backup_style(), backup_dir, config.max_backups(), config.max_backup_age_days()));
+        buffers.set_title_style(config.title_style());
+        buffers.set_memory_limit(config.max_buffer_memory_mb() * 1024 * 1024);
+        let recovery_dir = if data_dir.is_valid() { Some(data_dir.home().join(RECOVERY_DIR)) } else { None };
+        let journal = RecoveryJournal::new(recovery_dir, pi.pid,
+                                           config.autosave_interval_secs(), config.autosave_edits());
+        let mut commands = CommandRegistry::new();
+        register_builtin_commands(&mut commands);
+        register_configured_commands(&mut commands, config.commands());
+        let messages_buffer = buffer_factory.new_special_buffer(MESSAGES_BUFFER_TITLE);
+        let messages_buffer_id = messages_buffer.id();
+        buffers.insert(messages_buffer);
+
         Context {
             system_info: SystemInfo::new(),
             program_info: pi,
             config_dir: config_dir,
+            data_dir: data_dir,
             configuration: config,
             state: RefCell::new(state),
-            buffer_factory: RefCell::new(BufferFactory::new()),
-            buffers: Rc::new(RefCell::new(BufferCollection::new()))
+            buffer_factory: RefCell::new(buffer_factory),
+            buffers: Rc::new(RefCell::new(buffers)),
+            views: RefCell::new(ViewCollection::new()),
+            commands: RefCell::new(commands),
+            buffer_list: RefCell::new(BufferList::new()),
+            directories: RefCell::new(HashMap::new()),
+            workspace: RefCell::new(None),
+            quit_queue: RefCell::new(None),
+            running_history: RefCell::new(false),
+            misspelling_point: RefCell::new(None),
+            journal: RefCell::new(journal),
+            messages: messages,
+            messages_buffer_id: messages_buffer_id,
+            spell_checker: RefCell::new(None)
         }
     }
 
@@ -43,6 +103,10 @@ impl Context {
         &self.config_dir
     }
 
+    pub fn data_dir(&self) -> &DataDir {
+        &self.data_dir
+    }
+
     pub fn configuration(&self) -> &Configuration {
         &self.configuration
     }
@@ -58,5 +122,259 @@ impl Context {
     pub fn buffers(&self) -> RefMut<BufferCollection> {
         self.buffers.borrow_mut()
     }
+
+    /// The commands which can be typed at the command line.
+    pub fn commands(&self) -> RefMut<CommandRegistry> {
+        self.commands.borrow_mut()
+    }
+
+    /// The buffers still to be asked about before quitting. None unless a quit is in progress.
+    pub fn quit_queue(&self) -> RefMut<Option<VecDeque<BufferId>>> {
+        self.quit_queue.borrow_mut()
+    }
+
+    pub fn is_quitting(&self) -> bool {
+        self.quit_queue.borrow().is_some()
+    }
+
+    /// Whether a line from the command history is being run, which stops a line from running
+    /// history lines itself.
+    pub fn running_history(&self) -> RefMut<bool> {
+        self.running_history.borrow_mut()
+    }
+
+    /// Where the point was last moved to by next-misspelling or previous-misspelling.
+    pub fn misspelling_point(&self) -> RefMut<Option<(BufferId, usize)>> {
+        self.misspelling_point.borrow_mut()
+    }
+
+    /// The sorting, filtering and marks of the buffer list.
+    pub fn buffer_list(&self) -> RefMut<BufferList> {
+        self.buffer_list.borrow_mut()
+    }
+
+    /// The listings of the directory buffers. Entries for buffers which have since been
+    /// closed may remain, so look buffers up by id before using them.
+    pub fn directories(&self) -> RefMut<HashMap<BufferId, DirectoryListing>> {
+        self.directories.borrow_mut()
+    }
+
+    /// The name of the active workspace, or None if all buffers are visible.
+    pub fn workspace(&self) -> RefMut<Option<String>> {
+        self.workspace.borrow_mut()
+    }
+
+    /// The directory which relative filenames are resolved against: the root of the active
+    /// workspace, or the current directory. Switching workspaces does not change the current
+    /// directory of the process, which would move files saved under relative names.
+    pub fn working_dir(&self) -> PathBuf {
+        let root = self.workspace.borrow().as_ref()
+            .and_then(|name| self.state().workspaces().get(name).map(|w| w.root.clone()));
+        root.unwrap_or_else(|| env::current_dir().unwrap_or_default())
+    }
+
+    /// Expands '~' and environment variables in a filename typed by the user, and makes it
+    /// absolute by resolving it against the working directory.
+    pub fn absolute_path(&self, filename: &str) -> PathBuf {
+        self.working_dir().join(fs::expand_filename(filename))
+    }
+
+    /// Describes the visible buffers on files, and the current buffer, as a workspace.
+    pub fn capture_workspace(&self, root: PathBuf) -> Workspace {
+        let bc = self.buffers();
+        let files: Vec<PathBuf> = bc.visible_history().iter()
+            .filter_map(|&id| bc[id].borrow().filename().clone())
+            .collect();
+        let current = bc.get(bc.current_buffer()).and_then(|rc| rc.borrow().filename().clone());
+        Workspace { root, files, current }
+    }
+
+    /// Copies the buffers of the active workspace into the persistent state, so that they
+    /// are saved.
+    pub fn store_workspace(&self) {
+        let name = match *self.workspace.borrow() {
+            Some(ref name) => name.clone(),
+            None => return
+        };
+
+        let root = self.working_dir();
+        let workspace = self.capture_workspace(root);
+        self.state().workspaces().set(&name, workspace);
+    }
+
+    /// Returns the fold state of the view onto `buffer`, creating the view if necessary. When
+    /// a view is created for a file its collapsed folds are restored from the persistent state.
+    pub fn folds(&self, buffer: &Buffer) -> RefMut<FoldState> {
+        let mut views = self.views.borrow_mut();
+        let view_id = match views.find_by_buffer(buffer.id()) {
+            Some(view_id) => view_id,
+            None => {
+                let view_id = views.create(buffer);
+                if let Some(ref filename) = *buffer.filename() {
+                    if let Some(lines) = self.state().folds().get(filename) {
+                        views.get_mut(view_id).unwrap().folds(buffer).set_collapsed_lines(lines);
+                    }
+                }
+                view_id
+            }
+        };
+
+        RefMut::map(views, |views| views.get_mut(view_id).unwrap().folds(buffer))
+    }
+
+    /// Writes recovery files for any modified buffers that are due to be autosaved. Directory
+    /// buffers are left out, since their edits describe changes to the file system rather than
+    /// text worth recovering.
+    pub fn autosave(&self) {
+        let bc = self.buffers();
+        let buffers = bc.all_buffers();
+        let directories = self.directories();
+        self.journal.borrow_mut().autosave(buffers.iter().filter(|b| !directories.contains_key(&b.id())).map(|b| &**b));
+    }
+
+    /// Writes the recovery files for a buffer straight away, whether or not they are due. Used
+    /// when Qork has to exit without asking about unsaved changes.
+    pub fn write_recovery(&self, buffer_id: BufferId) {
+        if self.directories().contains_key(&buffer_id) {
+            return;
+        }
+        if let Some(rc) = self.buffers().get(buffer_id) {
+            if let Err(e) = self.journal.borrow_mut().write(&rc.borrow()) {
+                warn!("Could not write recovery file for buffer {}: {}", buffer_id, e);
+            }
+        }
+    }
+
+    /// Deletes the recovery files for a buffer, because it has been saved or closed.
+    pub fn discard_recovery(&self, buffer_id: BufferId) {
+        self.journal.borrow_mut().remove(buffer_id);
+    }
+
+    /// Finds recovery files left behind by Qork processes which died without cleaning up.
+    pub fn recovery_orphans(&self) -> Vec<Orphan> {
+        if self.data_dir.is_valid() {
+            find_orphans(&self.data_dir.home().join(RECOVERY_DIR))
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Deletes all the recovery files written by this process. Called on normal exit.
+    pub fn discard_all_recovery(&self) {
+        self.journal.borrow_mut().remove_all();
+    }
+
+    /// Estimates the memory used by a buffer, including the state its views keep about it.
+    pub fn memory_usage(&self, buffer_id: BufferId) -> Option<MemoryUsage> {
+        let mut usage = self.buffers().memory_usage(buffer_id)?;
+        usage.caches += self.views.borrow().memory_usage(buffer_id);
+        Some(usage)
+    }
+
+    /// Evicts buffers if they are using more memory than the configured limit.
+    pub fn enforce_memory_limit(&self) {
+        let mut bc = self.buffers();
+        if bc.memory_limit() == 0 {
+            return;
+        }
+        self.views.borrow_mut().sync(&bc);
+        let evicted = bc.enforce_memory_limit();
+        if !evicted.is_empty() {
+            info!("Evicted buffers {:?} to stay within the memory limit, they will be reloaded when needed", evicted);
+        }
+    }
+
+    /// Discards the views onto a buffer which has been closed.
+    pub fn remove_views(&self, buffer_id: BufferId) {
+        self.views.borrow_mut().remove_for_buffer(buffer_id);
+    }
+
+    /// The configuration files Qork reads at startup. They may not exist.
+    pub fn config_files(&self) -> Vec<PathBuf> {
+        if self.config_dir.is_valid() {
+            vec![self.config_dir.home().join(CONFIG_FILE), self.config_dir.home().join(LOGGING_FILE)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn is_config_file(&self, path: &Path) -> bool {
+        self.config_files().iter().any(|f| f == path)
+    }
+
+    /// The files which should be watched for changes made by other programs: those backing
+    /// buffers, and the configuration files.
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.buffers().all_buffers().iter()
+            .filter_map(|b| b.filename().clone())
+            .collect();
+        files.extend(self.config_files());
+        files
+    }
+
+    /// Copies the collapsed folds of all views onto files into the persistent state, so that
+    /// they are saved.
+    pub fn store_folds(&self) {
+        let bc = self.buffers();
+        let mut views = self.views.borrow_mut();
+        let mut state = self.state();
+
+        for view_id in views.ids() {
+            let view = views.get_mut(view_id).unwrap();
+            if let Some(rc) = bc.get(view.buffer_id()) {
+                let buffer = rc.borrow();
+                if let Some(ref filename) = *buffer.filename() {
+                    state.folds().set(filename, view.folds(&buffer).collapsed_lines());
+                }
+            }
+        }
+    }
+
+    pub fn messages_buffer_id(&self) -> BufferId {
+        self.messages_buffer_id
+    }
+
+    /// Moves any log messages received since the last call into the *messages* buffer.
+    pub fn flush_messages(&self) {
+        // Do not log anything in here, it would just generate more messages.
+        let messages = self.messages.drain();
+        if messages.is_empty() {
+            return;
+        }
+
+        let bc = self.buffers();
+        if let Some(rc) = bc.get(self.messages_buffer_id) {
+            rc.borrow_mut().append_lines_capped(&messages, self.configuration.max_messages_lines());
+        }
+    }
+
+    pub fn spell_checker(&self) -> RefMut<SpellChecker> {
+        let mut sc = self.spell_checker.borrow_mut();
+        if sc.is_none() {
+            *sc = Some(SpellChecker::load(self.configuration.spell_languages(), &self.config_dir, &self.data_dir));
+        }
+
+        RefMut::map(sc, |sc| sc.as_mut().unwrap())
+    }
 }
 
+#[cfg(test)]
+impl Context {
+    /// A context which neither loads nor saves any configuration or data, for testing commands.
+    pub fn new_for_tests() -> Context {
+        use command_line_arguments::CommandLineArguments;
+        use xdg::BaseDirectories;
+
+        let pi = ProgramInfo::with_arguments(CommandLineArguments::from_args(vec!["qork", "--no-config"]));
+        let xdg = BaseDirectories::with_prefix(::PKG_NAME).unwrap();
+        let config = Configuration::default();
+        let state = PersistentState::new(&config);
+        Context::new(pi, ConfigDir::new(xdg.clone(), false), DataDir::new(xdg, false), config, state, MessageSink::new())
+    }
+
+    /// Replaces the journal, since the test context has no data directory to write recovery
+    /// files into.
+    pub fn set_recovery_journal(&self, journal: RecoveryJournal) {
+        *self.journal.borrow_mut() = journal;
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use libc;
+
+/// What happened to a watched file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileEventKind {
+    /// The file was written, or replaced by another file (as happens with atomic saves).
+    Changed,
+    Deleted,
+    /// The file was renamed to something else.
+    Renamed
+}
+
+/// Something that happened to a watched file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileEvent {
+    File { path: PathBuf, kind: FileEventKind },
+    /// The kernel's event queue overflowed, so events were lost and every watched file should
+    /// be checked.
+    Overflow
+}
+
+/// The watched directories, and which of the files in them are of interest. Shared between
+/// the `FileWatcher` and its reader thread.
+#[derive(Default)]
+struct Watches {
+    /// The directory of each watch descriptor.
+    dirs: HashMap<libc::c_int, PathBuf>,
+    /// The watch descriptor of each directory.
+    wds: HashMap<PathBuf, libc::c_int>,
+    /// The files as they were given to `sync`.
+    requested: HashSet<PathBuf>,
+    /// The watched files, with their directories canonicalised to match the directories of
+    /// the watches, mapped to the paths they were given as.
+    files: HashMap<PathBuf, PathBuf>
+}
+
+/// Watches files for changes using Linux inotify, sending a `FileEvent` down a channel when
+/// one of them changes. The directories containing the files are watched rather than the
+/// files themselves, because a watch on a file is lost when the file is replaced by renaming
+/// another file over it, which is how most editors (including Qork) save files.
+pub struct FileWatcher {
+    fd: libc::c_int,
+    /// The write end of a pipe which is closed to tell the reader thread to stop.
+    stop_fd: libc::c_int,
+    /// The read end of the pipe, which the reader thread waits on along with `fd`.
+    stopped_fd: libc::c_int,
+    thread: Option<JoinHandle<()>>,
+    watches: Arc<Mutex<Watches>>
+}
+
+impl FileWatcher {
+    /// Creates a watcher and starts a thread which reads its events and sends them to `sender`,
+    /// converted to whatever type the channel carries. The thread stops when the watcher is
+    /// dropped, or when the receiving end of the channel is dropped and another event arrives.
+    pub fn new<T>(sender: Sender<T>) -> io::Result<FileWatcher>
+        where T: From<FileEvent> + Send + 'static
+    {
+        let fd = unsafe { ffi::inotify_init1(ffi::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut pipe = [0; 2];
+        if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+
+        let mut watcher = FileWatcher {
+            fd,
+            stop_fd: pipe[1],
+            stopped_fd: pipe[0],
+            thread: None,
+            watches: Arc::new(Mutex::new(Watches::default()))
+        };
+        let thread_watches = watcher.watches.clone();
+        let stopped_fd = watcher.stopped_fd;
+        watcher.thread = Some(thread::Builder::new()
+            .name(String::from("file_watcher"))
+            .spawn(move || read_events(fd, stopped_fd, &thread_watches, &sender))?);
+
+        Ok(watcher)
+    }
+
+    /// Makes the set of watched files equal to `paths`, adding and removing watches as
+    /// necessary. Failures to watch individual files are logged and otherwise ignored.
+    pub fn sync<P: AsRef<Path>>(&self, paths: &[P]) {
+        let requested: HashSet<PathBuf> = paths.iter().map(|p| PathBuf::from(p.as_ref())).collect();
+
+        let mut watches = self.watches.lock().unwrap();
+        if watches.requested == requested {
+            return;
+        }
+
+        let mut wanted = HashMap::new();
+        for path in &requested {
+            match canonical_path(path) {
+                Some(canonical) => { wanted.insert(canonical, path.clone()); },
+                None => warn!("Could not watch {:?}, its directory cannot be found", path)
+            }
+        }
+        let wanted_dirs: HashSet<PathBuf> = wanted.keys().filter_map(|p| p.parent()).map(PathBuf::from).collect();
+
+        let unwanted_dirs: Vec<_> = watches.wds.keys().filter(|d| !wanted_dirs.contains(*d)).cloned().collect();
+        for dir in unwanted_dirs {
+            let wd = watches.wds.remove(&dir).unwrap();
+            watches.dirs.remove(&wd);
+            unsafe { ffi::inotify_rm_watch(self.fd, wd) };
+        }
+
+        for dir in wanted_dirs {
+            if watches.wds.contains_key(&dir) {
+                continue;
+            }
+            match self.add_watch(&dir) {
+                Ok(wd) => {
+                    watches.wds.insert(dir.clone(), wd);
+                    watches.dirs.insert(wd, dir);
+                }
+                Err(e) => warn!("Could not watch directory {:?}: {}", dir, e)
+            }
+        }
+
+        info!("Watching {} files in {} directories", wanted.len(), watches.wds.len());
+        watches.files = wanted;
+        watches.requested = requested;
+    }
+
+    fn add_watch(&self, dir: &Path) -> io::Result<libc::c_int> {
+        let c_dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mask = ffi::IN_CLOSE_WRITE | ffi::IN_MOVED_TO | ffi::IN_MOVED_FROM | ffi::IN_DELETE | ffi::IN_ONLYDIR;
+        let wd = unsafe { ffi::inotify_add_watch(self.fd, c_dir.as_ptr(), mask) };
+        if wd < 0 { Err(io::Error::last_os_error()) } else { Ok(wd) }
+    }
+}
+
+impl Drop for FileWatcher {
+    /// Stops the reader thread, then closes the inotify instance.
+    fn drop(&mut self) {
+        unsafe { libc::close(self.stop_fd) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        unsafe {
+            libc::close(self.stopped_fd);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// The path with its directory made absolute and free of symbolic links, which is how the
+/// directory of an event is known. Relative paths are in the current directory. The file
+/// itself need not exist.
+fn canonical_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new(".")
+    };
+    dir.canonicalize().ok().map(|d| d.join(name))
+}
+
+/// The size of the fixed part of an inotify event; it is followed by a NUL-padded name.
+const EVENT_HEADER_LEN: usize = 16;
+
+/// Reads events from `fd` until `stopped_fd` becomes readable, which happens when the other
+/// end of its pipe is closed.
+fn read_events<T: From<FileEvent>>(fd: libc::c_int, stopped_fd: libc::c_int, watches: &Mutex<Watches>, sender: &Sender<T>) {
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let mut fds = [
+            libc::pollfd { fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: stopped_fd, events: libc::POLLIN, revents: 0 }
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            error!("Error waiting for file watcher events, no more changes will be seen: {}", e);
+            return;
+        }
+        if fds[1].revents != 0 {
+            return;
+        }
+        if fds[0].revents == 0 {
+            continue;
+        }
+
+        let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            error!("Error reading file watcher events, no more changes will be seen: {}", e);
+            return;
+        }
+
+        let events = parse_events(&buf[..len as usize], &watches.lock().unwrap());
+        for event in events {
+            if sender.send(T::from(event)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Turns the raw events read from inotify into `FileEvent`s for the files we are watching.
+fn parse_events(buf: &[u8], watches: &Watches) -> Vec<FileEvent> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+
+    while offset + EVENT_HEADER_LEN <= buf.len() {
+        let field = |n: usize| {
+            let start = offset + n * 4;
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&buf[start..start + 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        let (wd, mask, name_len) = (field(0) as libc::c_int, field(1), field(3) as usize);
+        let name_start = offset + EVENT_HEADER_LEN;
+        let name_bytes = &buf[name_start..(name_start + name_len).min(buf.len())];
+        let name = OsStr::from_bytes(name_bytes.split(|&b| b == 0).next().unwrap_or(&[]));
+        offset = name_start + name_len;
+
+        if mask & ffi::IN_Q_OVERFLOW != 0 {
+            events.push(FileEvent::Overflow);
+            continue;
+        }
+
+        let kind = if mask & (ffi::IN_CLOSE_WRITE | ffi::IN_MOVED_TO) != 0 {
+            FileEventKind::Changed
+        } else if mask & ffi::IN_DELETE != 0 {
+            FileEventKind::Deleted
+        } else if mask & ffi::IN_MOVED_FROM != 0 {
+            FileEventKind::Renamed
+        } else {
+            continue;
+        };
+
+        if let Some(dir) = watches.dirs.get(&wd) {
+            if let Some(path) = watches.files.get(&dir.join(name)) {
+                events.push(FileEvent::File { path: path.clone(), kind });
+            }
+        }
+    }
+
+    events
+}
+
+/// Declarations from <sys/inotify.h>, which the libc crate does not provide.
+mod ffi {
+    use libc::{c_char, c_int, uint32_t};
+
+    pub const IN_CLOSE_WRITE: uint32_t = 0x0000_0008;
+    pub const IN_MOVED_FROM: uint32_t = 0x0000_0040;
+    pub const IN_MOVED_TO: uint32_t = 0x0000_0080;
+    pub const IN_DELETE: uint32_t = 0x0000_0200;
+    pub const IN_Q_OVERFLOW: uint32_t = 0x0000_4000;
+    pub const IN_ONLYDIR: uint32_t = 0x0100_0000;
+    pub const IN_CLOEXEC: c_int = 0o2_000_000;
+
+    extern "C" {
+        pub fn inotify_init1(flags: c_int) -> c_int;
+        pub fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: uint32_t) -> c_int;
+        pub fn inotify_rm_watch(fd: c_int, wd: c_int) -> c_int;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+    use std::time::Duration;
+    use fs::{save_from_string, TestDir};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Waits for an event of the given kind. An atomic save can produce several Changed events,
+    /// because the temporary file is closed after it has been renamed, so others are skipped.
+    fn wait_for(rx: &Receiver<FileEvent>, kind: FileEventKind) -> PathBuf {
+        loop {
+            match rx.recv_timeout(TIMEOUT).unwrap() {
+                FileEvent::File { path, kind: k } if k == kind => return path,
+                _ => continue
+            }
+        }
+    }
+
+    #[test]
+    fn watcher_reports_changed_and_deleted_files() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        save_from_string(&filename, "hello").unwrap();
+
+        let (tx, rx) = channel::<FileEvent>();
+        let watcher = FileWatcher::new(tx).unwrap();
+        watcher.sync(&[&filename]);
+
+        save_from_string(&filename, "bye").unwrap();
+        assert_eq!(wait_for(&rx, FileEventKind::Changed), filename);
+
+        ::std::fs::remove_file(&filename).unwrap();
+        assert_eq!(wait_for(&rx, FileEventKind::Deleted), filename);
+    }
+
+    #[test]
+    fn watcher_ignores_other_files_in_the_same_directory() {
+        let dir = TestDir::new();
+        let (watched, other) = (dir.join("a.txt"), dir.join("b.txt"));
+
+        let (tx, rx) = channel::<FileEvent>();
+        let watcher = FileWatcher::new(tx).unwrap();
+        watcher.sync(&[&watched]);
+
+        save_from_string(&other, "hello").unwrap();
+        save_from_string(&watched, "hello").unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), FileEvent::File { path: watched.clone(), kind: FileEventKind::Changed });
+    }
+
+    #[test]
+    fn watcher_reports_files_by_the_path_they_were_watched_as() {
+        let dir = TestDir::new();
+        ::std::fs::create_dir(dir.join("sub")).unwrap();
+        let filename = dir.join("sub/../a.txt");
+
+        let (tx, rx) = channel::<FileEvent>();
+        let watcher = FileWatcher::new(tx).unwrap();
+        watcher.sync(&[&filename]);
+
+        save_from_string(&dir.join("a.txt"), "hello").unwrap();
+        assert_eq!(wait_for(&rx, FileEventKind::Changed), filename);
+    }
+
+    #[test]
+    fn dropping_the_watcher_stops_its_thread() {
+        let (tx, rx) = channel::<FileEvent>();
+        let watcher = FileWatcher::new(tx).unwrap();
+        drop(watcher);
+        assert_eq!(rx.recv_timeout(TIMEOUT), Err(RecvTimeoutError::Disconnected));
+    }
+}
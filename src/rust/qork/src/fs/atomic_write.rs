@@ -0,0 +1,329 @@
+use std::fs::{self as stdfs, File, Metadata, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use libc;
+use rand::{thread_rng, Rng};
+
+/// The maximum number of symlinks that will be followed when resolving the target of a save,
+/// the same as the Linux kernel's limit.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Writes `data` to `filename` so that, if the process dies part-way through, the file
+/// contains either its old contents or its new contents but never a mixture of the two.
+///
+/// The data is written to a temporary file in the same directory, which is fsynced and then
+/// renamed over the target. The mode, ownership and extended attributes of an existing file
+/// are copied to the new one before anything is written to it, and until then it can only be
+/// read by its owner. If `filename` is a symlink the file it points to is written
+/// and the link is left alone.
+///
+/// A file with several hard links cannot be replaced by renaming without breaking the links,
+/// so its contents are overwritten in place instead, which is NOT atomic. The new contents
+/// are still written to a temporary file first, and if overwriting fails that file is kept
+/// and named in the error, so that the data is not lost.
+pub fn write_atomically(filename: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomically_with_mode(filename, data, default_mode())
+}
+
+/// Like `write_atomically`, but if `filename` does not exist yet it is created with `mode`
+/// rather than the usual mode, for example to keep it private.
+pub fn write_atomically_with_mode(filename: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+    let target = resolve_symlinks(filename)?;
+    let existing = match stdfs::metadata(&target) {
+        Ok(m) => Some(m),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e)
+    };
+
+    let (temp_filename, temp_file) = create_temp_file(&target)?;
+    let prepared = match existing {
+        // The temporary file is only a spare copy when overwriting in place, so it is left
+        // private.
+        Some(ref m) if m.nlink() > 1 => Ok(()),
+        Some(ref m) => copy_metadata(&target, m, &temp_file),
+        None => temp_file.set_permissions(Permissions::from_mode(mode))
+    };
+    if let Err(e) = prepared.and_then(|_| write_and_sync(&temp_file, data)) {
+        let _ = stdfs::remove_file(&temp_filename);
+        return Err(e);
+    }
+
+    let result = match existing {
+        Some(ref m) if m.nlink() > 1 => {
+            // If this fails part-way through, the temporary file is the only complete copy of
+            // the data, so it is kept.
+            overwrite_in_place(&target, data).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}, the new contents have been kept in {:?}", e, temp_filename))
+            })?;
+            Ok(())
+        }
+        _ => replace_with(&temp_filename, &target)
+    };
+
+    // After a successful rename the temporary file no longer exists, so this only has any
+    // effect when something went wrong or the target was overwritten in place.
+    let _ = stdfs::remove_file(&temp_filename);
+    result
+}
+
+/// Follows symlinks until a path which is not a symlink is found. That path need not exist,
+/// a dangling symlink resolves to the file it would create.
+fn resolve_symlinks(filename: &Path) -> io::Result<PathBuf> {
+    let mut path = PathBuf::from(filename);
+
+    for _ in 0..MAX_SYMLINK_HOPS {
+        match stdfs::symlink_metadata(&path) {
+            Ok(ref m) if m.file_type().is_symlink() => {
+                let link = stdfs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link
+                };
+            }
+            _ => return Ok(path)
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::Other, format!("Too many levels of symbolic links at {:?}", filename)))
+}
+
+fn create_temp_file(target: &Path) -> io::Result<(PathBuf, File)> {
+    let dir = match target.parent() {
+        Some(p) if p.as_os_str().is_empty() => Path::new("."),
+        Some(p) => p,
+        None => Path::new(".")
+    };
+    let leaf = target.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+
+    loop {
+        let suffix: String = thread_rng().gen_ascii_chars().take(6).collect();
+        let temp_filename = dir.join(format!(".{}.{}.qork-tmp", leaf, suffix));
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp_filename) {
+            Ok(f) => return Ok((temp_filename, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// The mode a new file is normally created with, which is 0666 less the umask.
+fn default_mode() -> u32 {
+    // The umask can only be read by changing it, so it is put straight back.
+    let mask = unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask
+    };
+    0o666 & !(mask as u32)
+}
+
+fn write_and_sync(mut file: &File, data: &[u8]) -> io::Result<()> {
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Renames `from` over `to`, then fsyncs the directory so that the rename itself is durable.
+fn replace_with(from: &Path, to: &Path) -> io::Result<()> {
+    stdfs::rename(from, to)?;
+    match to.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(())
+    }
+}
+
+fn overwrite_in_place(target: &Path, data: &[u8]) -> io::Result<()> {
+    let file = OpenOptions::new().write(true).truncate(true).open(target)?;
+    write_and_sync(&file, data)
+}
+
+/// Gives `file` the same mode, owner and extended attributes as the `original` file. Only
+/// the mode is essential: a normal user cannot give a file away to someone else, and not
+/// every filesystem supports extended attributes, so failures to copy those are only logged.
+/// The owner is set first, because changing it clears the setuid and setgid bits.
+fn copy_metadata(original: &Path, metadata: &Metadata, file: &File) -> io::Result<()> {
+    let rc = unsafe { libc::fchown(file.as_raw_fd(), metadata.uid(), metadata.gid()) };
+    if rc != 0 {
+        warn!("Could not preserve ownership of {:?}: {}", original, io::Error::last_os_error());
+    }
+
+    file.set_permissions(metadata.permissions())?;
+
+    if let Err(e) = copy_xattrs(original, file) {
+        warn!("Could not preserve extended attributes of {:?}: {}", original, e);
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(original: &Path, file: &File) -> io::Result<()> {
+    let source = File::open(original)?;
+    let names = read_xattr_names(&source)?;
+
+    for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+        let mut name = name.to_vec();
+        name.push(0);
+        let value = read_xattr_value(&source, &name)?;
+        let rc = unsafe {
+            libc::fsetxattr(file.as_raw_fd(), name.as_ptr() as *const libc::c_char,
+                            value.as_ptr() as *const libc::c_void, value.len(), 0)
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_original: &Path, _file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns the NUL-separated list of extended attribute names of `file`.
+#[cfg(target_os = "linux")]
+fn read_xattr_names(file: &File) -> io::Result<Vec<u8>> {
+    read_sized(|buf, len| unsafe { libc::flistxattr(file.as_raw_fd(), buf as *mut libc::c_char, len) })
+}
+
+#[cfg(target_os = "linux")]
+fn read_xattr_value(file: &File, name: &[u8]) -> io::Result<Vec<u8>> {
+    read_sized(|buf, len| unsafe {
+        libc::fgetxattr(file.as_raw_fd(), name.as_ptr() as *const libc::c_char, buf as *mut libc::c_void, len)
+    })
+}
+
+/// Calls an xattr function, which returns the size it needs when passed a zero length buffer,
+/// retrying if the value grows between the two calls.
+#[cfg(target_os = "linux")]
+fn read_sized<F>(f: F) -> io::Result<Vec<u8>>
+    where F: Fn(*mut u8, usize) -> isize
+{
+    loop {
+        let size = f(::std::ptr::null_mut(), 0);
+        if size < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENOTSUP) => Ok(Vec::new()),
+                _ => Err(e)
+            };
+        }
+
+        let mut buf = vec![0u8; size as usize];
+        let len = f(buf.as_mut_ptr(), buf.len());
+        if len >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::TestDir;
+    use std::io::Read;
+    use std::os::unix::fs::symlink;
+
+    fn read(path: &Path) -> String {
+        let mut s = String::new();
+        File::open(path).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn write_atomically_for_new_file_creates_file_and_leaves_no_temporary_file() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        write_atomically(&filename, b"hello").unwrap();
+        assert_eq!(read(&filename), "hello");
+        assert_eq!(stdfs::metadata(&filename).unwrap().permissions().mode() & 0o777, default_mode());
+        assert_eq!(dir.file_count(), 1);
+    }
+
+    #[test]
+    fn write_atomically_with_mode_uses_mode_only_for_new_files() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        write_atomically_with_mode(&filename, b"hello", 0o600).unwrap();
+        assert_eq!(stdfs::metadata(&filename).unwrap().permissions().mode() & 0o777, 0o600);
+
+        stdfs::set_permissions(&filename, stdfs::Permissions::from_mode(0o640)).unwrap();
+        write_atomically_with_mode(&filename, b"bye", 0o600).unwrap();
+        assert_eq!(stdfs::metadata(&filename).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn create_temp_file_can_only_be_read_by_its_owner() {
+        let dir = TestDir::new();
+        let (temp_filename, _) = create_temp_file(&dir.join("a.txt")).unwrap();
+        assert_eq!(stdfs::metadata(&temp_filename).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn write_atomically_for_existing_file_replaces_contents_and_keeps_mode() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        write_atomically(&filename, b"hello").unwrap();
+        stdfs::set_permissions(&filename, stdfs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomically(&filename, b"bye").unwrap();
+        assert_eq!(read(&filename), "bye");
+        assert_eq!(stdfs::metadata(&filename).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(dir.file_count(), 1);
+    }
+
+    #[test]
+    fn write_atomically_for_symlink_writes_target_and_keeps_link() {
+        let dir = TestDir::new();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        write_atomically(&target, b"hello").unwrap();
+        symlink("target.txt", &link).unwrap();
+
+        write_atomically(&link, b"bye").unwrap();
+        assert!(stdfs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(read(&target), "bye");
+    }
+
+    #[test]
+    fn write_atomically_for_dangling_symlink_creates_target() {
+        let dir = TestDir::new();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        symlink(&target, &link).unwrap();
+
+        write_atomically(&link, b"hello").unwrap();
+        assert_eq!(read(&target), "hello");
+    }
+
+    #[test]
+    fn write_atomically_for_hard_linked_file_updates_all_links() {
+        let dir = TestDir::new();
+        let first = dir.join("first.txt");
+        let second = dir.join("second.txt");
+        write_atomically(&first, b"hello").unwrap();
+        stdfs::hard_link(&first, &second).unwrap();
+        let inode = stdfs::metadata(&first).unwrap().ino();
+
+        write_atomically(&first, b"bye").unwrap();
+        assert_eq!(read(&second), "bye");
+        assert_eq!(stdfs::metadata(&first).unwrap().ino(), inode);
+        assert_eq!(dir.file_count(), 2);
+    }
+
+    #[test]
+    fn write_atomically_for_missing_directory_returns_error() {
+        let dir = TestDir::new();
+        assert!(write_atomically(&dir.join("nope/a.txt"), b"hello").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+
+/// The number of bytes read from the start of a file to check for magic numbers.
+pub const MAGIC_LEN: usize = 8;
+
+/// Converts between the bytes of a file on disk and the bytes of its text, for example by
+/// decompressing it. Codecs are chosen by `Codecs::find`.
+pub trait Codec: Debug {
+    /// A short name for display, such as "gzip".
+    fn name(&self) -> &str;
+
+    /// Whether the file should be handled by this codec. `header` is the first bytes of the
+    /// file, and is empty if the file does not exist or is empty. A file with contents is only
+    /// handled if they really are in this codec's format, whatever its name; otherwise the
+    /// name decides.
+    fn matches(&self, filename: &Path, header: &[u8]) -> bool;
+
+    fn decode(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// A compression format handled by running an external program, as Emacs does. This means
+/// that support for a format needs nothing more than its command line tool being installed.
+#[derive(Debug)]
+pub struct ExternalCodec {
+    name: &'static str,
+    extension: &'static str,
+    magics: &'static [&'static [u8]],
+    program: &'static str
+}
+
+impl ExternalCodec {
+    /// Creates a codec for files with `extension`, or whose contents start with any of `magics`.
+    pub fn new(name: &'static str, extension: &'static str, magics: &'static [&'static [u8]], program: &'static str) -> ExternalCodec {
+        ExternalCodec { name, extension, magics, program }
+    }
+
+    /// Pipes `data` through the program with the given arguments.
+    fn run(&self, args: &[&str], data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut child = Command::new(self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("Could not run {}: {}", self.program, e)))?;
+
+        // Write and read stderr on other threads, otherwise the processes can block on full
+        // pipes.
+        let mut stdin = child.stdin.take().unwrap();
+        let input = data.to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&input));
+        let mut stderr = child.stderr.take().unwrap();
+        let error_reader = thread::spawn(move || {
+            let mut errors = String::new();
+            stderr.read_to_string(&mut errors).map(|_| errors)
+        });
+
+        let mut output = Vec::new();
+        child.stdout.take().unwrap().read_to_end(&mut output)?;
+        let status = child.wait()?;
+        let written = writer.join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "writer thread panicked")));
+        let errors = error_reader.join().ok().and_then(|r| r.ok()).unwrap_or_default();
+
+        if !status.success() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{} failed ({}): {}", self.program, status, errors.trim())));
+        }
+        written?;
+        Ok(output)
+    }
+}
+
+impl Codec for ExternalCodec {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn matches(&self, filename: &Path, header: &[u8]) -> bool {
+        if header.is_empty() {
+            filename.extension().map_or(false, |e| e == self.extension)
+        } else {
+            self.magics.iter().any(|m| header.starts_with(m))
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.run(&["-d", "-c"], data)
+    }
+
+    fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.run(&["-c"], data)
+    }
+}
+
+/// "BZh" is followed by the block size, from 1 to 9, which keeps text files starting with
+/// "BZh" from being taken for bzip2.
+const BZIP2_MAGICS: &'static [&'static [u8]] = &[b"BZh1", b"BZh2", b"BZh3", b"BZh4", b"BZh5", b"BZh6", b"BZh7", b"BZh8", b"BZh9"];
+
+/// The codecs available for opening and saving files, tried in order.
+#[derive(Debug, Clone, Default)]
+pub struct Codecs {
+    codecs: Vec<Rc<dyn Codec>>
+}
+
+impl Codecs {
+    /// Codecs for gzip, bzip2, xz and zstd compressed files.
+    pub fn standard() -> Codecs {
+        let mut codecs = Codecs::default();
+        codecs.register(ExternalCodec::new("gzip", "gz", &[&[0x1f, 0x8b]], "gzip"));
+        codecs.register(ExternalCodec::new("bzip2", "bz2", BZIP2_MAGICS, "bzip2"));
+        codecs.register(ExternalCodec::new("xz", "xz", &[&[0xfd, b'7', b'z', b'X', b'Z', 0x00]], "xz"));
+        codecs.register(ExternalCodec::new("zstd", "zst", &[&[0x28, 0xb5, 0x2f, 0xfd]], "zstd"));
+        codecs
+    }
+
+    pub fn register<C: Codec + 'static>(&mut self, codec: C) {
+        self.codecs.push(Rc::new(codec));
+    }
+
+    /// Finds the codec for a file, using its magic number if it has contents and otherwise
+    /// its extension. Returns None for a plain file.
+    pub fn find(&self, filename: &Path) -> Option<Rc<dyn Codec>> {
+        let header = read_header(filename);
+        self.codecs.iter().find(|c| c.matches(filename, &header)).cloned()
+    }
+
+    /// Finds the codec for a file by its name alone, ignoring any existing contents. Used
+    /// when saving to a new name.
+    pub fn find_by_name(&self, filename: &Path) -> Option<Rc<dyn Codec>> {
+        self.codecs.iter().find(|c| c.matches(filename, &[])).cloned()
+    }
+}
+
+fn read_header(filename: &Path) -> Vec<u8> {
+    let mut header = Vec::with_capacity(MAGIC_LEN);
+    if let Ok(f) = File::open(filename) {
+        let _ = f.take(MAGIC_LEN as u64).read_to_end(&mut header);
+    }
+    header
+}
+
+/// Loads a file as text, decoding it with `codec` if there is one.
+pub fn load_with_codec(filename: &Path, codec: Option<&dyn Codec>) -> Result<String, String> {
+    let mut data = Vec::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| e.to_string())?;
+
+    if let Some(c) = codec {
+        data = c.decode(&data).map_err(|e| e.to_string())?;
+        info!("Decoded {} bytes from {:?} using {}", data.len(), filename, c.name());
+    }
+
+    String::from_utf8(data).map_err(|e| format!("The file is not valid UTF-8: {}", e))
+}
+
+/// Saves text to a file atomically, encoding it with `codec` if there is one. Returns the
+/// number of bytes of text saved.
+pub fn save_with_codec(filename: &Path, text: &str, codec: Option<&dyn Codec>) -> Result<usize, String> {
+    let encoded;
+    let data = match codec {
+        Some(c) => {
+            encoded = c.encode(text.as_bytes()).map_err(|e| e.to_string())?;
+            &encoded[..]
+        },
+        None => text.as_bytes()
+    };
+
+    super::write_atomically(filename, data).map_err(|e| e.to_string())?;
+    info!("Saved {} bytes to {:?}", data.len(), filename);
+    Ok(text.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::TestDir;
+
+    #[test]
+    fn find_chooses_codec_by_magic_number_or_for_new_files_extension() {
+        let dir = TestDir::new();
+        let codecs = Codecs::standard();
+        assert_eq!(codecs.find(&dir.join("a.txt.gz")).unwrap().name(), "gzip");
+        assert_eq!(codecs.find(&dir.join("a.tar.zst")).unwrap().name(), "zstd");
+        assert!(codecs.find(&dir.join("a.txt")).is_none());
+
+        let disguised = dir.join("compressed");
+        super::super::write_atomically(&disguised, b"BZh91AY&SY").unwrap();
+        assert_eq!(codecs.find(&disguised).unwrap().name(), "bzip2");
+
+        let plain = dir.join("notes.txt");
+        super::super::write_atomically(&plain, b"BZh is not enough").unwrap();
+        assert!(codecs.find(&plain).is_none());
+
+        let misnamed = dir.join("plain.gz");
+        super::super::write_atomically(&misnamed, b"not compressed").unwrap();
+        assert!(codecs.find(&misnamed).is_none());
+    }
+
+    fn round_trip(program: &str, ext: &str) {
+        let dir = TestDir::new();
+        let codecs = Codecs::standard();
+        let filename = dir.join(format!("a.txt.{}", ext));
+        let codec = codecs.find(&filename).unwrap();
+        save_with_codec(&filename, "hello\nworld\n", Some(&*codec)).unwrap();
+
+        // Once written, the magic number alone is enough to recognise the file.
+        let renamed = dir.join("plain");
+        ::std::fs::rename(&filename, &renamed).unwrap();
+        let codec = codecs.find(&renamed).unwrap();
+        assert_eq!(codec.name(), program);
+        assert_eq!(load_with_codec(&renamed, Some(&*codec)).unwrap(), "hello\nworld\n");
+    }
+
+    // These need the compression programs to be installed, so are run with --ignored.
+
+    #[test]
+    #[ignore]
+    fn save_and_load_round_trip_through_gzip() {
+        round_trip("gzip", "gz");
+    }
+
+    #[test]
+    #[ignore]
+    fn save_and_load_round_trip_through_bzip2() {
+        round_trip("bzip2", "bz2");
+    }
+
+    #[test]
+    #[ignore]
+    fn save_and_load_round_trip_through_xz() {
+        round_trip("xz", "xz");
+    }
+
+    #[test]
+    #[ignore]
+    fn save_and_load_round_trip_through_zstd() {
+        round_trip("zstd", "zst");
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Enough information about a file on disk to tell whether it has been changed, or replaced
+/// by a different file, since the stamp was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: SystemTime,
+    pub len: u64,
+    pub dev: u64,
+    pub ino: u64
+}
+
+impl FileStamp {
+    /// Takes a stamp of the file, following symlinks. Returns None if the file does not exist
+    /// or cannot be examined.
+    pub fn of<P: AsRef<Path>>(path: P) -> Option<FileStamp> {
+        let m = fs::metadata(path).ok()?;
+        Some(FileStamp {
+            modified: m.modified().ok()?,
+            len: m.len(),
+            dev: m.dev(),
+            ino: m.ino()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::{save_from_string, TestDir};
+
+    #[test]
+    fn of_for_missing_file_returns_none() {
+        let dir = TestDir::new();
+        assert!(FileStamp::of(dir.join("a.txt")).is_none());
+    }
+
+    #[test]
+    fn of_for_replaced_file_returns_different_stamp() {
+        let dir = TestDir::new();
+        let filename = dir.join("a.txt");
+        save_from_string(&filename, "hello").unwrap();
+        let before = FileStamp::of(&filename).unwrap();
+        assert_eq!(FileStamp::of(&filename), Some(before));
+
+        // An atomic save replaces the file, so even with the same size the inode differs.
+        save_from_string(&filename, "howdy").unwrap();
+        assert_ne!(FileStamp::of(&filename), Some(before));
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+use std::fs as stdfs;
+use std::path::{Path, PathBuf};
+use users::get_user_by_name;
+use users::os::unix::UserExt;
+use utils;
+
+/// The maximum number of directory entries examined when looking for suggestions, and how
+/// many directories deep to look, so that a typo in a huge tree does not hang the editor.
+/// Suggestions are looked for whenever a file which does not exist is opened, so this needs
+/// to be quick.
+const MAX_SCANNED_ENTRIES: usize = 2_000;
+const MAX_SCAN_DEPTH: usize = 3;
+
+/// The maximum number of suggestions returned for a filename that could not be found.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// The result of resolving a filename typed by the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedFilename {
+    pub path: PathBuf,
+    /// The 1-based line from a `file:line` or `file:line:col` suffix.
+    pub line: Option<usize>,
+    /// The 1-based column from a `file:line:col` suffix.
+    pub column: Option<usize>,
+    pub exists: bool,
+    /// Existing files with similar names, when `path` does not exist.
+    pub suggestions: Vec<PathBuf>
+}
+
+/// Turns filenames typed by the user into paths. Relative names are looked for in the
+/// directory of the current buffer, then in the project root (the nearest ancestor of that
+/// directory containing one of the project markers, such as ".git"), then in the current
+/// working directory. A name that is found in none of them is taken to be a new file in the
+/// first of those directories.
+#[derive(Debug)]
+pub struct FilenameResolver {
+    search_dirs: Vec<PathBuf>,
+    project_root: Option<PathBuf>
+}
+
+impl FilenameResolver {
+    pub fn new<S: AsRef<str>>(buffer_dir: Option<&Path>, cwd: &Path, project_markers: &[S]) -> FilenameResolver {
+        let project_root = find_project_root(buffer_dir.unwrap_or(cwd), project_markers);
+
+        let mut search_dirs: Vec<PathBuf> = Vec::new();
+        for dir in buffer_dir.into_iter().chain(project_root.as_ref().map(|p| p.as_path())).chain(Some(cwd)) {
+            if !search_dirs.iter().any(|d| d == dir) {
+                search_dirs.push(PathBuf::from(dir));
+            }
+        }
+
+        FilenameResolver { search_dirs, project_root }
+    }
+
+    pub fn project_root(&self) -> Option<&Path> {
+        self.project_root.as_ref().map(|p| p.as_path())
+    }
+
+    /// The directories relative names are looked for in, in order.
+    pub fn search_dirs(&self) -> &[PathBuf] {
+        &self.search_dirs
+    }
+
+    pub fn resolve(&self, input: &str) -> ResolvedFilename {
+        let expanded = expand_filename(input.trim());
+
+        // A name which exists as typed is never split, in case it really contains colons.
+        if let Some(path) = self.find(&expanded) {
+            return ResolvedFilename { path, line: None, column: None, exists: true, suggestions: Vec::new() };
+        }
+
+        let (name, line, column) = split_line_and_column(&expanded);
+        if let Some(path) = self.find(name) {
+            return ResolvedFilename { path, line, column, exists: true, suggestions: Vec::new() };
+        }
+
+        let path = match self.search_dirs.first() {
+            Some(dir) if Path::new(name).is_relative() => dir.join(name),
+            _ => PathBuf::from(name)
+        };
+        let suggestions = self.suggest(name);
+        ResolvedFilename { path, line, column, exists: false, suggestions }
+    }
+
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return if name.exists() { Some(PathBuf::from(name)) } else { None };
+        }
+
+        self.search_dirs.iter().map(|d| d.join(name)).find(|p| p.exists())
+    }
+
+    /// Finds files whose names are like the leaf of `name`, searching the project root (or
+    /// the first search directory if there is no project) and the directories just below it.
+    fn suggest(&self, name: &str) -> Vec<PathBuf> {
+        let wanted = match Path::new(name).file_name() {
+            Some(leaf) => leaf.to_string_lossy().to_lowercase(),
+            None => return Vec::new()
+        };
+        let root = match self.project_root.as_ref().or_else(|| self.search_dirs.first()) {
+            Some(r) => r,
+            None => return Vec::new()
+        };
+
+        let mut scored: Vec<(usize, PathBuf)> = collect_files(root).into_iter()
+            .filter_map(|f| {
+                let score = {
+                    let leaf = f.file_name()?.to_string_lossy().to_lowercase();
+                    similarity(&wanted, &leaf)?
+                };
+                Some((score, f))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+        scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, f)| f).collect()
+    }
+}
+
+/// Expands `~`, `~user` and environment variables at the start of a filename.
+pub fn expand_filename(input: &str) -> String {
+    if input.starts_with('~') && !input.starts_with("~/") && input != "~" {
+        let (user, rest) = match input.find('/') {
+            Some(idx) => (&input[1..idx], &input[idx..]),
+            None => (&input[1..], "")
+        };
+        if let Some(u) = get_user_by_name(user) {
+            let expanded = format!("{}{}", u.home_dir().display(), rest);
+            return utils::expand_variables(&expanded).into_owned();
+        }
+    }
+
+    utils::expand_variables(input).into_owned()
+}
+
+/// Splits a trailing ":line" or ":line:col" off a filename, as found in compiler and grep
+/// output. A single trailing colon is also removed.
+fn split_line_and_column(input: &str) -> (&str, Option<usize>, Option<usize>) {
+    let trimmed = input.trim_end_matches(':');
+    let mut parts = trimmed.rsplitn(3, ':');
+    let last = parts.next().and_then(|p| p.parse::<usize>().ok());
+    let middle = parts.next();
+    let first = parts.next();
+
+    match (first, middle, last) {
+        (Some(name), Some(line), Some(col)) if line.parse::<usize>().is_ok() && !name.is_empty() =>
+            (name, line.parse().ok(), Some(col)),
+        (_, Some(_), Some(line)) => {
+            let idx = trimmed.rfind(':').unwrap();
+            if idx == 0 { (trimmed, None, None) } else { (&trimmed[..idx], Some(line), None) }
+        }
+        _ => (trimmed, None, None)
+    }
+}
+
+/// Walks up from `dir` looking for a directory containing one of `markers`.
+fn find_project_root<S: AsRef<str>>(dir: &Path, markers: &[S]) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| markers.iter().any(|m| d.join(m.as_ref()).exists()))
+        .map(PathBuf::from)
+}
+
+/// Lists files under `root`, nearest first, skipping hidden files and directories. At most
+/// `MAX_SCANNED_ENTRIES` entries are looked at, no more than `MAX_SCAN_DEPTH` directories down.
+fn collect_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = VecDeque::new();
+    dirs.push_back((PathBuf::from(root), 0));
+    let mut scanned = 0;
+
+    while let Some((dir, depth)) = dirs.pop_front() {
+        let entries = match stdfs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            scanned += 1;
+            if scanned > MAX_SCANNED_ENTRIES {
+                return files;
+            }
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            match entry.file_type() {
+                Ok(ft) if ft.is_dir() && depth < MAX_SCAN_DEPTH => dirs.push_back((entry.path(), depth + 1)),
+                Ok(ft) if ft.is_file() => files.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+
+    files
+}
+
+/// Scores how alike two filenames are, lower being better, or None if they are not alike
+/// enough to suggest. Names within a small edit distance score best, then names which
+/// contain the wanted characters in order.
+fn similarity(wanted: &str, candidate: &str) -> Option<usize> {
+    let distance = edit_distance(wanted, candidate);
+    let max_distance = (wanted.chars().count() / 3).max(2);
+    if distance <= max_distance {
+        Some(distance)
+    } else if is_subsequence(wanted, candidate) {
+        Some(max_distance + 1 + candidate.len() - wanted.len())
+    } else {
+        None
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::{save_from_string, TestDir};
+
+    const MARKERS: [&'static str; 1] = [".git"];
+
+    #[test]
+    fn split_line_and_column_handles_all_suffixes() {
+        assert_eq!(split_line_and_column("a.rs"), ("a.rs", None, None));
+        assert_eq!(split_line_and_column("a.rs:12"), ("a.rs", Some(12), None));
+        assert_eq!(split_line_and_column("a.rs:12:5"), ("a.rs", Some(12), Some(5)));
+        assert_eq!(split_line_and_column("a.rs:12:"), ("a.rs", Some(12), None));
+        assert_eq!(split_line_and_column("a:b.rs"), ("a:b.rs", None, None));
+    }
+
+    #[test]
+    fn expand_filename_expands_tilde_user() {
+        let root = get_user_by_name("root").unwrap();
+        assert_eq!(expand_filename("~root/a.txt"), format!("{}/a.txt", root.home_dir().display()));
+        assert_eq!(expand_filename("~no_such_user_i_hope/a"), "~no_such_user_i_hope/a");
+    }
+
+    #[test]
+    fn resolve_prefers_buffer_dir_then_project_root_then_cwd() {
+        let dir = TestDir::new();
+        let project = dir.join("project");
+        let src = project.join("src");
+        let cwd = dir.join("cwd");
+        stdfs::create_dir_all(project.join(".git")).unwrap();
+        stdfs::create_dir_all(&src).unwrap();
+        stdfs::create_dir_all(&cwd).unwrap();
+        save_from_string(&project.join("README"), "").unwrap();
+        save_from_string(&cwd.join("README"), "").unwrap();
+        save_from_string(&cwd.join("notes"), "").unwrap();
+
+        let resolver = FilenameResolver::new(Some(&src), &cwd, &MARKERS);
+        assert_eq!(resolver.project_root(), Some(project.as_path()));
+        assert_eq!(resolver.resolve("README").path, project.join("README"));
+        assert_eq!(resolver.resolve("notes:3:7"), ResolvedFilename {
+            path: cwd.join("notes"), line: Some(3), column: Some(7), exists: true, suggestions: Vec::new()
+        });
+    }
+
+    #[test]
+    fn resolve_for_missing_file_offers_suggestions_and_uses_buffer_dir() {
+        let dir = TestDir::new();
+        stdfs::create_dir_all(dir.join(".git")).unwrap();
+        stdfs::create_dir_all(dir.join("src")).unwrap();
+        save_from_string(&dir.join("src/main.rs"), "").unwrap();
+        save_from_string(&dir.join("src/lib.rs"), "").unwrap();
+
+        let resolver = FilenameResolver::new(Some(&dir.join("src")), &dir.join("src"), &MARKERS);
+        let resolved = resolver.resolve("mian.rs");
+        assert!(!resolved.exists);
+        assert_eq!(resolved.path, dir.join("src/mian.rs"));
+        assert_eq!(resolved.suggestions, vec![dir.join("src/main.rs")]);
+    }
+
+    #[test]
+    fn collect_files_does_not_look_too_deep() {
+        let dir = TestDir::new();
+        let deep = dir.join("a/b/c/d");
+        stdfs::create_dir_all(&deep).unwrap();
+        save_from_string(&dir.join("a/b/c/near.rs"), "").unwrap();
+        save_from_string(&deep.join("far.rs"), "").unwrap();
+        save_from_string(&dir.join(".hidden"), "").unwrap();
+
+        assert_eq!(collect_files(&dir.0), vec![dir.join("a/b/c/near.rs")]);
+    }
+}
@@ -1,18 +1,28 @@
 use std::env::temp_dir;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::BufReader;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use rand::{thread_rng, Rng};
 
+mod atomic_write;
+mod codec;
 mod config_dir;
 mod data_dir;
+mod file_stamp;
+mod filename_resolver;
+mod path_completion;
 mod base_dir;
 mod runtime_dir;
 
+pub use fs::atomic_write::{write_atomically, write_atomically_with_mode};
 pub use fs::base_dir::BaseDir;
+pub use fs::codec::{load_with_codec, save_with_codec, Codec, Codecs};
 pub use fs::config_dir::ConfigDir;
 pub use fs::data_dir::DataDir;
+pub use fs::file_stamp::FileStamp;
+pub use fs::filename_resolver::{expand_filename, FilenameResolver};
+pub use fs::path_completion::complete_path;
 pub use fs::runtime_dir::RuntimeDir;
 
 
@@ -48,31 +58,35 @@ pub fn load_to_vector(filename: &Path) -> Result<Vec<String>, String> {
         })
 }
 
+/// Saves `data` to `filename` one item per line. The save is atomic, see `write_atomically`.
 pub fn save_from_vector(filename: &Path, data: Vec<String>) -> Result<usize, String> {
-    File::create(filename)
-        .map_err(|err| err.to_string())
-        .and_then(|f| {
-            let mut f = BufWriter::new(f);
-            let mut byte_count = 0;
-            for line in data {
-                let bytes = line.as_bytes();
-                match f.write(bytes) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e.to_string()),
-                }
+    let mut contents = String::new();
+    for line in data {
+        contents.push_str(&line);
+        contents.push('\n');
+    }
 
-                match f.write(b"\n") {
-                    Ok(_) => {}
-                    Err(e) => return Err(e.to_string()),
-                }
+    save_from_string(filename, &contents)
+}
 
-                byte_count += bytes.len() + 1;
-            }
+/// Saves `data` to `filename`. The save is atomic, see `write_atomically`.
+pub fn save_from_string(filename: &Path, data: &str) -> Result<usize, String> {
+    let _timer = timer!("fs::save_from_string");
 
-            Ok(byte_count)
+    write_atomically(filename, data.as_bytes())
+        .map_err(|err| err.to_string())
+        .map(|_| {
+            info!("Saved {} bytes to {:?}", data.len(), filename);
+            data.len()
         })
 }
 
+/// Turns a path into a single filename by replacing the separators, Emacs style, so that
+/// files about other files (backups, recovery files) can be kept in a single directory.
+pub fn path_to_filename(path: &Path) -> String {
+    path.to_string_lossy().replace('!', "!!").replace('/', "!")
+}
+
 /// Generate a filename that, at the time of the call, does not exist. This is mainly
 /// intended for use in testing scenarios - to check how functions behave when passed
 /// non-existing filenames - not in real production code, because it exposes a
@@ -104,11 +118,46 @@ pub fn filename_that_does_not_exist() -> PathBuf {
     p
 }
 
+/// A uniquely named directory under the temp directory, which is deleted along with its
+/// contents when dropped. For use in tests which need several related files.
+#[cfg(test)]
+pub struct TestDir(pub PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new() -> TestDir {
+        let name: String = thread_rng().gen_ascii_chars().take(10).collect();
+        let path = temp_dir().join(format!("qork-test-{}", name));
+        ::std::fs::create_dir(&path).unwrap();
+        TestDir(path)
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.0.join(path)
+    }
+
+    pub fn file_count(&self) -> usize {
+        ::std::fs::read_dir(&self.0).unwrap().count()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = ::std::fs::remove_dir_all(&self.0);
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn path_to_filename_replaces_separators() {
+        assert_eq!(path_to_filename(Path::new("/home/a!b/c.txt")), "!home!a!!b!c.txt");
+    }
+
     #[test]
     fn filename_that_does_not_exist_returns_filename_that_does_not_exist_in_temp_dir() {
         let p = filename_that_does_not_exist();
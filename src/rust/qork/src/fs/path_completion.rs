@@ -0,0 +1,112 @@
+use std::fs as stdfs;
+use std::path::Path;
+
+use fs::expand_filename;
+use text::fuzzy_score;
+
+/// The maximum number of directory entries offered as completions, so that completing in a
+/// huge directory does not swamp the editor.
+const MAX_ENTRIES: usize = 5_000;
+
+/// The possible completions of a partly typed filename.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCompletions {
+    /// The part of the filename after its last '/', which the names complete.
+    pub leaf: String,
+    /// Each completion in full: the filename up to its last '/', unexpanded, followed by the
+    /// name of an entry in that directory and a '/' if the entry is a directory.
+    pub paths: Vec<String>,
+    /// The entry names on their own, in the same order as `paths`.
+    pub names: Vec<String>
+}
+
+/// Lists the entries which could complete the last component of `partial`, which are those
+/// whose names fuzzily match it, in order of name. The directory part may contain '~' and
+/// environment variables, which are expanded to find the directory but are kept in the
+/// completions. Relative names are looked for in each of `base_dirs`, as they are when a file
+/// is opened. Hidden entries are only offered if the last component starts with '.'.
+pub fn complete_path<P: AsRef<Path>>(partial: &str, base_dirs: &[P]) -> PathCompletions {
+    let (dir, leaf) = match partial.rfind('/') {
+        Some(idx) => (&partial[..idx + 1], &partial[idx + 1..]),
+        None => ("", partial)
+    };
+
+    // An absolute directory replaces the base directory when joined to it.
+    let expanded = expand_filename(dir);
+    let mut entries: Vec<(String, bool)> = Vec::new();
+    for base in base_dirs {
+        if let Ok(rd) = stdfs::read_dir(base.as_ref().join(&expanded)) {
+            entries.extend(rd.filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let name = e.file_name().to_string_lossy().into_owned();
+                    let is_wanted = (leaf.starts_with('.') || !name.starts_with('.')) && fuzzy_score(leaf, &name).is_some();
+                    if is_wanted { Some((name, e.path().is_dir())) } else { None }
+                }));
+        }
+    }
+    entries.sort();
+    entries.dedup_by(|a, b| a.0 == b.0);
+    entries.truncate(MAX_ENTRIES);
+
+    let names: Vec<String> = entries.into_iter()
+        .map(|(name, is_dir)| if is_dir { name + "/" } else { name })
+        .collect();
+    PathCompletions {
+        leaf: String::from(leaf),
+        paths: names.iter().map(|name| format!("{}{}", dir, name)).collect(),
+        names
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use users::get_user_by_name;
+    use users::os::unix::UserExt;
+    use fs::TestDir;
+
+    #[test]
+    fn complete_path_lists_entries_of_the_typed_directory() {
+        let dir = TestDir::new();
+        stdfs::create_dir(dir.join("src")).unwrap();
+        stdfs::write(dir.join("Cargo.toml"), "").unwrap();
+        stdfs::write(dir.join(".hidden"), "").unwrap();
+        let partial = format!("{}/Ca", dir.0.display());
+
+        let completions = complete_path(&partial, &["/"]);
+        assert_eq!(completions.leaf, "Ca");
+        assert_eq!(completions.names, vec!["Cargo.toml"]);
+        assert_eq!(completions.paths[0], format!("{}/Cargo.toml", dir.0.display()));
+
+        let completions = complete_path(&format!("{}/", dir.0.display()), &["/"]);
+        assert_eq!(completions.names, vec!["Cargo.toml", "src/"]);
+
+        let completions = complete_path(&format!("{}/.", dir.0.display()), &["/"]);
+        assert_eq!(completions.names, vec![".hidden", "Cargo.toml"]);
+    }
+
+    #[test]
+    fn complete_path_keeps_the_typed_directory_unexpanded() {
+        let home = get_user_by_name("root").unwrap().home_dir().to_path_buf();
+
+        let completions = complete_path("~root/", &["/"]);
+        assert_eq!(completions.names, complete_path(&format!("{}/", home.display()), &["/"]).names);
+        assert!(completions.paths.iter().all(|p| p.starts_with("~root/")));
+    }
+
+    #[test]
+    fn complete_path_looks_for_relative_names_in_each_base_dir() {
+        let dir = TestDir::new();
+        stdfs::create_dir_all(dir.join("first/src")).unwrap();
+        stdfs::create_dir_all(dir.join("second/src")).unwrap();
+        stdfs::write(dir.join("first/src/main.rs"), "").unwrap();
+        stdfs::write(dir.join("second/src/lib.rs"), "").unwrap();
+        stdfs::write(dir.join("second/src/main.rs"), "").unwrap();
+
+        let completions = complete_path("src/", &[dir.join("first"), dir.join("second")]);
+        assert_eq!(completions.names, vec!["lib.rs", "main.rs"]);
+        assert_eq!(completions.paths, vec!["src/lib.rs", "src/main.rs"]);
+        assert!(complete_path("src/", &[dir.join("missing")]).names.is_empty());
+    }
+}
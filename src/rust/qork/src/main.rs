@@ -9,6 +9,7 @@ extern crate libc;
 extern crate log;
 extern crate log4rs;
 extern crate rand;
+extern crate regex;
 //extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -22,26 +23,42 @@ extern crate xdg;
 extern crate xi_rope;
 
 #[macro_use] mod execution_timer;
+mod backup;
 mod buffer;
 mod commands;
+mod command_history;
 mod command_line_arguments;
 mod configuration;
 mod fs;
 mod context;
+mod file_watcher;
+mod messages;
 mod datetime;
 mod mru_list;
 mod program_info;
 mod system_info;
 mod persistent_state;
+mod recovery;
+mod spelling;
+mod text;
 mod utils;
+mod view;
+mod workspace;
 
 use std::io::stdin;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use log::LogLevelFilter;
+use log4rs::config::{Appender, Config, Root};
 use xdg::BaseDirectories;
 
-use commands::{handle_command, parse_command};
-use configuration::Configuration;
+use commands::{answer_quit_prompt, execute_command_line, handle_disk_changes, handle_input_closed};
+use configuration::{Configuration, LOGGING_FILE};
 use fs::{ConfigDir, DataDir};
 use context::Context;
+use file_watcher::{FileEvent, FileEventKind, FileWatcher};
+use messages::{MessageSink, MessagesAppender};
 use program_info::ProgramInfo;
 use persistent_state::PersistentState;
 
@@ -55,7 +72,8 @@ fn main() {
     // Configure logging as early as possible (because, obviously, we want to log in the rest of the initialization process).
     let pi = ProgramInfo::new();
     let xdg = BaseDirectories::with_profile(::PKG_NAME, pi.parsed_args().xdg_profile()).unwrap();
-    configure_logging(&xdg);
+    let messages = MessageSink::new();
+    configure_logging(&xdg, &messages);
 
     let _timer = timer!("main.main");
     log_build_info();
@@ -66,21 +84,43 @@ fn main() {
     let data_dir = DataDir::new(xdg.clone(), pi.parsed_args().load_config());
     let config = Configuration::load_user_configuration(&config_dir);
     let persistent_state = PersistentState::load(&config, &data_dir);
-    let context = Context::new(pi, config_dir, config, persistent_state);
+    let context = Context::new(pi, config_dir, data_dir, config, persistent_state, messages);
     info!("{:?}", context.system_info());
 
-    run_event_loop(&context);
+    offer_recovery(&context);
+    let nothing_unsaved = run_event_loop(&context);
 
-    context.state().save(&data_dir);
+    if context.configuration().save_folds() {
+        context.store_folds();
+    }
+    context.store_workspace();
+    context.state().save(context.data_dir());
+    // Recovery files are only deleted when the user has dealt with every unsaved change,
+    // otherwise they are left for offer_recovery to find next time.
+    if nothing_unsaved {
+        context.discard_all_recovery();
+    }
 }
 
-fn configure_logging(xdg: &BaseDirectories) {
-    if let Ok(path) = xdg.place_config_file("logging.toml") {
+/// If there is a logging.toml file it is used to configure logging, and it can send records to the
+/// *messages* buffer by using an appender with `kind = "messages"`. Otherwise, info level messages
+/// and above are sent to the *messages* buffer.
+fn configure_logging(xdg: &BaseDirectories, messages: &MessageSink) {
+    if let Ok(path) = xdg.place_config_file(LOGGING_FILE) {
         if path.exists() {
-            log4rs::init_file(&path, Default::default()).unwrap();
+            log4rs::init_file(&path, messages::deserializers(messages)).unwrap();
             info!("Logging initialized using file at {:?}", &path);
+            return;
         }
     }
+
+    let appender = Appender::builder().build("messages", Box::new(MessagesAppender::new(messages.clone())));
+    let config = Config::builder()
+        .appender(appender)
+        .build(Root::builder().appender("messages").build(LogLevelFilter::Info))
+        .unwrap();
+    log4rs::init_config(config).unwrap();
+    info!("Logging initialized to the *messages* buffer only");
 }
 
 fn log_build_info() {
@@ -90,15 +130,155 @@ fn log_build_info() {
         );
 }
 
-fn run_event_loop(context: &Context) {
+/// If a previous Qork process died with unsaved changes, asks whether to restore them into
+/// buffers, discard them, or keep them for later.
+fn offer_recovery(context: &Context) {
     use std::io::BufRead;
 
+    let orphans = context.recovery_orphans();
+    if orphans.is_empty() {
+        return;
+    }
+
     let stdin = stdin();
-    for line in stdin.lock().lines() {
-        let l = line.unwrap();
-        let cmd = parse_command(&l);
-        if handle_command(context, cmd) {
-            break;
+    let mut lines = stdin.lock().lines();
+    for orphan in orphans {
+        // Another Qork may have been started on the files while we were asking about others.
+        if !orphan.is_still_orphaned() {
+            continue;
+        }
+        let name = orphan.header.filename.as_ref()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_else(|| orphan.header.title.clone());
+        // Unlike the other prompts this is printed rather than logged: log messages only go to
+        // the *messages* buffer, which nobody can look at until the event loop is running.
+        println!("Found unsaved changes to {} from process {}, which is no longer running.", name, orphan.header.pid);
+        println!("[r]estore, [d]iscard or [k]eep for later?");
+
+        let answer = match lines.next() {
+            Some(Ok(l)) => l,
+            _ => return
+        };
+
+        match answer.trim() {
+            "r" => {
+                let result = orphan.restore(&mut context.buffers(), &mut context.buffer_factory());
+                match result {
+                    Ok(id) => info!("Restored unsaved changes to {} into buffer {}", name, id),
+                    Err(e) => warn!("Could not restore unsaved changes to {}: {}", name, e)
+                }
+            },
+            "d" => {
+                orphan.discard();
+                info!("Discarded unsaved changes to {}", name);
+            },
+            _ => info!("Kept unsaved changes to {} for later", name)
         }
     }
-}
\ No newline at end of file
+}
+
+/// How often the event loop wakes up when nothing is happening, so that buffers are written
+/// to their recovery files even while the user is idle.
+const IDLE_TICK: Duration = Duration::from_secs(1);
+
+/// The things the event loop responds to.
+enum Event {
+    /// A line typed by the user.
+    Input(String),
+    /// Standard input was closed or could not be read.
+    InputClosed,
+    File(FileEvent)
+}
+
+impl From<FileEvent> for Event {
+    fn from(e: FileEvent) -> Event {
+        Event::File(e)
+    }
+}
+
+/// Runs until the user quits or standard input is closed. Returns false if Qork is exiting
+/// with unsaved changes whose recovery files should be kept.
+fn run_event_loop(context: &Context) -> bool {
+    context.flush_messages();
+
+    let (sender, receiver) = channel();
+    read_input(sender.clone());
+    let watcher = match FileWatcher::new(sender) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            warn!("Could not start the file watcher, changes made by other programs will only be noticed when a command is entered: {}", e);
+            None
+        }
+    };
+
+    loop {
+        if let Some(ref w) = watcher {
+            w.sync(&context.watched_files());
+        }
+        context.flush_messages();
+
+        let event = match receiver.recv_timeout(IDLE_TICK) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => {
+                context.autosave();
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => Event::InputClosed
+        };
+
+        match event {
+            Event::Input(l) => {
+                // The user has come back to Qork, so this is the time to see if anything else
+                // has changed their files, in case the watcher is not running or missed it.
+                handle_disk_changes(context);
+                let quit = if context.is_quitting() {
+                    answer_quit_prompt(context, &l)
+                } else {
+                    execute_command_line(context, &l).unwrap_or_else(|e| {
+                        warn!("{}", e);
+                        false
+                    })
+                };
+                context.autosave();
+                context.enforce_memory_limit();
+                if quit {
+                    context.flush_messages();
+                    return true;
+                }
+            },
+            Event::InputClosed => {
+                let nothing_unsaved = handle_input_closed(context);
+                context.flush_messages();
+                return nothing_unsaved;
+            },
+            Event::File(FileEvent::File { ref path, kind }) if context.is_config_file(path) => {
+                if kind == FileEventKind::Changed {
+                    info!("The configuration file {:?} has changed, restart Qork to use the new settings", path);
+                }
+            },
+            Event::File(_) => handle_disk_changes(context)
+        }
+    }
+}
+
+/// Starts a thread which reads lines from standard input and sends them to the event loop.
+fn read_input(sender: Sender<Event>) {
+    use std::io::BufRead;
+
+    thread::Builder::new()
+        .name(String::from("input"))
+        .spawn(move || {
+            let stdin = stdin();
+            for line in stdin.lock().lines() {
+                let event = match line {
+                    Ok(l) => Event::Input(l),
+                    Err(_) => break
+                };
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Event::InputClosed);
+        })
+        .unwrap();
+}
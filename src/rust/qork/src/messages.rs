@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use log::LogRecord;
+use log4rs::append::Append;
+use log4rs::file::{Deserialize, Deserializers};
+use time::{now, strftime};
+
+/// The title of the special buffer which receives log messages.
+pub const MESSAGES_BUFFER_TITLE: &'static str = "*messages*";
+
+/// A thread-safe queue of formatted log messages. Log records can arrive from any thread, but
+/// buffers are single-threaded, so the `MessagesAppender` pushes messages into this queue
+/// and the main loop periodically drains them into the *messages* buffer.
+#[derive(Clone, Default)]
+pub struct MessageSink {
+    pending: Arc<Mutex<Vec<String>>>
+}
+
+impl MessageSink {
+    pub fn new() -> MessageSink {
+        MessageSink::default()
+    }
+
+    pub fn push(&self, message: String) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push(message);
+        }
+    }
+
+    /// Removes and returns all the messages received since the last call.
+    pub fn drain(&self) -> Vec<String> {
+        match self.pending.lock() {
+            Ok(mut pending) => mem::replace(&mut *pending, Vec::new()),
+            Err(_) => Vec::new()
+        }
+    }
+}
+
+impl fmt::Debug for MessageSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MessageSink")
+    }
+}
+
+/// A log4rs appender which sends log records to the *messages* buffer.
+#[derive(Debug)]
+pub struct MessagesAppender {
+    sink: MessageSink
+}
+
+impl MessagesAppender {
+    pub fn new(sink: MessageSink) -> MessagesAppender {
+        MessagesAppender { sink }
+    }
+}
+
+impl Append for MessagesAppender {
+    fn append(&self, record: &LogRecord) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let timestamp = strftime("%H:%M:%S", &now()).unwrap_or_default();
+        self.sink.push(format!("{} {:5} {} - {}", timestamp, record.level(), record.target(), record.args()));
+        Ok(())
+    }
+}
+
+/// The configuration of the messages appender in logging.toml. It has no settings.
+#[derive(Deserialize)]
+pub struct MessagesAppenderConfig {}
+
+/// Allows the messages appender to be used in logging.toml, using `kind = "messages"`.
+pub struct MessagesAppenderDeserializer {
+    sink: MessageSink
+}
+
+impl Deserialize for MessagesAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = MessagesAppenderConfig;
+
+    fn deserialize(&self, _config: MessagesAppenderConfig, _deserializers: &Deserializers)
+        -> Result<Box<dyn Append>, Box<dyn Error + Sync + Send>>
+    {
+        Ok(Box::new(MessagesAppender::new(self.sink.clone())))
+    }
+}
+
+/// Returns the default log4rs deserializers plus one for the messages appender.
+pub fn deserializers(sink: &MessageSink) -> Deserializers {
+    let mut d = Deserializers::new();
+    d.insert("messages", MessagesAppenderDeserializer { sink: sink.clone() });
+    d
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_returns_pushed_messages_in_order_and_empties_sink() {
+        let sink = MessageSink::new();
+        sink.push(String::from("a"));
+        sink.push(String::from("b"));
+        assert_eq!(sink.drain(), vec!["a", "b"]);
+        assert!(sink.drain().is_empty());
+    }
+
+    #[test]
+    fn clones_share_the_same_queue() {
+        let sink = MessageSink::new();
+        let clone = sink.clone();
+        clone.push(String::from("a"));
+        assert_eq!(sink.drain(), vec!["a"]);
+    }
+}
@@ -5,6 +5,7 @@ use std::io::prelude::*;
 use std::ops::Index;
 use std::path::Path;
 use std::slice::Iter;
+use fs;
 
 /// A simple MRU-list data structure. Create a list of the appropriate
 /// maximum size (which can be changed later) then use `insert` to add new
@@ -124,14 +125,11 @@ impl MRUList {
 
     pub fn save<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize, String> {
         if self.is_changed {
-            return File::create(filename)
-                .map_err(|err| err.to_string())
-                .and_then(|mut f| {
-                    let result = self.write(&mut f);
-                    self.is_changed = false;
-                    result
-                    }
-                );
+            let mut contents = Vec::new();
+            let num_bytes = self.write(&mut contents)?;
+            fs::write_atomically(filename.as_ref(), &contents).map_err(|err| err.to_string())?;
+            self.is_changed = false;
+            return Ok(num_bytes);
         }
 
         Ok(0)
@@ -201,14 +199,14 @@ mod tests {
     #[test]
     fn save_if_mru_is_changed_writes_file() {
         let mut mru = make_simple_mru();
-        let mut file = NamedTempFile::new().expect("failed to create temporary file");
+        let file = NamedTempFile::new().expect("failed to create temporary file");
         let cnt = mru.save(file.path()).unwrap();
 
         assert!(!mru.is_changed());
 
-        file.seek(SeekFrom::Start(0)).unwrap();
+        // The save replaces the file, so it has to be opened again to see the new contents.
         let mut output = String::new();
-        file.read_to_string(&mut output).unwrap();
+        File::open(file.path()).unwrap().read_to_string(&mut output).unwrap();
         assert_eq!(output, SIMPLE_MRU_AS_STRING);
         assert_eq!(output.len(), cnt);
     }
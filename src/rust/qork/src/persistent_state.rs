@@ -1,6 +1,11 @@
+use command_history::CommandHistory;
 use fs::{DataDir, BaseDir};
 use configuration::Configuration;
 use mru_list::MRUList;
+use spelling::PersonalWordList;
+use regex::Regex;
+use view::FoldStore;
+use workspace::WorkspaceStore;
 
 /// Represents the persistent runtime data of the system. This is things like MRU lists
 /// that we expect to get written to disk and be available the next time we start.
@@ -8,16 +13,28 @@ use mru_list::MRUList;
 /// always used are non-lazy, while things that are used less frequently are wrapped
 /// by a Lazy<T>.
 pub struct PersistentState {
-    mru: MRUList
+    mru: MRUList,
+    personal_words: PersonalWordList,
+    folds: FoldStore,
+    workspaces: WorkspaceStore,
+    command_history: CommandHistory
 }
 
 const MRU_FILE : &'static str = "mru.toml";
+const PERSONAL_WORDS_FILE : &'static str = "personal_words.txt";
+const FOLDS_FILE : &'static str = "folds.toml";
+const WORKSPACES_FILE : &'static str = "workspaces.toml";
+const COMMAND_HISTORY_FILE : &'static str = "command_history.txt";
 
 impl PersistentState {
     /// Constructs a new PersistentState object based on the default configuration.
     pub fn new(config: &Configuration) -> PersistentState {
         PersistentState {
-            mru: MRUList::new(config.max_mru_items())
+            mru: MRUList::new(config.max_mru_items()),
+            personal_words: PersonalWordList::new(),
+            folds: FoldStore::new(),
+            workspaces: WorkspaceStore::new(),
+            command_history: CommandHistory::new(config.max_command_history(), history_exclusions(config))
         }
     }
 
@@ -35,6 +52,39 @@ impl PersistentState {
                     info!("Loaded {} items into the MRU List from {:?}", rd.mru.iter().count(), path);
                 }));
 
+        data_dir.get_existing_path(PERSONAL_WORDS_FILE)
+            .map(|path| PersonalWordList::load(&path)
+                .map(|pwl| {
+                    rd.personal_words = pwl;
+                    info!("Loaded {} words into the personal word list from {:?}", rd.personal_words.len(), path);
+                }));
+
+        if config.save_folds() {
+            data_dir.get_existing_path(FOLDS_FILE)
+                .map(|path| FoldStore::load(&path)
+                    .map(|folds| {
+                        rd.folds = folds;
+                        info!("Loaded folds from {:?}", path);
+                    })
+                    .map_err(|err| warn!("Error reading {:?}: {}", path, err)));
+        }
+
+        data_dir.get_existing_path(WORKSPACES_FILE)
+            .map(|path| WorkspaceStore::load(&path)
+                .map(|workspaces| {
+                    rd.workspaces = workspaces;
+                    info!("Loaded {} workspaces from {:?}", rd.workspaces.len(), path);
+                })
+                .map_err(|err| warn!("Error reading {:?}: {}", path, err)));
+
+        data_dir.get_existing_path(COMMAND_HISTORY_FILE)
+            .map(|path| CommandHistory::load(config.max_command_history(), history_exclusions(config), &path)
+                .map(|history| {
+                    rd.command_history = history;
+                    info!("Loaded {} lines into the command history from {:?}", rd.command_history.len(), path);
+                })
+                .map_err(|err| warn!("Error reading {:?}: {}", path, err)));
+
         rd
     }
 
@@ -46,9 +96,58 @@ impl PersistentState {
                 .map(|path| self.mru.save(&path)
                     .map(|num_bytes| info!("Wrote {} bytes to {:?}", num_bytes, &path)));
         }
+
+        if self.personal_words.is_changed() {
+            data_dir.get_proposed_path(PERSONAL_WORDS_FILE)
+                .map(|path| self.personal_words.save(&path)
+                    .map(|num_bytes| info!("Wrote {} bytes to {:?}", num_bytes, &path)));
+        }
+
+        if self.folds.is_changed() {
+            data_dir.get_proposed_path(FOLDS_FILE)
+                .map(|path| self.folds.save(&path)
+                    .map(|num_bytes| info!("Wrote {} bytes to {:?}", num_bytes, &path)));
+        }
+
+        if self.workspaces.is_changed() {
+            data_dir.get_proposed_path(WORKSPACES_FILE)
+                .map(|path| self.workspaces.save(&path)
+                    .map(|num_bytes| info!("Wrote {} bytes to {:?}", num_bytes, &path)));
+        }
+
+        if self.command_history.is_changed() {
+            data_dir.get_proposed_path(COMMAND_HISTORY_FILE)
+                .map(|path| self.command_history.save(&path)
+                    .map(|num_bytes| info!("Wrote {} bytes to {:?}", num_bytes, &path)));
+        }
     }
 
     pub fn mru(&mut self) -> &mut MRUList {
         &mut self.mru
     }
+
+    pub fn personal_words(&mut self) -> &mut PersonalWordList {
+        &mut self.personal_words
+    }
+
+    pub fn folds(&mut self) -> &mut FoldStore {
+        &mut self.folds
+    }
+
+    pub fn workspaces(&mut self) -> &mut WorkspaceStore {
+        &mut self.workspaces
+    }
+
+    pub fn command_history(&mut self) -> &mut CommandHistory {
+        &mut self.command_history
+    }
+}
+
+/// Compiles the configured patterns for command lines which are not kept in the history.
+fn history_exclusions(config: &Configuration) -> Vec<Regex> {
+    config.command_history_exclude().iter()
+        .filter_map(|pattern| Regex::new(pattern)
+            .map_err(|err| warn!("Invalid command_history_exclude pattern {:?}: {}", pattern, err))
+            .ok())
+        .collect()
 }
@@ -35,6 +35,11 @@ pub struct ProgramInfo {
 
 impl ProgramInfo {
     pub fn new() -> ProgramInfo {
+        ProgramInfo::with_arguments(CommandLineArguments::new())
+    }
+
+    /// Gathers the information about the program, with the command line already parsed.
+    pub fn with_arguments(parsed_args: CommandLineArguments) -> ProgramInfo {
         let path = std::env::current_exe().ok();
         let md = path.as_ref().and_then(|e| e.metadata().ok());
 
@@ -42,7 +47,7 @@ impl ProgramInfo {
             path: path,
             meta_data: md,
             raw_args: std::env::args().collect(),
-            parsed_args: CommandLineArguments::new(),
+            parsed_args: parsed_args,
             pid: unsafe { libc::getpid() },
             parent_pid: unsafe { libc::getppid() },
             uid: users::get_current_uid(),
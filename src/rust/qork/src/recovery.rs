@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fs::{self as stdfs, File};
+use std::io::{self, Read};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use libc;
+use libc::pid_t;
+use rand::{thread_rng, Rng};
+use toml;
+
+use buffer::{Buffer, BufferCollection, BufferFactory, BufferId};
+use fs;
+
+/// The sub-directory of the data directory in which recovery files are stored.
+pub const RECOVERY_DIR: &'static str = "recovery";
+
+/// Recovery files hold the text of unsaved buffers, so like Vim's swap files they are only
+/// readable by their owner.
+const DIR_MODE: u32 = 0o700;
+const FILE_MODE: u32 = 0o600;
+
+const HEADER_EXTENSION: &'static str = "toml";
+const TEXT_EXTENSION: &'static str = "txt";
+
+/// Describes a recovery file: which process wrote it and which buffer it belongs to. The
+/// text of the buffer is stored alongside it in a file with the same stem.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecoveryHeader {
+    pub pid: pid_t,
+    pub filename: Option<PathBuf>,
+    pub title: String,
+    /// Seconds since the Unix epoch.
+    pub written: u64
+}
+
+/// A recovery file left behind by a process which is no longer running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Orphan {
+    pub header: RecoveryHeader,
+    header_path: PathBuf,
+    text_path: PathBuf
+}
+
+impl Orphan {
+    pub fn load_text(&self) -> Result<String, String> {
+        fs::load_to_string(&self.text_path)
+    }
+
+    /// Restores the recovered text into a buffer, which is marked as changed, then deletes the
+    /// recovery files. A recovered file is opened (or the existing buffer on it is used) so
+    /// that saving will overwrite it.
+    pub fn restore(&self, buffers: &mut BufferCollection, factory: &mut BufferFactory) -> Result<BufferId, String> {
+        let text = self.load_text()?;
+        let id = match self.header.filename {
+            Some(ref f) => buffers.open_file(factory, f).map_err(|e| e.to_string())?,
+            None => buffers.new_buffer(factory, &self.header.title)
+        };
+
+        {
+            let mut b = buffers[id].borrow_mut();
+            b.widen();
+            let len = b.text().len();
+            b.replace(0..len, &text);
+        }
+
+        self.discard();
+        Ok(id)
+    }
+
+    /// Whether the recovery files are still there and the process which wrote them is still
+    /// not running. Another Qork may have restored or discarded them since they were found.
+    pub fn is_still_orphaned(&self) -> bool {
+        self.header_path.exists() && !is_process_alive(self.header.pid)
+    }
+
+    /// Deletes the recovery files.
+    pub fn discard(&self) {
+        remove_files(&self.header_path, &self.text_path);
+    }
+}
+
+/// Periodically writes the text of modified buffers to recovery files, so that work can be
+/// recovered if Qork dies before the buffers are saved. A buffer is written when it has been
+/// edited `max_edits` times since it was last written, or when `interval` has passed. Each
+/// buffer backed by a file is keyed by its path; other buffers are given a random key. The
+/// keys include the pid, so that several processes editing the same file do not share, and
+/// delete, each other's recovery files.
+pub struct RecoveryJournal {
+    dir: Option<PathBuf>,
+    pid: pid_t,
+    interval: Duration,
+    max_edits: usize,
+    last_write: Instant,
+    keys: HashMap<BufferId, String>,
+    written_revisions: HashMap<BufferId, usize>
+}
+
+impl RecoveryJournal {
+    /// Creates a journal writing into `dir`. If `dir` is None, or both `interval_secs` and
+    /// `max_edits` are 0, nothing is ever written.
+    pub fn new(dir: Option<PathBuf>, pid: pid_t, interval_secs: u64, max_edits: usize) -> RecoveryJournal {
+        let enabled = interval_secs > 0 || max_edits > 0;
+
+        RecoveryJournal {
+            dir: if enabled { dir } else { None },
+            pid,
+            interval: Duration::from_secs(interval_secs),
+            max_edits,
+            last_write: Instant::now(),
+            keys: HashMap::new(),
+            written_revisions: HashMap::new()
+        }
+    }
+
+    fn is_interval_due(&self) -> bool {
+        self.interval.as_secs() > 0 && self.last_write.elapsed() >= self.interval
+    }
+
+    /// Writes recovery files for those of `buffers` that are due. Buffers without unsaved
+    /// changes are never written.
+    pub fn autosave<'a, I>(&mut self, buffers: I)
+        where I: IntoIterator<Item = &'a Buffer>
+    {
+        if self.dir.is_none() {
+            return;
+        }
+
+        let interval_due = self.is_interval_due();
+        let mut wrote_any = false;
+
+        for buffer in buffers {
+            if !buffer.is_changed() || buffer.is_read_only() {
+                continue;
+            }
+
+            let written = self.written_revisions.get(&buffer.id()).cloned().unwrap_or(0);
+            let edits = buffer.revision().saturating_sub(written);
+            let edits_due = self.max_edits > 0 && edits >= self.max_edits;
+
+            if edits > 0 && (interval_due || edits_due) {
+                match self.write(buffer) {
+                    Ok(_) => wrote_any = true,
+                    Err(e) => warn!("Could not write recovery file for buffer {}: {}", buffer.id(), e)
+                }
+            }
+        }
+
+        if interval_due || wrote_any {
+            self.last_write = Instant::now();
+        }
+    }
+
+    /// Writes a recovery file for `buffer` unconditionally.
+    pub fn write(&mut self, buffer: &Buffer) -> io::Result<()> {
+        let (header_path, text_path) = match self.paths_for(buffer) {
+            Some(paths) => paths,
+            None => return Ok(())
+        };
+
+        let header = RecoveryHeader {
+            pid: self.pid,
+            filename: buffer.filename().clone(),
+            title: String::from(buffer.title()),
+            written: SystemTime::now().duration_since(::std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+        };
+        let header = toml::to_string(&header).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+        // The text is written first so that a header never refers to a missing text file.
+        fs::write_atomically_with_mode(&text_path, buffer.text().as_bytes(), FILE_MODE)?;
+        fs::write_atomically_with_mode(&header_path, header.as_bytes(), FILE_MODE)?;
+        self.written_revisions.insert(buffer.id(), buffer.revision());
+        info!("Wrote recovery file {:?} for buffer {}", text_path, buffer.id());
+        Ok(())
+    }
+
+    /// Deletes the recovery files for a buffer, which should be done when it is saved or closed.
+    pub fn remove(&mut self, buffer_id: BufferId) {
+        self.written_revisions.remove(&buffer_id);
+        if let (Some(dir), Some(key)) = (self.dir.as_ref(), self.keys.remove(&buffer_id)) {
+            let (header_path, text_path) = recovery_paths(dir, &key);
+            remove_files(&header_path, &text_path);
+        }
+    }
+
+    /// Deletes all the recovery files written by this process, which should be done when Qork
+    /// exits normally.
+    pub fn remove_all(&mut self) {
+        let ids: Vec<_> = self.keys.keys().cloned().collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+
+    fn paths_for(&mut self, buffer: &Buffer) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_ref()?;
+        if let Err(e) = stdfs::DirBuilder::new().recursive(true).mode(DIR_MODE).create(dir) {
+            warn!("Could not create recovery directory {:?}: {}", dir, e);
+            return None;
+        }
+
+        let pid = self.pid;
+        let key = self.keys.entry(buffer.id()).or_insert_with(|| {
+            match *buffer.filename() {
+                Some(ref f) => format!("{}-{}", pid, fs::path_to_filename(f)),
+                None => {
+                    let id: String = thread_rng().gen_ascii_chars().take(10).collect();
+                    format!("{}-untitled-{}", pid, id)
+                }
+            }
+        });
+
+        Some(recovery_paths(dir, key))
+    }
+}
+
+fn recovery_paths(dir: &Path, key: &str) -> (PathBuf, PathBuf) {
+    (dir.join(format!("{}.{}", key, HEADER_EXTENSION)), dir.join(format!("{}.{}", key, TEXT_EXTENSION)))
+}
+
+fn remove_files(header_path: &Path, text_path: &Path) {
+    for path in &[header_path, text_path] {
+        if let Err(e) = stdfs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Could not delete recovery file {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Returns true if there is a running process with the given pid.
+pub fn is_process_alive(pid: pid_t) -> bool {
+    let rc = unsafe { libc::kill(pid, 0) };
+    // EPERM means the process exists but belongs to someone else.
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Finds the recovery files in `dir` which were written by processes that are no longer
+/// running, and hence were never cleaned up.
+pub fn find_orphans(dir: &Path) -> Vec<Orphan> {
+    let entries = match stdfs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+
+    let mut orphans: Vec<_> = entries.filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map_or(false, |ext| ext == HEADER_EXTENSION))
+        .filter_map(|header_path| {
+            let header = match read_header(&header_path) {
+                Ok(h) => h,
+                Err(e) => { warn!("Ignoring invalid recovery file {:?}: {}", header_path, e); return None; }
+            };
+            if is_process_alive(header.pid) {
+                return None;
+            }
+            let text_path = header_path.with_extension(TEXT_EXTENSION);
+            Some(Orphan { header, header_path, text_path })
+        })
+        .collect();
+
+    orphans.sort_by(|a, b| a.header.written.cmp(&b.header.written));
+    orphans
+}
+
+fn read_header(path: &Path) -> Result<RecoveryHeader, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|err| err.to_string())?;
+    toml::from_str(&contents).map_err(|err| err.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::BufferFactory;
+    use fs::TestDir;
+
+    /// A pid which cannot belong to a running process.
+    const DEAD_PID: pid_t = pid_t::max_value();
+
+    fn changed_buffer(fac: &mut BufferFactory, text: &str) -> Buffer {
+        let mut b = fac.new_empty_buffer();
+        b.replace(0..0, text);
+        b
+    }
+
+    #[test]
+    fn is_process_alive_for_own_pid_returns_true() {
+        assert!(is_process_alive(unsafe { libc::getpid() }));
+        assert!(!is_process_alive(DEAD_PID));
+    }
+
+    #[test]
+    fn autosave_writes_buffers_once_max_edits_is_reached() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 2);
+        let mut fac = BufferFactory::new();
+        let mut b = changed_buffer(&mut fac, "a");
+
+        journal.autosave(vec![&b]);
+        assert!(find_orphans(&dir.join("r")).is_empty());
+
+        b.replace(0..0, "b");
+        journal.autosave(vec![&b]);
+        let orphans = find_orphans(&dir.join("r"));
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].load_text().unwrap(), "ba");
+    }
+
+    #[test]
+    fn autosave_for_unchanged_buffer_writes_nothing() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut fac = BufferFactory::new();
+        let b = fac.new_empty_buffer();
+        journal.autosave(vec![&b]);
+        assert!(find_orphans(&dir.join("r")).is_empty());
+    }
+
+    #[test]
+    fn find_orphans_ignores_files_of_running_processes() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), unsafe { libc::getpid() }, 0, 1);
+        let mut fac = BufferFactory::new();
+        let b = changed_buffer(&mut fac, "a");
+        journal.write(&b).unwrap();
+        assert!(find_orphans(&dir.join("r")).is_empty());
+    }
+
+    #[test]
+    fn write_makes_recovery_files_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut fac = BufferFactory::new();
+        journal.write(&changed_buffer(&mut fac, "a")).unwrap();
+
+        let mode = |p: &Path| stdfs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.join("r")), DIR_MODE);
+        for entry in stdfs::read_dir(dir.join("r")).unwrap() {
+            assert_eq!(mode(&entry.unwrap().path()), FILE_MODE);
+        }
+    }
+
+    #[test]
+    fn remove_deletes_recovery_files() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut fac = BufferFactory::new();
+        let b = changed_buffer(&mut fac, "a");
+        journal.write(&b).unwrap();
+        journal.remove(b.id());
+        assert!(find_orphans(&dir.join("r")).is_empty());
+    }
+
+    #[test]
+    fn orphan_restore_for_untitled_buffer_creates_changed_buffer_and_deletes_files() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut fac = BufferFactory::new();
+        let b = changed_buffer(&mut fac, "hello");
+        journal.write(&b).unwrap();
+
+        let mut bc = BufferCollection::new();
+        let orphans = find_orphans(&dir.join("r"));
+        let id = orphans[0].restore(&mut bc, &mut fac).unwrap();
+
+        let restored = bc[id].borrow();
+        assert_eq!(restored.text(), "hello");
+        assert!(restored.is_changed());
+        assert!(find_orphans(&dir.join("r")).is_empty());
+    }
+
+    #[test]
+    fn orphan_header_records_filename_and_title() {
+        let dir = TestDir::new();
+        let mut journal = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut fac = BufferFactory::new();
+        let mut b = fac.open_file("/c/foo.txt").unwrap();
+        b.replace(0..0, "a");
+        journal.write(&b).unwrap();
+
+        let orphans = find_orphans(&dir.join("r"));
+        assert_eq!(orphans[0].header.filename, Some(PathBuf::from("/c/foo.txt")));
+        assert_eq!(orphans[0].header.title, "/c/foo.txt");
+        orphans[0].discard();
+        assert!(find_orphans(&dir.join("r")).is_empty());
+    }
+
+    #[test]
+    fn processes_editing_the_same_file_keep_separate_recovery_files() {
+        let dir = TestDir::new();
+        let mut first = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID, 0, 1);
+        let mut second = RecoveryJournal::new(Some(dir.join("r")), DEAD_PID - 1, 0, 1);
+        let mut fac = BufferFactory::new();
+        let mut b = fac.open_file("/c/foo.txt").unwrap();
+        b.replace(0..0, "a");
+        first.write(&b).unwrap();
+        second.write(&b).unwrap();
+        assert_eq!(find_orphans(&dir.join("r")).len(), 2);
+
+        second.remove_all();
+        let orphans = find_orphans(&dir.join("r"));
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].header.pid, DEAD_PID);
+        assert!(orphans[0].is_still_orphaned());
+        orphans[0].discard();
+        assert!(!orphans[0].is_still_orphaned());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+/// Hunspell flags are stored as integers. How the textual form is converted depends on the
+/// `FLAG` setting in the affix file.
+pub type Flag = u32;
+
+/// The ways in which Hunspell can encode flags in `.dic` and `.aff` files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagFormat {
+    /// The default: every character is a flag.
+    Char,
+    /// `FLAG long`: every pair of characters is a flag.
+    Long,
+    /// `FLAG num`: flags are decimal numbers separated by commas.
+    Num
+}
+
+impl FlagFormat {
+    pub fn parse_flags(&self, s: &str) -> Vec<Flag> {
+        match *self {
+            FlagFormat::Char => s.chars().map(|c| c as Flag).collect(),
+            FlagFormat::Long => {
+                let chars: Vec<_> = s.chars().collect();
+                chars.chunks(2).map(|pair| {
+                    let hi = pair[0] as Flag;
+                    let lo = if pair.len() > 1 { pair[1] as Flag } else { 0 };
+                    (hi << 16) | lo
+                }).collect()
+            }
+            FlagFormat::Num => s.split(',').filter_map(|n| n.trim().parse().ok()).collect()
+        }
+    }
+}
+
+/// One element of an affix condition, which is a much simplified regular expression.
+#[derive(Debug, PartialEq)]
+enum ConditionElement {
+    Any,
+    Char(char),
+    OneOf(Vec<char>),
+    NoneOf(Vec<char>)
+}
+
+impl ConditionElement {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ConditionElement::Any => true,
+            ConditionElement::Char(x) => x == c,
+            ConditionElement::OneOf(ref v) => v.contains(&c),
+            ConditionElement::NoneOf(ref v) => !v.contains(&c)
+        }
+    }
+}
+
+/// The condition that a stem must satisfy for an affix to be applied to it. For suffixes the
+/// condition is matched against the end of the stem, for prefixes against the beginning.
+#[derive(Debug, PartialEq)]
+pub struct Condition {
+    elements: Vec<ConditionElement>
+}
+
+impl Condition {
+    pub fn parse(s: &str) -> Condition {
+        let mut elements = Vec::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => elements.push(ConditionElement::Any),
+                '[' => {
+                    let mut set: Vec<char> = chars.by_ref().take_while(|&c| c != ']').collect();
+                    if !set.is_empty() && set[0] == '^' {
+                        set.remove(0);
+                        elements.push(ConditionElement::NoneOf(set));
+                    } else {
+                        elements.push(ConditionElement::OneOf(set));
+                    }
+                }
+                _ => elements.push(ConditionElement::Char(c))
+            }
+        }
+
+        // A condition of "." matches everything, so we can dispense with it entirely.
+        if elements == vec![ConditionElement::Any] {
+            elements.clear();
+        }
+
+        Condition { elements }
+    }
+
+    pub fn matches_start(&self, stem: &str) -> bool {
+        let chars: Vec<_> = stem.chars().take(self.elements.len()).collect();
+        chars.len() == self.elements.len() &&
+            self.elements.iter().zip(chars).all(|(e, c)| e.matches(c))
+    }
+
+    pub fn matches_end(&self, stem: &str) -> bool {
+        let chars: Vec<_> = stem.chars().rev().take(self.elements.len()).collect();
+        chars.len() == self.elements.len() &&
+            self.elements.iter().rev().zip(chars).all(|(e, c)| e.matches(c))
+    }
+}
+
+/// A single `PFX` or `SFX` rule.
+#[derive(Debug)]
+pub struct AffixRule {
+    pub flag: Flag,
+    pub cross_product: bool,
+    /// The characters removed from the stem before `add` is applied. "0" in the file means none.
+    pub strip: String,
+    /// The characters added to the stem. "0" in the file means none.
+    pub add: String,
+    pub condition: Condition
+}
+
+/// The parts of a Hunspell `.aff` file that we make use of: prefix and suffix rules, the
+/// characters to try when making suggestions, and common misspelling replacements. Everything
+/// else is ignored. The file is assumed to be UTF-8.
+#[derive(Debug)]
+pub struct AffixFile {
+    pub flag_format: FlagFormat,
+    pub try_chars: Vec<char>,
+    pub replacements: Vec<(String, String)>,
+    pub prefixes: Vec<AffixRule>,
+    pub suffixes: Vec<AffixRule>
+}
+
+impl Default for AffixFile {
+    fn default() -> AffixFile {
+        AffixFile {
+            flag_format: FlagFormat::Char,
+            try_chars: "abcdefghijklmnopqrstuvwxyz".chars().collect(),
+            replacements: Vec::new(),
+            prefixes: Vec::new(),
+            suffixes: Vec::new()
+        }
+    }
+}
+
+fn zero_to_empty(s: &str) -> String {
+    if s == "0" { String::new() } else { String::from(s) }
+}
+
+impl AffixFile {
+    pub fn read<T: Read>(src: &mut T) -> Result<AffixFile, String> {
+        let mut aff = AffixFile::default();
+        // Maps a flag to its cross product setting, which is only given on the header line.
+        let mut cross_products = HashMap::<Flag, bool>::new();
+
+        for line in BufReader::new(src).lines() {
+            let line = line.map_err(|err| err.to_string())?;
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+
+            match fields[0] {
+                "FLAG" if fields.len() > 1 => {
+                    aff.flag_format = match fields[1] {
+                        "long" => FlagFormat::Long,
+                        "num" => FlagFormat::Num,
+                        _ => FlagFormat::Char
+                    };
+                }
+                "TRY" if fields.len() > 1 => aff.try_chars = fields[1].chars().collect(),
+                "REP" if fields.len() > 2 => {
+                    aff.replacements.push((fields[1].replace('_', " "), fields[2].replace('_', " ")));
+                }
+                "PFX" | "SFX" if fields.len() == 4 => {
+                    let flag = aff.flag_format.parse_flags(fields[1]).first().cloned().unwrap_or(0);
+                    cross_products.insert(flag, fields[2] == "Y");
+                }
+                "PFX" | "SFX" if fields.len() > 4 => {
+                    let flag = aff.flag_format.parse_flags(fields[1]).first().cloned().unwrap_or(0);
+                    // Continuation flags on the affix ("s/XY") are not supported, so drop them.
+                    let add = fields[3].split('/').next().unwrap_or("");
+                    let rule = AffixRule {
+                        flag,
+                        cross_product: cross_products.get(&flag).cloned().unwrap_or(false),
+                        strip: zero_to_empty(fields[2]),
+                        add: zero_to_empty(add),
+                        condition: Condition::parse(fields[4])
+                    };
+
+                    if fields[0] == "PFX" {
+                        aff.prefixes.push(rule);
+                    } else {
+                        aff.suffixes.push(rule);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(aff)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFF: &'static str = "# A comment
+SET UTF-8
+TRY esianrtolcdugmphbyfvkwz
+
+REP 1
+REP f ph
+
+PFX A Y 1
+PFX A   0     re         .
+
+SFX D Y 2
+SFX D   0     d          e
+SFX D   y     ied        [^aeiou]y
+";
+
+    #[test]
+    fn condition_parse_for_single_dot_matches_everything() {
+        let c = Condition::parse(".");
+        assert!(c.matches_end(""));
+        assert!(c.matches_start("abc"));
+    }
+
+    #[test]
+    fn condition_matches_end_for_negated_set_and_char() {
+        let c = Condition::parse("[^aeiou]y");
+        assert!(c.matches_end("cry"));
+        assert!(!c.matches_end("play"));
+        assert!(!c.matches_end("y"));
+    }
+
+    #[test]
+    fn condition_matches_start_for_set() {
+        let c = Condition::parse("[ab]c");
+        assert!(c.matches_start("bcd"));
+        assert!(!c.matches_start("ccd"));
+    }
+
+    #[test]
+    fn flag_format_long_combines_pairs_of_chars() {
+        let flags = FlagFormat::Long.parse_flags("AaBb");
+        assert_eq!(flags.len(), 2);
+        assert_ne!(flags[0], flags[1]);
+    }
+
+    #[test]
+    fn flag_format_num_splits_on_commas() {
+        assert_eq!(FlagFormat::Num.parse_flags("1,20,300"), vec![1, 20, 300]);
+    }
+
+    #[test]
+    fn read_for_valid_file_loads_rules() {
+        let mut src = AFF.as_bytes();
+        let aff = AffixFile::read(&mut src).unwrap();
+
+        assert_eq!(aff.try_chars[0], 'e');
+        assert_eq!(aff.replacements, vec![(String::from("f"), String::from("ph"))]);
+        assert_eq!(aff.prefixes.len(), 1);
+        assert_eq!(aff.prefixes[0].add, "re");
+        assert!(aff.prefixes[0].cross_product);
+        assert_eq!(aff.suffixes.len(), 2);
+        assert_eq!(aff.suffixes[1].strip, "y");
+        assert_eq!(aff.suffixes[1].add, "ied");
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use spelling::affix::{AffixFile, Flag};
+
+/// The maximum number of suggestions returned for a misspelt word.
+const MAX_SUGGESTIONS: usize = 10;
+
+/// A Hunspell dictionary, consisting of a list of stems from the `.dic` file and the prefix
+/// and suffix rules from the `.aff` file that may be applied to them.
+pub struct Dictionary {
+    stems: HashMap<String, Vec<Flag>>,
+    affixes: AffixFile
+}
+
+impl Dictionary {
+    pub fn read<D: Read, A: Read>(dic: &mut D, aff: &mut A) -> Result<Dictionary, String> {
+        let affixes = AffixFile::read(aff)?;
+        let mut stems = HashMap::new();
+
+        // The first line is an approximate word count, which we skip.
+        for line in BufReader::new(dic).lines().skip(1) {
+            let line = line.map_err(|err| err.to_string())?;
+            // Anything after whitespace is morphological data, which we do not use.
+            let entry = line.split_whitespace().next().unwrap_or("");
+            if entry.is_empty() {
+                continue;
+            }
+
+            let (word, flags) = split_entry(entry);
+            stems.insert(word, affixes.flag_format.parse_flags(&flags));
+        }
+
+        Ok(Dictionary { stems, affixes })
+    }
+
+    pub fn load<P: AsRef<Path>>(dic_filename: P, aff_filename: P) -> Result<Dictionary, String> {
+        let mut dic = File::open(dic_filename).map_err(|err| err.to_string())?;
+        let mut aff = File::open(aff_filename).map_err(|err| err.to_string())?;
+        Dictionary::read(&mut dic, &mut aff)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stems.is_empty()
+    }
+
+    /// Checks whether `word` is correctly spelt. Words which are capitalized or in all-caps
+    /// are also accepted if their lower-case form is in the dictionary.
+    pub fn check(&self, word: &str) -> bool {
+        if self.check_exact_case(word) {
+            return true;
+        }
+
+        let lower = word.to_lowercase();
+        if lower != word && self.check_exact_case(&lower) {
+            return true;
+        }
+
+        // "PARIS" should be accepted if the dictionary contains "Paris".
+        let capitalized = capitalize(&lower);
+        capitalized != word && word.chars().all(|c| !c.is_lowercase()) && self.check_exact_case(&capitalized)
+    }
+
+    fn check_exact_case(&self, word: &str) -> bool {
+        self.stems.contains_key(word) || self.check_suffixed(word) || self.check_prefixed(word)
+    }
+
+    fn has_flag(&self, stem: &str, flag: Flag) -> bool {
+        self.stems.get(stem).map_or(false, |flags| flags.contains(&flag))
+    }
+
+    fn check_suffixed(&self, word: &str) -> bool {
+        for sfx in &self.affixes.suffixes {
+            if !word.ends_with(&sfx.add) || word.len() == sfx.add.len() {
+                continue;
+            }
+
+            let stem = String::from(&word[..word.len() - sfx.add.len()]) + &sfx.strip;
+            if !sfx.condition.matches_end(&stem) {
+                continue;
+            }
+
+            if self.has_flag(&stem, sfx.flag) {
+                return true;
+            }
+
+            // Try a prefix as well, e.g. "re" + "build" + "ing".
+            if sfx.cross_product {
+                for pfx in self.affixes.prefixes.iter().filter(|p| p.cross_product) {
+                    if stem.starts_with(&pfx.add) && stem.len() > pfx.add.len() {
+                        let inner = pfx.strip.clone() + &stem[pfx.add.len()..];
+                        if pfx.condition.matches_start(&inner) &&
+                            self.has_flag(&inner, sfx.flag) && self.has_flag(&inner, pfx.flag) {
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    fn check_prefixed(&self, word: &str) -> bool {
+        self.affixes.prefixes.iter().any(|pfx| {
+            if !word.starts_with(&pfx.add) || word.len() == pfx.add.len() {
+                return false;
+            }
+
+            let stem = pfx.strip.clone() + &word[pfx.add.len()..];
+            pfx.condition.matches_start(&stem) && self.has_flag(&stem, pfx.flag)
+        })
+    }
+
+    /// Suggests correctly spelt words that are similar to `word`. Candidates are generated by
+    /// applying the `REP` replacements from the affix file and then by deleting, transposing,
+    /// replacing or inserting a single character, using the characters from the `TRY` line.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let mut result = Vec::new();
+        let add = |candidate: String, result: &mut Vec<String>| {
+            if !result.contains(&candidate) && candidate != word && self.check(&candidate) {
+                result.push(candidate);
+            }
+        };
+
+        for &(ref from, ref to) in &self.affixes.replacements {
+            for (idx, _) in word.match_indices(from.as_str()) {
+                let candidate = String::from(&word[..idx]) + to + &word[idx + from.len()..];
+                add(candidate, &mut result);
+            }
+        }
+
+        let chars: Vec<char> = word.chars().collect();
+        for i in 0..chars.len() {
+            let mut v = chars.clone();
+            v.remove(i);
+            add(v.into_iter().collect(), &mut result);
+        }
+
+        for i in 1..chars.len() {
+            let mut v = chars.clone();
+            v.swap(i - 1, i);
+            add(v.into_iter().collect(), &mut result);
+        }
+
+        for i in 0..chars.len() + 1 {
+            for &c in &self.affixes.try_chars {
+                if i < chars.len() {
+                    let mut v = chars.clone();
+                    v[i] = c;
+                    add(v.into_iter().collect(), &mut result);
+                }
+
+                let mut v = chars.clone();
+                v.insert(i, c);
+                add(v.into_iter().collect(), &mut result);
+            }
+        }
+
+        result.truncate(MAX_SUGGESTIONS);
+        result
+    }
+}
+
+/// Splits a `.dic` entry such as "work/DGS" into the word and its flags. A slash that is
+/// part of the word is escaped as "\/".
+fn split_entry(entry: &str) -> (String, String) {
+    let mut word = String::new();
+    let mut chars = entry.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => if let Some(next) = chars.next() { word.push(next) },
+            '/' => return (word, chars.collect()),
+            _ => word.push(c)
+        }
+    }
+
+    (word, String::new())
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+        None => String::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFF: &'static str = "TRY esianrtolcdugmphbyfvkwz
+REP 1
+REP f ph
+PFX A Y 1
+PFX A 0 re .
+SFX D Y 2
+SFX D 0 d e
+SFX D y ied [^aeiou]y
+SFX G Y 1
+SFX G e ing e
+";
+
+    const DIC: &'static str = "5
+create/ADG
+cry/D
+phone
+Paris
+and\\/or
+";
+
+    fn make_dictionary() -> Dictionary {
+        Dictionary::read(&mut DIC.as_bytes(), &mut AFF.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn read_loads_all_stems() {
+        let d = make_dictionary();
+        assert_eq!(d.len(), 5);
+    }
+
+    #[test]
+    fn check_for_stem_returns_true() {
+        assert!(make_dictionary().check("create"));
+    }
+
+    #[test]
+    fn check_for_unknown_word_returns_false() {
+        assert!(!make_dictionary().check("krate"));
+    }
+
+    #[test]
+    fn check_for_escaped_slash_in_entry_returns_true() {
+        assert!(make_dictionary().check("and/or"));
+    }
+
+    #[test]
+    fn check_for_suffixed_word_returns_true() {
+        let d = make_dictionary();
+        assert!(d.check("created"));
+        assert!(d.check("creating"));
+        assert!(d.check("cried"));
+    }
+
+    #[test]
+    fn check_for_suffix_whose_flag_is_not_on_stem_returns_false() {
+        assert!(!make_dictionary().check("crying"));
+    }
+
+    #[test]
+    fn check_for_prefixed_word_returns_true() {
+        assert!(make_dictionary().check("recreate"));
+    }
+
+    #[test]
+    fn check_for_prefixed_and_suffixed_word_returns_true() {
+        assert!(make_dictionary().check("recreated"));
+    }
+
+    #[test]
+    fn check_for_capitalized_word_accepts_lower_case_stem() {
+        assert!(make_dictionary().check("Create"));
+        assert!(make_dictionary().check("CREATE"));
+    }
+
+    #[test]
+    fn check_for_all_caps_proper_noun_returns_true() {
+        assert!(make_dictionary().check("PARIS"));
+    }
+
+    #[test]
+    fn check_for_lower_case_proper_noun_returns_false() {
+        assert!(!make_dictionary().check("paris"));
+    }
+
+    #[test]
+    fn suggest_includes_single_edits_and_replacements() {
+        let d = make_dictionary();
+        assert!(d.suggest("craete").contains(&String::from("create")));
+        assert!(d.suggest("fone").contains(&String::from("phone")));
+    }
+}
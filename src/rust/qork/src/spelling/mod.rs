@@ -0,0 +1,196 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+use fs::{BaseDir, ConfigDir, DataDir};
+
+mod affix;
+mod dictionary;
+mod personal_word_list;
+mod regions;
+
+pub use spelling::dictionary::Dictionary;
+pub use spelling::personal_word_list::PersonalWordList;
+pub use spelling::regions::{Syntax, syntax_for_path};
+
+/// The sub-directory of the config and data directories in which dictionaries are stored.
+const DICTIONARY_DIR: &'static str = "dictionaries";
+
+/// A word that was not found in any of the dictionaries or in the personal word list.
+#[derive(Debug, PartialEq)]
+pub struct Misspelling {
+    pub range: Range<usize>,
+    pub word: String
+}
+
+/// Checks words against a set of Hunspell dictionaries. A word is considered to be correct if
+/// it is in any of the dictionaries.
+pub struct SpellChecker {
+    dictionaries: Vec<Dictionary>
+}
+
+impl SpellChecker {
+    pub fn new() -> SpellChecker {
+        SpellChecker { dictionaries: Vec::new() }
+    }
+
+    /// Loads the dictionaries for `languages`. For a language such as "en_US" we look for the
+    /// files "dictionaries/en_US.dic" and "dictionaries/en_US.aff", first in the config
+    /// directory and then in the data directory. Missing or invalid dictionaries are logged
+    /// and skipped.
+    pub fn load<S: AsRef<str>>(languages: &[S], config_dir: &ConfigDir, data_dir: &DataDir) -> SpellChecker {
+        let _timer = timer!("SpellChecker::load");
+
+        let mut checker = SpellChecker::new();
+
+        for language in languages {
+            let language = language.as_ref();
+            let paths = find_dictionary(config_dir, language).or_else(|| find_dictionary(data_dir, language));
+
+            match paths {
+                Some((dic, aff)) => {
+                    match Dictionary::load(&dic, &aff) {
+                        Ok(d) => {
+                            info!("Loaded {} words for language {} from {:?}", d.len(), language, dic);
+                            checker.add_dictionary(d);
+                        }
+                        Err(e) => warn!("Error loading dictionary {:?}: {}", dic, e)
+                    }
+                }
+                None => warn!("No dictionary found for language {}", language)
+            }
+        }
+
+        checker
+    }
+
+    pub fn add_dictionary(&mut self, dictionary: Dictionary) {
+        self.dictionaries.push(dictionary);
+    }
+
+    pub fn has_dictionaries(&self) -> bool {
+        !self.dictionaries.is_empty()
+    }
+
+    pub fn check(&self, word: &str, personal: &PersonalWordList) -> bool {
+        personal.contains(word) || self.dictionaries.iter().any(|d| d.check(word))
+    }
+
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        for d in &self.dictionaries {
+            for s in d.suggest(word) {
+                if !result.contains(&s) {
+                    result.push(s);
+                }
+            }
+        }
+        result
+    }
+
+    /// Finds all the misspelt words in `text`, in order. If a `syntax` is given then only the
+    /// comments and strings are checked.
+    pub fn misspellings(&self, text: &str, syntax: Option<&Syntax>, personal: &PersonalWordList) -> Vec<Misspelling> {
+        if !self.has_dictionaries() {
+            return Vec::new();
+        }
+
+        regions::checkable_regions(text, syntax).into_iter()
+            .flat_map(|region| regions::words_in_region(text, region))
+            .filter(|range| !self.check(&text[range.clone()], personal))
+            .map(|range| Misspelling { word: String::from(&text[range.clone()]), range })
+            .collect()
+    }
+}
+
+fn find_dictionary<D: BaseDir>(dir: &D, language: &str) -> Option<(PathBuf, PathBuf)> {
+    let dic = dir.get_existing_path(format!("{}/{}.dic", DICTIONARY_DIR, language));
+    let aff = dir.get_existing_path(format!("{}/{}.aff", DICTIONARY_DIR, language));
+    match (dic, aff) {
+        (Some(dic), Some(aff)) => Some((dic, aff)),
+        _ => None
+    }
+}
+
+/// Returns the first misspelling which starts at or after `offset`.
+pub fn next_misspelling(misspellings: &[Misspelling], offset: usize) -> Option<&Misspelling> {
+    misspellings.iter().find(|m| m.range.start >= offset)
+}
+
+/// Returns the last misspelling which starts before `offset`.
+pub fn previous_misspelling(misspellings: &[Misspelling], offset: usize) -> Option<&Misspelling> {
+    misspellings.iter().rev().find(|m| m.range.start < offset)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn make_checker() -> SpellChecker {
+        let mut dic = "3\nthe\ncat\nsat\n".as_bytes();
+        let mut aff = "".as_bytes();
+        let mut checker = SpellChecker::new();
+        checker.add_dictionary(Dictionary::read(&mut dic, &mut aff).unwrap());
+        checker
+    }
+
+    #[test]
+    fn misspellings_for_checker_with_no_dictionaries_returns_nothing() {
+        let checker = SpellChecker::new();
+        let pwl = PersonalWordList::new();
+        assert!(checker.misspellings("zzz", None, &pwl).is_empty());
+    }
+
+    #[test]
+    fn misspellings_returns_ranges_of_unknown_words() {
+        let checker = make_checker();
+        let pwl = PersonalWordList::new();
+        let result = checker.misspellings("the cat sta on the mta", None, &pwl);
+        assert_eq!(result, vec![
+            Misspelling { range: 8..11, word: String::from("sta") },
+            Misspelling { range: 12..14, word: String::from("on") },
+            Misspelling { range: 19..22, word: String::from("mta") }
+        ]);
+    }
+
+    #[test]
+    fn misspellings_accepts_words_in_personal_word_list() {
+        let checker = make_checker();
+        let mut pwl = PersonalWordList::new();
+        pwl.insert("qork");
+        assert!(checker.misspellings("the qork", None, &pwl).is_empty());
+    }
+
+    #[test]
+    fn misspellings_for_code_only_checks_comments() {
+        let checker = make_checker();
+        let pwl = PersonalWordList::new();
+        let syntax = syntax_for_path(Path::new("a.rs"));
+        let result = checker.misspellings("let dog = 1; // the dgo", syntax, &pwl);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].word, "dgo");
+    }
+
+    #[test]
+    fn next_and_previous_misspelling_navigate_relative_to_offset() {
+        let checker = make_checker();
+        let pwl = PersonalWordList::new();
+        let result = checker.misspellings("aa the bb the cc", None, &pwl);
+
+        assert_eq!(next_misspelling(&result, 0).unwrap().word, "aa");
+        assert_eq!(next_misspelling(&result, 1).unwrap().word, "bb");
+        assert_eq!(next_misspelling(&result, 7).unwrap().word, "bb");
+        assert_eq!(next_misspelling(&result, 8).unwrap().word, "cc");
+        assert_eq!(next_misspelling(&result, 14).unwrap().word, "cc");
+        assert!(next_misspelling(&result, 15).is_none());
+        assert_eq!(previous_misspelling(&result, 14).unwrap().word, "bb");
+        assert!(previous_misspelling(&result, 0).is_none());
+    }
+
+    #[test]
+    fn suggest_returns_words_from_dictionary() {
+        let checker = make_checker();
+        assert_eq!(checker.suggest("cta"), vec![String::from("cat")]);
+    }
+}
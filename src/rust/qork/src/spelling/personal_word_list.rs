@@ -0,0 +1,110 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use fs;
+
+/// The user's own list of words which should be accepted by the spell checker in addition to
+/// those in the dictionaries. It is stored one word per line in the data directory. Like the
+/// `MRUList`, it tracks whether it has changed so that it is only written when necessary.
+pub struct PersonalWordList {
+    is_changed: bool,
+    words: BTreeSet<String>
+}
+
+impl PersonalWordList {
+    pub fn new() -> PersonalWordList {
+        PersonalWordList {
+            is_changed: false,
+            words: BTreeSet::new()
+        }
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.is_changed
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word)
+    }
+
+    /// Adds a word to the list, returning false if it was already there.
+    pub fn insert<S: Into<String>>(&mut self, word: S) -> bool {
+        let inserted = self.words.insert(word.into());
+        self.is_changed |= inserted;
+        inserted
+    }
+
+    /// Removes a word from the list, returning false if it was not there.
+    pub fn remove(&mut self, word: &str) -> bool {
+        let removed = self.words.remove(word);
+        self.is_changed |= removed;
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn load<P: AsRef<Path>>(filename: P) -> Result<PersonalWordList, String> {
+        fs::load_to_vector(filename.as_ref()).map(|lines| {
+            PersonalWordList {
+                is_changed: false,
+                words: lines.into_iter().map(|l| String::from(l.trim())).filter(|l| !l.is_empty()).collect()
+            }
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize, String> {
+        if !self.is_changed {
+            return Ok(0);
+        }
+
+        let result = fs::save_from_vector(filename.as_ref(), self.words.iter().cloned().collect());
+        if result.is_ok() {
+            self.is_changed = false;
+        }
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn insert_for_new_word_sets_is_changed() {
+        let mut pwl = PersonalWordList::new();
+        assert!(pwl.insert("qork"));
+        assert!(pwl.is_changed());
+        assert!(pwl.contains("qork"));
+    }
+
+    #[test]
+    fn insert_for_existing_word_returns_false() {
+        let mut pwl = PersonalWordList::new();
+        pwl.insert("qork");
+        assert!(!pwl.insert("qork"));
+        assert_eq!(pwl.len(), 1);
+    }
+
+    #[test]
+    fn save_then_load_round_trips_words() {
+        let mut pwl = PersonalWordList::new();
+        pwl.insert("qork");
+        pwl.insert("xdg");
+        let file = NamedTempFile::new().expect("failed to create temporary file");
+        pwl.save(file.path()).unwrap();
+        assert!(!pwl.is_changed());
+
+        let loaded = PersonalWordList::load(file.path()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.contains("xdg"));
+        assert!(!loaded.is_changed());
+    }
+}
@@ -0,0 +1,246 @@
+use std::ops::Range;
+use std::path::Path;
+
+/// Just enough knowledge of a programming language's syntax to find its comments and strings,
+/// which are the only parts of a source file that are spell checked.
+#[derive(Debug)]
+pub struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    string_delimiters: &'static [char],
+    /// Whether single-quoted characters such as '"' are char literals, which must be skipped
+    /// so that the quote inside them is not taken to start a string.
+    char_literals: bool
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    string_delimiters: &['"'],
+    char_literals: true
+};
+
+const SCRIPT: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    string_delimiters: &['"', '\''],
+    char_literals: false
+};
+
+const LISP: Syntax = Syntax {
+    line_comments: &[";"],
+    block_comments: &[],
+    string_delimiters: &['"'],
+    char_literals: false
+};
+
+const SQL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("/*", "*/")],
+    string_delimiters: &['\''],
+    char_literals: false
+};
+
+/// Determines the syntax of a file from its extension. Returns None for files that are not
+/// source code (or which we know nothing about), in which case all of the text is checked.
+pub fn syntax_for_path(path: &Path) -> Option<&'static Syntax> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+    match ext.as_str() {
+        "rs" | "c" | "h" | "cpp" | "hpp" | "cc" | "cs" | "java" | "js" | "ts" | "go" | "swift" | "kt" => Some(&C_LIKE),
+        "py" | "sh" | "bash" | "rb" | "pl" | "toml" | "yaml" | "yml" | "mak" | "cmake" => Some(&SCRIPT),
+        "el" | "lisp" | "clj" | "scm" => Some(&LISP),
+        "sql" => Some(&SQL),
+        _ => None
+    }
+}
+
+/// Returns the byte ranges of `text` which should be spell checked. For plain text that is
+/// the whole text, for source code it is the contents of comments and strings.
+pub fn checkable_regions(text: &str, syntax: Option<&Syntax>) -> Vec<Range<usize>> {
+    let syntax = match syntax {
+        None => return vec![0..text.len()],
+        Some(s) => s
+    };
+
+    let mut regions = Vec::new();
+    let mut i = 0;
+
+    'outer: while i < text.len() {
+        let rest = &text[i..];
+
+        for lc in syntax.line_comments {
+            if rest.starts_with(lc) {
+                let start = i + lc.len();
+                let end = text[start..].find('\n').map_or(text.len(), |n| start + n);
+                regions.push(start..end);
+                i = end;
+                continue 'outer;
+            }
+        }
+
+        for &(open, close) in syntax.block_comments {
+            if rest.starts_with(open) {
+                let start = i + open.len();
+                let end = text[start..].find(close).map_or(text.len(), |n| start + n);
+                regions.push(start..end);
+                i = (end + close.len()).min(text.len());
+                continue 'outer;
+            }
+        }
+
+        if syntax.char_literals {
+            if let Some(len) = char_literal_len(rest) {
+                i += len;
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        if syntax.string_delimiters.contains(&c) {
+            let start = i + c.len_utf8();
+            let end = find_string_end(text, start, c);
+            regions.push(start..end);
+            i = (end + c.len_utf8()).min(text.len());
+            continue;
+        }
+
+        i += c.len_utf8();
+    }
+
+    regions
+}
+
+/// The longest escape in a char literal after its first character, as in '\u{10FFFF}'.
+const MAX_ESCAPE_LEN: usize = 8;
+
+/// The length of the char literal at the start of `text`, such as 'a', '"' or '\u{7FFF}', if
+/// there is one. A quote which starts anything else, such as a Rust lifetime, is not a literal.
+fn char_literal_len(text: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    if chars.next() != Some('\'') {
+        return None;
+    }
+
+    match chars.next() {
+        // The character after the backslash is skipped, since it may itself be a quote.
+        Some('\\') => text.get(3..)
+            .and_then(|rest| rest.find('\''))
+            .filter(|&n| n <= MAX_ESCAPE_LEN)
+            .map(|n| 3 + n + 1),
+        Some(c) if chars.next() == Some('\'') => Some(1 + c.len_utf8() + 1),
+        _ => None
+    }
+}
+
+/// Finds the closing `delimiter` of a string that starts at `start`, skipping backslash escapes.
+fn find_string_end(text: &str, start: usize, delimiter: char) -> usize {
+    let mut escaped = false;
+    for (idx, c) in text[start..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            return start + idx;
+        }
+    }
+
+    text.len()
+}
+
+/// Finds the words in `region` of `text` which are worth spell checking, returning their byte
+/// ranges. Tokens that look like identifiers - containing digits or underscores, or in
+/// camelCase - are skipped because they are almost always names from the code.
+pub fn words_in_region(text: &str, region: Range<usize>) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let slice = &text[region.clone()];
+    let is_token_char = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+
+    let mut token_start = None;
+    for (idx, c) in slice.char_indices().chain(Some((slice.len(), ' '))) {
+        match (token_start, is_token_char(c)) {
+            (None, true) => token_start = Some(idx),
+            (Some(start), false) => {
+                if let Some(word) = trim_token(&slice[start..idx]) {
+                    let offset = region.start + start + slice[start..idx].find(word).unwrap();
+                    words.push(offset..offset + word.len());
+                }
+                token_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+fn trim_token(token: &str) -> Option<&str> {
+    let word = token.trim_matches('\'');
+    if word.is_empty() || word.chars().any(|c| c.is_numeric() || c == '_') || is_camel_case(word) {
+        None
+    } else {
+        Some(word)
+    }
+}
+
+fn is_camel_case(word: &str) -> bool {
+    let has_lower = word.chars().any(|c| c.is_lowercase());
+    has_lower && word.chars().skip(1).any(|c| c.is_uppercase())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn words<'a>(text: &'a str, syntax: Option<&Syntax>) -> Vec<&'a str> {
+        checkable_regions(text, syntax).into_iter()
+            .flat_map(|r| words_in_region(text, r))
+            .map(|r| &text[r])
+            .collect()
+    }
+
+    #[test]
+    fn syntax_for_path_for_text_file_returns_none() {
+        assert!(syntax_for_path(&PathBuf::from("notes.txt")).is_none());
+        assert!(syntax_for_path(&PathBuf::from("README")).is_none());
+    }
+
+    #[test]
+    fn syntax_for_path_for_rust_file_returns_syntax() {
+        assert!(syntax_for_path(&PathBuf::from("src/main.rs")).is_some());
+    }
+
+    #[test]
+    fn checkable_regions_for_plain_text_returns_everything() {
+        assert_eq!(checkable_regions("some text", None), vec![0..9]);
+    }
+
+    #[test]
+    fn words_for_code_only_includes_comments_and_strings() {
+        let text = "let mispelt = \"a strng\"; // a coment\n/* blok */ fn foo() {}";
+        assert_eq!(words(text, Some(&C_LIKE)), vec!["a", "strng", "a", "coment", "blok"]);
+    }
+
+    #[test]
+    fn words_for_string_with_escaped_quote_does_not_end_early() {
+        let text = r#"x = "say \"hello\" there""#;
+        assert_eq!(words(text, Some(&SCRIPT)), vec!["say", "hello", "there"]);
+    }
+
+    #[test]
+    fn words_for_code_skips_char_literals_containing_quotes() {
+        let text = "if c == '\"' || c == '\\'' { f::<'a>(\"a strng\") } // a coment";
+        assert_eq!(words(text, Some(&C_LIKE)), vec!["a", "strng", "a", "coment"]);
+        assert_eq!(char_literal_len("'\\u{10FFFF}' x"), Some(12));
+        assert_eq!(char_literal_len("'a: loop"), None);
+    }
+
+    #[test]
+    fn words_skips_identifiers_and_keeps_apostrophes() {
+        let text = "don't use buffer_id or bufferId or x86 'quoted'";
+        assert_eq!(words(text, None), vec!["don't", "use", "or", "or", "quoted"]);
+    }
+}
@@ -0,0 +1,167 @@
+/// Above this many comparisons the LCS table would be too big, and the differing middle of
+/// the two texts is simply reported as removed then added.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// One line of a line-based diff.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str)
+}
+
+/// Computes the differences between `old` and `new` line by line, using the longest common
+/// subsequence. Lines common to the start and end are trimmed first, which makes the usual
+/// case of a few scattered changes cheap.
+pub fn diff_lines<'a, S: AsRef<str>>(old: &'a [S], new: &'a [S]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a.as_ref() == b.as_ref()).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a.as_ref() == b.as_ref())
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut result: Vec<_> = old[..prefix].iter().map(|l| DiffLine::Same(l.as_ref())).collect();
+    result.extend(lcs_diff(old_mid, new_mid));
+    result.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same(l.as_ref())));
+    result
+}
+
+fn lcs_diff<'a, S: AsRef<str>>(old: &'a [S], new: &'a [S]) -> Vec<DiffLine<'a>> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1) * (m + 1) > MAX_LCS_CELLS {
+        let mut result: Vec<_> = old.iter().map(|l| DiffLine::Removed(l.as_ref())).collect();
+        result.extend(new.iter().map(|l| DiffLine::Added(l.as_ref())));
+        return result;
+    }
+
+    // lengths[i][j] is the length of the LCS of old[i..] and new[j..].
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old[i].as_ref() == new[j].as_ref() {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i].as_ref() == new[j].as_ref() {
+            result.push(DiffLine::Same(old[i].as_ref()));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            result.push(DiffLine::Removed(old[i].as_ref()));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(new[j].as_ref()));
+            j += 1;
+        }
+    }
+
+    result.extend(old[i..].iter().map(|l| DiffLine::Removed(l.as_ref())));
+    result.extend(new[j..].iter().map(|l| DiffLine::Added(l.as_ref())));
+    result
+}
+
+/// Formats a diff in the style of `diff -u`, showing `context` unchanged lines around each
+/// group of changes. Returns no lines if there are no differences.
+pub fn format_unified_diff(diff: &[DiffLine], old_name: &str, new_name: &str, context: usize) -> Vec<String> {
+    let changed: Vec<usize> = diff.iter().enumerate()
+        .filter(|&(_, d)| match *d { DiffLine::Same(_) => false, _ => true })
+        .map(|(idx, _)| idx)
+        .collect();
+
+    if changed.is_empty() {
+        return Vec::new();
+    }
+
+    // Group the changes into hunks, merging those whose context would overlap.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for idx in changed {
+        let start = idx.saturating_sub(context);
+        let end = (idx + context + 1).min(diff.len());
+        match hunks.last_mut() {
+            Some(ref mut last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end))
+        }
+    }
+
+    let mut result = vec![format!("--- {}", old_name), format!("+++ {}", new_name)];
+    for (start, end) in hunks {
+        let old_start = diff[..start].iter().filter(|d| !is_added(d)).count();
+        let new_start = diff[..start].iter().filter(|d| !is_removed(d)).count();
+        let old_len = diff[start..end].iter().filter(|d| !is_added(d)).count();
+        let new_len = diff[start..end].iter().filter(|d| !is_removed(d)).count();
+        result.push(format!("@@ -{},{} +{},{} @@", old_start + 1, old_len, new_start + 1, new_len));
+
+        for d in &diff[start..end] {
+            result.push(match *d {
+                DiffLine::Same(l) => format!(" {}", l),
+                DiffLine::Removed(l) => format!("-{}", l),
+                DiffLine::Added(l) => format!("+{}", l)
+            });
+        }
+    }
+
+    result
+}
+
+fn is_added(d: &DiffLine) -> bool {
+    match *d { DiffLine::Added(_) => true, _ => false }
+}
+
+fn is_removed(d: &DiffLine) -> bool {
+    match *d { DiffLine::Removed(_) => true, _ => false }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lines_for_identical_text_returns_all_same() {
+        let a = ["a", "b"];
+        assert_eq!(diff_lines(&a, &a), vec![DiffLine::Same("a"), DiffLine::Same("b")]);
+    }
+
+    #[test]
+    fn diff_lines_finds_changed_line() {
+        let old = ["a", "b", "c"];
+        let new = ["a", "x", "c"];
+        assert_eq!(diff_lines(&old, &new), vec![
+            DiffLine::Same("a"), DiffLine::Removed("b"), DiffLine::Added("x"), DiffLine::Same("c")
+        ]);
+    }
+
+    #[test]
+    fn diff_lines_finds_insertions_and_deletions() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["b", "c", "e", "d"];
+        assert_eq!(diff_lines(&old, &new), vec![
+            DiffLine::Removed("a"), DiffLine::Same("b"), DiffLine::Same("c"), DiffLine::Added("e"), DiffLine::Same("d")
+        ]);
+    }
+
+    #[test]
+    fn format_unified_diff_for_no_changes_returns_nothing() {
+        let a = ["a"];
+        assert!(format_unified_diff(&diff_lines(&a, &a), "a", "b", 3).is_empty());
+    }
+
+    #[test]
+    fn format_unified_diff_shows_context_around_changes() {
+        let old = ["1", "2", "3", "4", "5", "6"];
+        let new = ["1", "2", "3", "4", "X", "6"];
+        let diff = diff_lines(&old, &new);
+        assert_eq!(format_unified_diff(&diff, "old", "new", 1), vec![
+            "--- old", "+++ new", "@@ -4,3 +4,3 @@", " 4", "-5", "+X", " 6"
+        ]);
+    }
+}
@@ -0,0 +1,283 @@
+use std::ops::Range;
+
+/// Comment leaders that are recognised at the start of a line when filling. Longer leaders
+/// must come before their prefixes so that "///" is not mistaken for "//". A leader only
+/// counts if it is followed by whitespace or the end of the line, which stops things
+/// like "#[derive]" or "--verbose" being treated as comments.
+const COMMENT_LEADERS: [&'static str; 7] = ["///", "//!", "//", "#", "--", ";;", ">"];
+
+/// The leading part of a line which is preserved when the line is filled.
+/// For the line "    // - some text" the leader is "    // " and the bullet is "- ".
+#[derive(Debug, PartialEq)]
+struct LinePrefix<'a> {
+    /// Indentation, optional comment leader and the whitespace following it.
+    leader: &'a str,
+    /// A list bullet such as "- ", "* " or "12. ", including trailing whitespace. May be empty.
+    bullet: &'a str,
+    /// Whatever is left over.
+    body: &'a str
+}
+
+impl<'a> LinePrefix<'a> {
+    fn parse(line: &'a str) -> LinePrefix<'a> {
+        let indent_len = line.len() - line.trim_start().len();
+        let mut leader_len = indent_len;
+
+        for cl in &COMMENT_LEADERS {
+            let rest = &line[indent_len..];
+            if rest.starts_with(cl) && is_blank_or_starts_with_whitespace(&rest[cl.len()..]) {
+                let after_leader = &rest[cl.len()..];
+                leader_len = indent_len + cl.len() + (after_leader.len() - after_leader.trim_start().len());
+                break;
+            }
+        }
+
+        let leader = &line[..leader_len];
+        let rest = &line[leader_len..];
+        let bullet_len = bullet_length(rest);
+
+        LinePrefix {
+            leader,
+            bullet: &rest[..bullet_len],
+            body: &rest[bullet_len..]
+        }
+    }
+
+    fn is_blank(&self) -> bool {
+        self.bullet.is_empty() && self.body.trim().is_empty()
+    }
+
+    /// The prefix to use on the first line of the filled paragraph.
+    fn first_prefix(&self) -> String {
+        String::from(self.leader) + self.bullet
+    }
+
+    /// The prefix to use on the second and subsequent lines of the filled paragraph.
+    /// For bulleted lines this is the leader plus enough spaces to line up with the text
+    /// after the bullet.
+    fn continuation_prefix(&self) -> String {
+        String::from(self.leader) + &" ".repeat(self.bullet.chars().count())
+    }
+}
+
+fn is_blank_or_starts_with_whitespace(s: &str) -> bool {
+    s.chars().next().map_or(true, |c| c.is_whitespace())
+}
+
+/// Returns the length in bytes of the list bullet at the start of `s`, or 0 if there is none.
+/// Recognised bullets are "-", "*", "+" and numbers followed by "." or ")". They must be
+/// followed by at least one space.
+fn bullet_length(s: &str) -> usize {
+    let marker_len = if s.starts_with('-') || s.starts_with('*') || s.starts_with('+') {
+        1
+    } else {
+        let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 && (s[digits..].starts_with('.') || s[digits..].starts_with(')')) {
+            digits + 1
+        } else {
+            return 0;
+        }
+    };
+
+    let after = &s[marker_len..];
+    let spaces = after.len() - after.trim_start().len();
+    if spaces == 0 || after.trim().is_empty() {
+        0
+    } else {
+        marker_len + spaces
+    }
+}
+
+/// Returns true if `line` is a continuation of the paragraph whose previous line is `prev`.
+fn joins(prev: &LinePrefix, line: &LinePrefix) -> bool {
+    !prev.is_blank() && !line.is_blank() && line.bullet.is_empty() && prev.continuation_prefix() == line.leader
+}
+
+/// Finds the paragraph which contains the line `line`, returning the (half-open) range of line
+/// numbers that it spans. A paragraph is a run of non-blank lines which share the same comment
+/// leader and indentation; a line which starts with a list bullet always begins a new paragraph.
+/// Returns None if `line` is blank or out of range.
+pub fn find_paragraph<S: AsRef<str>>(lines: &[S], line: usize) -> Option<Range<usize>> {
+    if line >= lines.len() {
+        return None;
+    }
+
+    let prefixes: Vec<_> = lines.iter().map(|l| LinePrefix::parse(l.as_ref())).collect();
+    if prefixes[line].is_blank() {
+        return None;
+    }
+
+    let mut start = line;
+    while start > 0 && joins(&prefixes[start - 1], &prefixes[start]) {
+        start -= 1;
+    }
+
+    let mut end = line + 1;
+    while end < prefixes.len() && joins(&prefixes[end - 1], &prefixes[end]) {
+        end += 1;
+    }
+
+    Some(start..end)
+}
+
+/// Re-wraps the paragraph formed by `lines` so that no line is longer than `column` characters,
+/// unless it consists of a single word that is itself too long. The comment leader, indentation
+/// and any list bullet of the first line are kept, and subsequent lines are indented to line up
+/// with the text after the bullet.
+pub fn fill_paragraph<S: AsRef<str>>(lines: &[S], column: usize) -> Vec<String> {
+    let mut result = Vec::new();
+    if lines.is_empty() {
+        return result;
+    }
+
+    let first = LinePrefix::parse(lines[0].as_ref());
+    let continuation = first.continuation_prefix();
+
+    let mut current = first.first_prefix();
+    let mut current_width = current.chars().count();
+    let mut current_has_words = false;
+
+    for word in words(lines) {
+        let word_width = word.chars().count();
+
+        if current_has_words && current_width + 1 + word_width > column {
+            result.push(current);
+            current = continuation.clone();
+            current_width = current.chars().count();
+            current_has_words = false;
+        }
+
+        if current_has_words {
+            current.push(' ');
+            current_width += 1;
+        }
+
+        current.push_str(word);
+        current_width += word_width;
+        current_has_words = true;
+    }
+
+    result.push(current);
+    result
+}
+
+/// Joins the paragraph formed by `lines` into a single line, keeping the comment leader,
+/// indentation and any list bullet of the first line.
+pub fn unfill_paragraph<S: AsRef<str>>(lines: &[S]) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    let first = LinePrefix::parse(lines[0].as_ref());
+    let body: Vec<_> = words(lines).collect();
+    first.first_prefix() + &body.join(" ")
+}
+
+/// Iterates over all the words in the bodies of `lines`, i.e. ignoring their prefixes.
+fn words<'a, S: AsRef<str>>(lines: &'a [S]) -> impl Iterator<Item = &'a str> + 'a {
+    lines.iter().flat_map(|l| LinePrefix::parse(l.as_ref()).body.split_whitespace())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_for_plain_text_has_empty_leader_and_bullet() {
+        let p = LinePrefix::parse("hello world");
+        assert_eq!(p, LinePrefix { leader: "", bullet: "", body: "hello world" });
+    }
+
+    #[test]
+    fn parse_for_indented_comment_with_bullet_splits_all_parts() {
+        let p = LinePrefix::parse("    // - hello");
+        assert_eq!(p, LinePrefix { leader: "    // ", bullet: "- ", body: "hello" });
+    }
+
+    #[test]
+    fn parse_for_attribute_does_not_treat_hash_as_comment() {
+        let p = LinePrefix::parse("#[derive(Debug)]");
+        assert_eq!(p.leader, "");
+    }
+
+    #[test]
+    fn parse_for_numbered_list_item_recognises_bullet() {
+        let p = LinePrefix::parse("12. twelve");
+        assert_eq!(p.bullet, "12. ");
+        assert_eq!(p.body, "twelve");
+    }
+
+    #[test]
+    fn find_paragraph_for_blank_line_returns_none() {
+        let lines = ["a", "", "b"];
+        assert_eq!(find_paragraph(&lines, 1), None);
+    }
+
+    #[test]
+    fn find_paragraph_for_line_out_of_range_returns_none() {
+        let lines = ["a"];
+        assert_eq!(find_paragraph(&lines, 1), None);
+    }
+
+    #[test]
+    fn find_paragraph_stops_at_blank_lines() {
+        let lines = ["a", "", "b", "c", "", "d"];
+        assert_eq!(find_paragraph(&lines, 3), Some(2..4));
+    }
+
+    #[test]
+    fn find_paragraph_stops_when_comment_leader_changes() {
+        let lines = ["let x = 1;", "// a", "// b", "let y = 2;"];
+        assert_eq!(find_paragraph(&lines, 1), Some(1..3));
+    }
+
+    #[test]
+    fn find_paragraph_treats_comment_line_with_no_text_as_blank() {
+        let lines = ["// a", "//", "// b"];
+        assert_eq!(find_paragraph(&lines, 0), Some(0..1));
+    }
+
+    #[test]
+    fn find_paragraph_starts_new_paragraph_at_each_bullet() {
+        let lines = ["- one", "  more", "- two"];
+        assert_eq!(find_paragraph(&lines, 1), Some(0..2));
+        assert_eq!(find_paragraph(&lines, 2), Some(2..3));
+    }
+
+    #[test]
+    fn fill_paragraph_wraps_at_column() {
+        let lines = ["aaa bbb ccc ddd"];
+        assert_eq!(fill_paragraph(&lines, 7), vec!["aaa bbb", "ccc ddd"]);
+    }
+
+    #[test]
+    fn fill_paragraph_joins_short_lines() {
+        let lines = ["aaa", "bbb", "ccc"];
+        assert_eq!(fill_paragraph(&lines, 80), vec!["aaa bbb ccc"]);
+    }
+
+    #[test]
+    fn fill_paragraph_keeps_comment_leader_and_indentation() {
+        let lines = ["    // aaa bbb ccc"];
+        assert_eq!(fill_paragraph(&lines, 14), vec!["    // aaa bbb", "    // ccc"]);
+    }
+
+    #[test]
+    fn fill_paragraph_aligns_continuation_lines_with_bullet_text() {
+        let lines = ["* aaa bbb ccc"];
+        assert_eq!(fill_paragraph(&lines, 9), vec!["* aaa bbb", "  ccc"]);
+    }
+
+    #[test]
+    fn fill_paragraph_puts_overlong_word_on_its_own_line() {
+        let lines = ["a bbbbbbbbbb c"];
+        assert_eq!(fill_paragraph(&lines, 5), vec!["a", "bbbbbbbbbb", "c"]);
+    }
+
+    #[test]
+    fn unfill_paragraph_joins_lines_and_keeps_first_prefix() {
+        let lines = ["/// - aaa", "///   bbb", "///   ccc"];
+        assert_eq!(unfill_paragraph(&lines), "/// - aaa bbb ccc");
+    }
+}
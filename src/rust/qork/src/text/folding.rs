@@ -0,0 +1,147 @@
+/// The ways in which fold ranges can be computed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FoldMethod {
+    /// A fold starts at a line and covers all the following lines which are indented further.
+    Indent,
+    /// A fold starts at a line containing "{{{" and ends at the line containing the matching "}}}".
+    Marker
+}
+
+pub const FOLD_OPEN_MARKER: &'static str = "{{{";
+pub const FOLD_CLOSE_MARKER: &'static str = "}}}";
+
+/// A range of lines that can be folded. The `start_line` (the header) remains visible when the
+/// fold is collapsed, the lines after it up to and including `end_line` are hidden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoldRange {
+    pub start_line: usize,
+    pub end_line: usize
+}
+
+impl FoldRange {
+    pub fn contains(&self, line: usize) -> bool {
+        self.start_line <= line && line <= self.end_line
+    }
+}
+
+pub fn compute_folds<S: AsRef<str>>(lines: &[S], method: FoldMethod) -> Vec<FoldRange> {
+    match method {
+        FoldMethod::Indent => indent_folds(lines),
+        FoldMethod::Marker => marker_folds(lines)
+    }
+}
+
+fn indent_of(line: &str) -> Option<usize> {
+    if line.trim().is_empty() {
+        None
+    } else {
+        Some(line.len() - line.trim_start().len())
+    }
+}
+
+/// Computes folds from indentation. Blank lines inside a fold belong to it, but blank lines
+/// at its end do not. The result is ordered by start line.
+pub fn indent_folds<S: AsRef<str>>(lines: &[S]) -> Vec<FoldRange> {
+    let indents: Vec<_> = lines.iter().map(|l| indent_of(l.as_ref())).collect();
+    let mut folds = Vec::new();
+
+    for (i, indent) in indents.iter().enumerate() {
+        let indent = match *indent {
+            Some(n) => n,
+            None => continue
+        };
+
+        let mut end_line = None;
+        for (j, other) in indents.iter().enumerate().skip(i + 1) {
+            match *other {
+                Some(n) if n > indent => end_line = Some(j),
+                Some(_) => break,
+                None => {}
+            }
+        }
+
+        if let Some(end_line) = end_line {
+            folds.push(FoldRange { start_line: i, end_line });
+        }
+    }
+
+    folds
+}
+
+/// Computes folds from "{{{" and "}}}" markers, which may be nested. Unbalanced markers
+/// are ignored. The result is ordered by start line.
+pub fn marker_folds<S: AsRef<str>>(lines: &[S]) -> Vec<FoldRange> {
+    let mut folds = Vec::new();
+    let mut open = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.as_ref();
+        if line.contains(FOLD_OPEN_MARKER) {
+            open.push(i);
+        } else if line.contains(FOLD_CLOSE_MARKER) {
+            if let Some(start_line) = open.pop() {
+                folds.push(FoldRange { start_line, end_line: i });
+            }
+        }
+    }
+
+    folds.sort_by_key(|f| f.start_line);
+    folds
+}
+
+/// Finds the fold which `line` belongs to: the fold whose header it is, or else the innermost
+/// fold that contains it.
+pub fn fold_at_line(folds: &[FoldRange], line: usize) -> Option<FoldRange> {
+    folds.iter().find(|f| f.start_line == line).cloned()
+        .or_else(|| folds.iter().filter(|f| f.contains(line)).max_by_key(|f| f.start_line).cloned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_folds_for_flat_text_returns_no_folds() {
+        assert!(indent_folds(&["a", "b", "", "c"]).is_empty());
+    }
+
+    #[test]
+    fn indent_folds_finds_nested_folds() {
+        let lines = ["fn a() {", "    if x {", "        y();", "    }", "}"];
+        assert_eq!(indent_folds(&lines), vec![
+            FoldRange { start_line: 0, end_line: 3 },
+            FoldRange { start_line: 1, end_line: 2 }
+        ]);
+    }
+
+    #[test]
+    fn indent_folds_includes_inner_blank_lines_but_not_trailing_ones() {
+        let lines = ["a", "  b", "", "  c", "", "d"];
+        assert_eq!(indent_folds(&lines), vec![FoldRange { start_line: 0, end_line: 3 }]);
+    }
+
+    #[test]
+    fn marker_folds_finds_nested_folds() {
+        let lines = ["// {{{ outer", "// {{{ inner", "x", "// }}}", "// }}}"];
+        assert_eq!(marker_folds(&lines), vec![
+            FoldRange { start_line: 0, end_line: 4 },
+            FoldRange { start_line: 1, end_line: 3 }
+        ]);
+    }
+
+    #[test]
+    fn marker_folds_ignores_unbalanced_markers() {
+        let lines = ["}}}", "{{{", "x"];
+        assert!(marker_folds(&lines).is_empty());
+    }
+
+    #[test]
+    fn fold_at_line_prefers_fold_starting_at_line_then_innermost() {
+        let folds = [FoldRange { start_line: 0, end_line: 10 }, FoldRange { start_line: 2, end_line: 5 }];
+        assert_eq!(fold_at_line(&folds, 2), Some(folds[1]));
+        assert_eq!(fold_at_line(&folds, 3), Some(folds[1]));
+        assert_eq!(fold_at_line(&folds, 7), Some(folds[0]));
+        assert_eq!(fold_at_line(&folds, 11), None);
+    }
+}
@@ -0,0 +1,81 @@
+/// Scores how well `candidate` matches a fuzzily typed `pattern`, for ranking completions.
+/// The characters of the pattern must appear in the candidate in order, ignoring case, but
+/// not necessarily together; if they do not, None is returned. Higher scores are better:
+/// matches at the start of the candidate or of a word within it, runs of consecutive
+/// characters and exact case score extra, while gaps and unmatched characters cost a little.
+/// An empty pattern matches everything, shorter candidates first.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i32> {
+    const MATCH: i32 = 16;
+    const START_BONUS: i32 = 24;
+    const WORD_START_BONUS: i32 = 12;
+    const CONSECUTIVE_BONUS: i32 = 16;
+    const CASE_BONUS: i32 = 2;
+    const GAP_PENALTY: i32 = 3;
+
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous_match: Option<usize> = None;
+
+    for p in pattern.chars() {
+        let lower_p = lower(p);
+        let index = (next..candidate.len()).find(|&i| lower(candidate[i]) == lower_p)?;
+        let c = candidate[index];
+
+        score += MATCH;
+        if index == 0 {
+            score += START_BONUS;
+        } else if is_word_start(candidate[index - 1], c) {
+            score += WORD_START_BONUS;
+        }
+        if c == p {
+            score += CASE_BONUS;
+        }
+        match previous_match {
+            Some(prev) if prev + 1 == index => score += CONSECUTIVE_BONUS,
+            Some(prev) => score -= GAP_PENALTY * (index - prev - 1).min(8) as i32,
+            None => score -= GAP_PENALTY * index.min(8) as i32
+        }
+
+        previous_match = Some(index);
+        next = index + 1;
+    }
+
+    // Prefer shorter candidates among otherwise equal matches.
+    score -= (candidate.len() - next).min(32) as i32;
+    Some(score)
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Whether `c` starts a word, given the character before it: after a separator such as '/',
+/// '-' or '_', or at the start of a capitalised word in camelCase.
+fn is_word_start(before: char, c: char) -> bool {
+    (!before.is_alphanumeric() && c.is_alphanumeric()) || (before.is_lowercase() && c.is_uppercase())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_requires_characters_in_order() {
+        assert!(fuzzy_score("sva", "save-as").is_some());
+        assert!(fuzzy_score("SAVE", "save-as").is_some());
+        assert!(fuzzy_score("ev", "save-as").is_none());
+        assert!(fuzzy_score("", "short") > fuzzy_score("", "much longer"));
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_prefixes_word_starts_and_runs() {
+        let score = |p, c| fuzzy_score(p, c).unwrap();
+        assert!(score("save", "save") > score("save", "save-all"));
+        assert!(score("sa", "save-all") > score("sa", "close-all"));
+        assert!(score("fa", "fold-all") > score("fa", "buffer-tab"));
+        assert!(score("bc", "buffer_collection.rs") > score("bc", "abacus.rs"));
+        assert!(score("mod", "src/mod.rs") > score("mod", "src/my_old_data.rs"));
+    }
+}
@@ -0,0 +1,9 @@
+mod diff;
+mod fill;
+mod folding;
+mod fuzzy;
+
+pub use text::diff::{diff_lines, format_unified_diff};
+pub use text::fill::{fill_paragraph, find_paragraph, unfill_paragraph};
+pub use text::folding::{FoldMethod, FoldRange, compute_folds, fold_at_line};
+pub use text::fuzzy::fuzzy_score;
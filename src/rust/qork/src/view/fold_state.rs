@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use toml;
+
+use buffer::{Buffer, LineEdit};
+use fs;
+use text::FoldRange;
+
+/// The collapsed folds of a single view. Folds are identified by the line number of their
+/// header, which is adjusted as the buffer is edited so that collapsed folds stay collapsed
+/// even when lines are inserted or deleted above them. The fold ranges themselves are not
+/// stored; they are recomputed from the text whenever they are needed.
+#[derive(Debug, Default)]
+pub struct FoldState {
+    collapsed: BTreeSet<usize>,
+    /// The buffer revision that `collapsed` is up to date with.
+    revision: usize
+}
+
+impl FoldState {
+    pub fn new(buffer: &Buffer) -> FoldState {
+        FoldState {
+            collapsed: BTreeSet::new(),
+            revision: buffer.revision()
+        }
+    }
+
+    /// Brings the fold state up to date with any edits made to `buffer`. If the buffer no
+    /// longer remembers all of them, the collapsed folds can no longer be found, so they are
+    /// expanded.
+    pub fn sync(&mut self, buffer: &Buffer) {
+        match buffer.edits_since(self.revision) {
+            Some(edits) => for edit in edits {
+                self.apply_edit(edit);
+            },
+            None => self.collapsed.clear()
+        }
+        self.revision = buffer.revision();
+    }
+
+    fn apply_edit(&mut self, edit: &LineEdit) {
+        let last_removed_line = edit.line + edit.lines_removed;
+        let inserted_before_line = edit.at_line_start && edit.lines_removed == 0 && edit.lines_added > 0;
+        self.collapsed = self.collapsed.iter().filter_map(|&header| {
+            if header == edit.line && inserted_before_line {
+                // Whole lines were inserted above the header.
+                Some(header + edit.lines_added)
+            } else if header <= edit.line {
+                Some(header)
+            } else if header > last_removed_line {
+                Some(header + edit.lines_added - edit.lines_removed)
+            } else {
+                // The header line was deleted, so the fold is gone.
+                None
+            }
+        }).collect();
+    }
+
+    /// Estimates the memory used by the fold state.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<FoldState>() + self.collapsed.len() * mem::size_of::<usize>()
+    }
+
+    pub fn is_collapsed(&self, fold: &FoldRange) -> bool {
+        self.collapsed.contains(&fold.start_line)
+    }
+
+    pub fn collapse(&mut self, fold: &FoldRange) {
+        self.collapsed.insert(fold.start_line);
+    }
+
+    pub fn expand(&mut self, fold: &FoldRange) {
+        self.collapsed.remove(&fold.start_line);
+    }
+
+    /// Toggles the fold, returning true if it is now collapsed.
+    pub fn toggle(&mut self, fold: &FoldRange) -> bool {
+        if self.is_collapsed(fold) {
+            self.expand(fold);
+            false
+        } else {
+            self.collapse(fold);
+            true
+        }
+    }
+
+    pub fn collapse_all(&mut self, folds: &[FoldRange]) {
+        self.collapsed.extend(folds.iter().map(|f| f.start_line));
+    }
+
+    pub fn expand_all(&mut self) {
+        self.collapsed.clear();
+    }
+
+    pub fn collapsed_lines(&self) -> Vec<usize> {
+        self.collapsed.iter().cloned().collect()
+    }
+
+    pub fn set_collapsed_lines(&mut self, lines: &[usize]) {
+        self.collapsed = lines.iter().cloned().collect();
+    }
+
+    /// Returns the ranges of lines which are hidden by collapsed folds, given the current
+    /// fold ranges of the buffer. Nested folds inside a collapsed fold are subsumed by it.
+    pub fn hidden_lines(&self, folds: &[FoldRange]) -> Vec<Range<usize>> {
+        let mut result: Vec<Range<usize>> = Vec::new();
+
+        for fold in folds.iter().filter(|f| self.is_collapsed(f)) {
+            let hidden = fold.start_line + 1..fold.end_line + 1;
+            match result.last_mut() {
+                Some(ref mut last) if hidden.start <= last.end => last.end = last.end.max(hidden.end),
+                _ => result.push(hidden)
+            }
+        }
+
+        result
+    }
+}
+
+/// The collapsed fold lines of files, keyed by filename, which is kept in the data directory
+/// so that folds can be restored the next time a file is opened.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FoldStore {
+    #[serde(skip)]
+    is_changed: bool,
+    files: BTreeMap<String, Vec<usize>>
+}
+
+impl FoldStore {
+    pub fn new() -> FoldStore {
+        FoldStore::default()
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.is_changed
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, filename: P) -> Option<&Vec<usize>> {
+        self.files.get(&*filename.as_ref().to_string_lossy())
+    }
+
+    /// Records the collapsed lines for a file. Files with no collapsed folds are forgotten.
+    pub fn set<P: AsRef<Path>>(&mut self, filename: P, collapsed_lines: Vec<usize>) {
+        let key = filename.as_ref().to_string_lossy().into_owned();
+        if self.files.get(&key) == Some(&collapsed_lines) {
+            return;
+        }
+
+        if collapsed_lines.is_empty() {
+            self.is_changed |= self.files.remove(&key).is_some();
+        } else {
+            self.files.insert(key, collapsed_lines);
+            self.is_changed = true;
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(filename: P) -> Result<FoldStore, String> {
+        let mut contents = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|err| err.to_string())?;
+        toml::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize, String> {
+        if !self.is_changed {
+            return Ok(0);
+        }
+
+        let contents = toml::to_string(self).map_err(|err| err.to_string())?;
+        fs::write_atomically(filename.as_ref(), contents.as_bytes()).map_err(|err| err.to_string())?;
+        self.is_changed = false;
+        Ok(contents.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::BufferFactory;
+    use tempfile::NamedTempFile;
+
+    fn fold(start_line: usize, end_line: usize) -> FoldRange {
+        FoldRange { start_line, end_line }
+    }
+
+    #[test]
+    fn apply_edit_shifts_folds_below_the_edit() {
+        let mut fs = FoldState::default();
+        fs.collapse(&fold(2, 4));
+        fs.collapse(&fold(10, 12));
+        fs.apply_edit(&LineEdit { line: 5, lines_removed: 0, lines_added: 3, at_line_start: false });
+        assert_eq!(fs.collapsed_lines(), vec![2, 13]);
+    }
+
+    #[test]
+    fn apply_edit_shifts_folds_when_lines_are_inserted_before_the_header() {
+        let mut fs = FoldState::default();
+        fs.collapse(&fold(2, 4));
+        fs.collapse(&fold(10, 12));
+        fs.apply_edit(&LineEdit { line: 2, lines_removed: 0, lines_added: 1, at_line_start: true });
+        assert_eq!(fs.collapsed_lines(), vec![3, 11]);
+
+        // Inserting a line break part way along the header leaves the fold where it is.
+        fs.apply_edit(&LineEdit { line: 3, lines_removed: 0, lines_added: 1, at_line_start: false });
+        assert_eq!(fs.collapsed_lines(), vec![3, 12]);
+    }
+
+    #[test]
+    fn apply_edit_removes_folds_whose_header_was_deleted() {
+        let mut fs = FoldState::default();
+        fs.collapse(&fold(2, 4));
+        fs.collapse(&fold(10, 12));
+        fs.apply_edit(&LineEdit { line: 1, lines_removed: 2, lines_added: 0, at_line_start: true });
+        assert_eq!(fs.collapsed_lines(), vec![8]);
+    }
+
+    #[test]
+    fn sync_applies_edits_made_to_buffer() {
+        let mut fac = BufferFactory::new();
+        let mut b = fac.new_empty_buffer();
+        b.replace(0..0, "a\nb\nc\n");
+        let mut fs = FoldState::new(&b);
+        fs.collapse(&fold(0, 1));
+        fs.collapse(&fold(1, 2));
+        b.replace(0..0, "new line\n");
+        fs.sync(&b);
+        assert_eq!(fs.collapsed_lines(), vec![1, 2]);
+    }
+
+    #[test]
+    fn toggle_flips_collapsed_state() {
+        let mut fs = FoldState::default();
+        assert!(fs.toggle(&fold(1, 2)));
+        assert!(!fs.toggle(&fold(1, 2)));
+    }
+
+    #[test]
+    fn hidden_lines_merges_nested_collapsed_folds() {
+        let mut fs = FoldState::default();
+        let folds = [fold(0, 10), fold(2, 4), fold(12, 14)];
+        fs.collapse_all(&folds);
+        assert_eq!(fs.hidden_lines(&folds), vec![1..11, 13..15]);
+    }
+
+    #[test]
+    fn fold_store_save_then_load_round_trips() {
+        let mut store = FoldStore::new();
+        store.set("/tmp/a.rs", vec![1, 5]);
+        assert!(store.is_changed());
+
+        let file = NamedTempFile::new().expect("failed to create temporary file");
+        store.save(file.path()).unwrap();
+        assert!(!store.is_changed());
+
+        let loaded = FoldStore::load(file.path()).unwrap();
+        assert_eq!(loaded.get("/tmp/a.rs"), Some(&vec![1, 5]));
+    }
+
+    #[test]
+    fn fold_store_set_with_no_collapsed_lines_forgets_file() {
+        let mut store = FoldStore::new();
+        store.set("/tmp/a.rs", vec![1]);
+        store.set("/tmp/a.rs", vec![]);
+        assert!(store.get("/tmp/a.rs").is_none());
+    }
+}
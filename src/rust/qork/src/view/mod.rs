@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::mem;
+
+use buffer::{Buffer, BufferCollection, BufferId};
+
+mod fold_state;
+
+pub use view::fold_state::{FoldState, FoldStore};
+
+pub type ViewId = i64;
+
+/// A `BufferView` is a view onto a `Buffer`. It holds the state which belongs to the way a
+/// buffer is being looked at rather than to the buffer itself, such as which folds are
+/// collapsed. A buffer may have several views.
+pub struct BufferView {
+    id: ViewId,
+    buffer_id: BufferId,
+    folds: FoldState
+}
+
+impl BufferView {
+    pub fn id(&self) -> ViewId {
+        self.id
+    }
+
+    pub fn buffer_id(&self) -> BufferId {
+        self.buffer_id
+    }
+
+    /// Returns the fold state, brought up to date with any edits made to `buffer`.
+    pub fn folds(&mut self, buffer: &Buffer) -> &mut FoldState {
+        self.folds.sync(buffer);
+        &mut self.folds
+    }
+}
+
+/// Owns all the views in Qork.
+pub struct ViewCollection {
+    next_view_id: ViewId,
+    views: HashMap<ViewId, BufferView>
+}
+
+impl ViewCollection {
+    pub fn new() -> ViewCollection {
+        ViewCollection {
+            next_view_id: 0,
+            views: HashMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// Creates a new view onto `buffer`.
+    pub fn create(&mut self, buffer: &Buffer) -> ViewId {
+        self.next_view_id += 1;
+        let view = BufferView {
+            id: self.next_view_id,
+            buffer_id: buffer.id(),
+            folds: FoldState::new(buffer)
+        };
+        self.views.insert(view.id, view);
+        self.next_view_id
+    }
+
+    pub fn get_mut(&mut self, view_id: ViewId) -> Option<&mut BufferView> {
+        self.views.get_mut(&view_id)
+    }
+
+    /// Returns the id of the first view onto `buffer_id`, if there is one.
+    pub fn find_by_buffer(&self, buffer_id: BufferId) -> Option<ViewId> {
+        self.views.values().filter(|v| v.buffer_id == buffer_id).map(|v| v.id).min()
+    }
+
+    /// Returns the ids of all views.
+    pub fn ids(&self) -> Vec<ViewId> {
+        self.views.keys().cloned().collect()
+    }
+
+    /// Estimates the memory used by the views onto `buffer_id`.
+    pub fn memory_usage(&self, buffer_id: BufferId) -> usize {
+        self.views.values()
+            .filter(|v| v.buffer_id == buffer_id)
+            .map(|v| mem::size_of::<BufferView>() + v.folds.memory_usage())
+            .sum()
+    }
+
+    /// Brings every view up to date with the edits made to its buffer, so that nothing is
+    /// lost when a buffer forgets its edits.
+    pub fn sync(&mut self, buffers: &BufferCollection) {
+        for view in self.views.values_mut() {
+            if let Some(rc) = buffers.get(view.buffer_id) {
+                view.folds.sync(&rc.borrow());
+            }
+        }
+    }
+
+    /// Removes all views onto `buffer_id`, returning how many there were.
+    pub fn remove_for_buffer(&mut self, buffer_id: BufferId) -> usize {
+        let before = self.views.len();
+        self.views.retain(|_, v| v.buffer_id != buffer_id);
+        before - self.views.len()
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use toml;
+
+use fs;
+
+/// A named project context: a root directory, the files open in it and which of them was
+/// current. Only buffers on files are kept, since other buffers cannot be restored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Workspace {
+    pub root: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub current: Option<PathBuf>
+}
+
+/// All the named workspaces, which are kept in the data directory so that a workspace can
+/// be restored in a later session.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WorkspaceStore {
+    #[serde(skip)]
+    is_changed: bool,
+    workspaces: BTreeMap<String, Workspace>
+}
+
+impl WorkspaceStore {
+    pub fn new() -> WorkspaceStore {
+        WorkspaceStore::default()
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.is_changed
+    }
+
+    pub fn len(&self) -> usize {
+        self.workspaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workspaces.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Workspace> {
+        self.workspaces.get(name)
+    }
+
+    /// The workspaces, in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Workspace)> {
+        self.workspaces.iter()
+    }
+
+    pub fn set(&mut self, name: &str, workspace: Workspace) {
+        if self.workspaces.get(name) == Some(&workspace) {
+            return;
+        }
+        self.workspaces.insert(String::from(name), workspace);
+        self.is_changed = true;
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let removed = self.workspaces.remove(name).is_some();
+        self.is_changed |= removed;
+        removed
+    }
+
+    pub fn load<P: AsRef<Path>>(filename: P) -> Result<WorkspaceStore, String> {
+        let mut contents = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|err| err.to_string())?;
+        toml::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize, String> {
+        if !self.is_changed {
+            return Ok(0);
+        }
+
+        let contents = toml::to_string(self).map_err(|err| err.to_string())?;
+        fs::write_atomically(filename.as_ref(), contents.as_bytes()).map_err(|err| err.to_string())?;
+        self.is_changed = false;
+        Ok(contents.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn workspace(files: &[&str]) -> Workspace {
+        Workspace {
+            root: PathBuf::from("/q"),
+            files: files.iter().map(PathBuf::from).collect(),
+            current: files.first().map(PathBuf::from)
+        }
+    }
+
+    #[test]
+    fn set_only_marks_store_changed_when_workspace_differs() {
+        let mut store = WorkspaceStore::new();
+        store.set("q", workspace(&["/q/a.rs"]));
+        assert!(store.is_changed());
+
+        store.is_changed = false;
+        store.set("q", workspace(&["/q/a.rs"]));
+        assert!(!store.is_changed());
+        assert!(!store.remove("other"));
+        assert!(!store.is_changed());
+    }
+
+    #[test]
+    fn save_and_load_round_trips_workspaces() {
+        let mut store = WorkspaceStore::new();
+        store.set("q", workspace(&["/q/a.rs", "/q/b.rs"]));
+        store.set("empty", workspace(&[]));
+        let file = NamedTempFile::new().unwrap();
+        assert!(store.save(file.path()).unwrap() > 0);
+
+        let loaded = WorkspaceStore::load(file.path()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get("q"), Some(&workspace(&["/q/a.rs", "/q/b.rs"])));
+        assert_eq!(loaded.get("empty"), Some(&workspace(&[])));
+    }
+}